@@ -0,0 +1,178 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.17;
+
+interface IERC20 {
+    function balanceOf(address account) external view returns (uint256);
+    function transfer(address to, uint256 amount) external returns (bool);
+    function approve(address spender, uint256 amount) external returns (bool);
+}
+
+/// @dev Uniswap V3 and Algebra pools share these functions, only the names of their callbacks differ.
+interface IConcentratedLiquidityPool {
+    function token0() external view returns (address);
+    function token1() external view returns (address);
+    function swap(
+        address recipient,
+        bool zeroForOne,
+        int256 amountSpecified,
+        uint160 sqrtPriceLimitX96,
+        bytes calldata data
+    ) external returns (int256 amount0, int256 amount1);
+    function flash(address recipient, uint256 amount0, uint256 amount1, bytes calldata data) external;
+}
+
+/// @title FlashArbExecutor
+/// @notice Swaps borrowed funds through an arbitrage cycle found by the bot, and reverts unless it is profitable.
+/// The start token is borrowed either with a flash swap on the first hop, when its pool pays out before being paid
+/// (Uniswap V3, Algebra), or with `flash()` on a Uniswap V3 or Algebra pool outside of the cycle.
+/// The profit is kept by the contract until the owner withdraws it.
+contract FlashArbExecutor {
+    /// @dev Values of `Swap.payment`, see `SwapPayment` in `src/pool.rs`.
+    uint8 private constant CALLBACK = 0;
+    uint8 private constant TRANSFER = 1;
+    uint8 private constant APPROVAL = 2;
+
+    /// @notice A hop of the cycle.
+    /// @param payment How `target` is paid `amountIn` of `tokenIn`.
+    /// @param target Contract executing the swap, which sends its output to this contract.
+    /// @param data Calldata of the swap, as returned by `Pool.swap_calldata()`.
+    struct Swap {
+        uint8 payment;
+        address target;
+        address tokenIn;
+        uint256 amountIn;
+        bytes data;
+    }
+
+    address public immutable owner;
+
+    /// @dev Only pool allowed to call back this contract, set before each call to a pool.
+    address private pendingPool;
+
+    constructor() {
+        owner = msg.sender;
+    }
+
+    modifier onlyOwner() {
+        require(msg.sender == owner, "FAE: not owner");
+        _;
+    }
+
+    /// @notice Borrows `swaps[0].amountIn` of `swaps[0].tokenIn`, swaps it through `swaps` and repays the loan.
+    /// Each hop pays its precomputed `amountIn`, so a hop delivering less than the next one pays would be covered
+    /// by the profits held in the intermediate token: the cycle reverts unless none of their balances dropped.
+    /// @param loanPool Pool lending the start token with `flash()`, or the zero address to flash swap on the first hop.
+    /// @param minProfit Minimum increase of the balance of the start token, net of the loan fee.
+    /// @return profit Increase of the balance of the start token, read by the bot when simulating the cycle.
+    function execute(address loanPool, uint256 minProfit, Swap[] calldata swaps) external onlyOwner returns (uint256 profit) {
+        require(swaps.length > 0, "FAE: empty cycle");
+        address startToken = swaps[0].tokenIn;
+        uint256 balanceBefore = IERC20(startToken).balanceOf(address(this));
+        uint256[] memory inventory = new uint256[](swaps.length);
+        for (uint256 i = 1; i < swaps.length; i++) {
+            inventory[i] = IERC20(swaps[i].tokenIn).balanceOf(address(this));
+        }
+        bytes memory cycle = abi.encode(swaps);
+
+        if (loanPool == address(0)) {
+            require(swaps[0].payment == CALLBACK, "FAE: no flash source");
+            (address recipient, bool zeroForOne, int256 amountSpecified, uint160 sqrtPriceLimitX96, ) =
+                abi.decode(swaps[0].data[4:], (address, bool, int256, uint160, bytes));
+            pendingPool = swaps[0].target;
+            IConcentratedLiquidityPool(swaps[0].target).swap(recipient, zeroForOne, amountSpecified, sqrtPriceLimitX96, cycle);
+        } else {
+            uint256 amount = swaps[0].amountIn;
+            bool isToken0 = IConcentratedLiquidityPool(loanPool).token0() == startToken;
+            pendingPool = loanPool;
+            IConcentratedLiquidityPool(loanPool).flash(address(this), isToken0 ? amount : 0, isToken0 ? 0 : amount, cycle);
+        }
+        pendingPool = address(0);
+
+        for (uint256 i = 1; i < swaps.length; i++) {
+            require(IERC20(swaps[i].tokenIn).balanceOf(address(this)) >= inventory[i], "FAE: inventory spent");
+        }
+        uint256 balanceAfter = IERC20(startToken).balanceOf(address(this));
+        require(balanceAfter >= balanceBefore + minProfit, "FAE: insufficient profit");
+        profit = balanceAfter - balanceBefore;
+    }
+
+    /// @notice Sends `amount` of `token` held by the contract to the owner.
+    function withdraw(address token, uint256 amount) external onlyOwner {
+        _transfer(token, owner, amount);
+    }
+
+    function uniswapV3SwapCallback(int256 amount0Delta, int256 amount1Delta, bytes calldata data) external {
+        _swapCallback(amount0Delta, amount1Delta, data);
+    }
+
+    function algebraSwapCallback(int256 amount0Delta, int256 amount1Delta, bytes calldata data) external {
+        _swapCallback(amount0Delta, amount1Delta, data);
+    }
+
+    function uniswapV3FlashCallback(uint256 fee0, uint256 fee1, bytes calldata data) external {
+        _flashCallback(fee0, fee1, data);
+    }
+
+    function algebraFlashCallback(uint256 fee0, uint256 fee1, bytes calldata data) external {
+        _flashCallback(fee0, fee1, data);
+    }
+
+    /// @dev Pays the pool of a `CALLBACK` hop. When it is the flash swap on the first hop, `data` holds the cycle,
+    /// whose remaining hops are swapped with its output before paying.
+    function _swapCallback(int256 amount0Delta, int256 amount1Delta, bytes calldata data) private {
+        address pool = msg.sender;
+        require(pool == pendingPool, "FAE: unexpected callback");
+        if (data.length > 0) {
+            _swap(abi.decode(data, (Swap[])), 1);
+        }
+        if (amount0Delta > 0) {
+            _transfer(IConcentratedLiquidityPool(pool).token0(), pool, uint256(amount0Delta));
+        }
+        if (amount1Delta > 0) {
+            _transfer(IConcentratedLiquidityPool(pool).token1(), pool, uint256(amount1Delta));
+        }
+    }
+
+    /// @dev Swaps the loan through the cycle held by `data`, then repays it along with the fee.
+    function _flashCallback(uint256 fee0, uint256 fee1, bytes calldata data) private {
+        address pool = msg.sender;
+        require(pool == pendingPool, "FAE: unexpected callback");
+        Swap[] memory swaps = abi.decode(data, (Swap[]));
+        _swap(swaps, 0);
+        address startToken = swaps[0].tokenIn;
+        uint256 fee = IConcentratedLiquidityPool(pool).token0() == startToken ? fee0 : fee1;
+        _transfer(startToken, pool, swaps[0].amountIn + fee);
+    }
+
+    /// @dev Executes the hops of `swaps` from index `from`, bubbling up the revert reason of a failed swap.
+    function _swap(Swap[] memory swaps, uint256 from) private {
+        for (uint256 i = from; i < swaps.length; i++) {
+            Swap memory hop = swaps[i];
+            if (hop.payment == TRANSFER) {
+                _transfer(hop.tokenIn, hop.target, hop.amountIn);
+            } else if (hop.payment == APPROVAL) {
+                _approve(hop.tokenIn, hop.target, hop.amountIn);
+            } else {
+                pendingPool = hop.target;
+            }
+            (bool success, bytes memory result) = hop.target.call(hop.data);
+            if (!success) {
+                assembly {
+                    revert(add(result, 32), mload(result))
+                }
+            }
+        }
+    }
+
+    /// @dev Transfers tokens, whether or not `token` returns a boolean.
+    function _transfer(address token, address to, uint256 amount) private {
+        (bool success, bytes memory result) = token.call(abi.encodeWithSelector(IERC20.transfer.selector, to, amount));
+        require(success && (result.length == 0 || abi.decode(result, (bool))), "FAE: transfer failed");
+    }
+
+    /// @dev Approves tokens, whether or not `token` returns a boolean.
+    function _approve(address token, address spender, uint256 amount) private {
+        (bool success, bytes memory result) = token.call(abi.encodeWithSelector(IERC20.approve.selector, spender, amount));
+        require(success && (result.length == 0 || abi.decode(result, (bool))), "FAE: approve failed");
+    }
+}
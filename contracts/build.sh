@@ -0,0 +1,7 @@
+#!/bin/sh
+# Compiles the executor into config/executor/ and the mocks of its tests into contracts/test/out/.
+# Run from the root of the repository with solc 0.8.17 or later.
+set -e
+solc --optimize --bin --abi --overwrite contracts/FlashArbExecutor.sol -o config/executor
+mv config/executor/FlashArbExecutor.abi config/executor/FlashArbExecutorABI.json
+solc --optimize --bin --overwrite contracts/test/*.sol -o contracts/test/out
@@ -0,0 +1,38 @@
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.17;
+
+interface IERC20 {
+    function balanceOf(address account) external view returns (uint256);
+    function transfer(address to, uint256 amount) external returns (bool);
+}
+
+interface IUniswapV3FlashCallback {
+    function uniswapV3FlashCallback(uint256 fee0, uint256 fee1, bytes calldata data) external;
+}
+
+/// @title MockFlashLender
+/// @notice The `flash()` of a Uniswap V3 pool: lends its tokens and checks they are repaid with the fee after the callback.
+contract MockFlashLender {
+    address public immutable token0;
+    address public immutable token1;
+    /// @notice Fee of the loans, in hundredths of a bip.
+    uint24 public immutable fee;
+
+    constructor(address _token0, address _token1, uint24 _fee) {
+        token0 = _token0;
+        token1 = _token1;
+        fee = _fee;
+    }
+
+    function flash(address recipient, uint256 amount0, uint256 amount1, bytes calldata data) external {
+        uint256 fee0 = (amount0 * fee + 1e6 - 1) / 1e6;
+        uint256 fee1 = (amount1 * fee + 1e6 - 1) / 1e6;
+        uint256 balance0Before = IERC20(token0).balanceOf(address(this));
+        uint256 balance1Before = IERC20(token1).balanceOf(address(this));
+        if (amount0 > 0) IERC20(token0).transfer(recipient, amount0);
+        if (amount1 > 0) IERC20(token1).transfer(recipient, amount1);
+        IUniswapV3FlashCallback(msg.sender).uniswapV3FlashCallback(fee0, fee1, data);
+        require(IERC20(token0).balanceOf(address(this)) >= balance0Before + fee0, "F0");
+        require(IERC20(token1).balanceOf(address(this)) >= balance1Before + fee1, "F1");
+    }
+}
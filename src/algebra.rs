@@ -0,0 +1,487 @@
+use ethers::{
+    middleware::SignerMiddleware,
+    providers::{Http, Provider, Middleware},
+    signers::LocalWallet,
+    types::{ Address, Bytes, U256, U64, I256, H256, Log, },
+    contract::{ BaseContract, AbiError, },
+    abi::{ Tokenize, Detokenize, },
+};
+use std::{
+    any::Any,
+    fs::File,
+    io::{Write, ErrorKind},
+    convert::TryFrom,
+    sync::Arc,
+};
+
+use crate::utils::*;
+use crate::univ3::{ Token, PoolImmutables, PoolState, PoolSnapshot, PoolEvent, Univ3Fetch, TICK_BITMAP_WORDS,
+    tick_bitmap_word_range, initialized_ticks_in_word, };
+use crate::univ3_math::*;
+use crate::multicall::Multicall;
+use crate::optimizer::Quote;
+use crate::pool::{ Pool, SwapPayment, };
+use crate::profitability::{ ALGEBRA_SWAP_GAS, TICK_CROSS_GAS, };
+use crate::error::{ BotError, BotResult, };
+
+/// Name of the event emitted by an Algebra pool when its dynamic fee changes.
+const FEE_EVENT: &str = "Fee";
+
+/// Fee of `AlgebraPool.flash()`, in hundredths of a bip, the same whatever the dynamic fee of the swaps.
+pub const ALGEBRA_FLASH_FEE: u32 = 100;
+
+//------------------------------------- AlgebraImmutables
+
+/// Reads the Algebra pools from `config/<chain id>/algebraPools.json`, with the fee they had when discovered.
+/// If the file is not found, the pools between `tokens` are discovered on the factory of `algebra_factory_addr`
+/// and saved to it. No pools are returned on networks without an Algebra factory.
+pub async fn get_algebra_pools(
+    chain_id: u32,
+    provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+    tokens: &[Token],
+    chunk_size: usize,
+) -> BotResult<Vec<PoolImmutables>> {
+    let file_storing_pools = format!("config/{}/algebraPools.json", &chain_id);
+
+    let file = match File::open(&file_storing_pools) {
+        Ok(f) => f,
+        Err(e) => match e.kind() {
+            ErrorKind::NotFound => {
+                let factory = match algebra_factory_addr(chain_id)? {
+                    Some(factory) => factory,
+                    None => return Ok(Vec::new()),
+                };
+                println!("{} not found, creating from AlgebraFactory.poolByPair()", &file_storing_pools);
+                let multicall = Multicall::new(chain_id, provider, chunk_size)?;
+                let block = provider
+                    .get_block_number()
+                    .await
+                    .map_err(|e| BotError::Rpc(format!("`eth_blockNumber` asynchronous call failed: {}", e)))?;
+                let mut pools = discover_algebra_pools(factory, tokens, &multicall, block).await?;
+                for (pool_id, pool) in pools.iter_mut().enumerate() {
+                    pool.pool_id = pool_id;
+                }
+                save_algebra_pools(&file_storing_pools, &pools)?;
+                return Ok(pools);
+            },
+            _ => return Err(BotError::Config(format!("Failed to open file {}: {}", &file_storing_pools, e))),
+        },
+    };
+    serde_json::from_reader(file)
+        .map_err(|e| BotError::Config(format!("Failed to extract algebra pools from json: {}", e)))
+}
+
+/// Finds the pools of `factory` between every couple of `tokens`, with a `pool_id` of 0.
+/// Algebra deploys a single pool per couple, whose token 0 is the lowest address.
+pub async fn discover_algebra_pools(
+    factory: Address,
+    tokens: &[Token],
+    multicall: &Multicall<'_>,
+    block: U64,
+) -> BotResult<Vec<PoolImmutables>> {
+    let factory_abi = BaseContract::from(i_algebra_factory_abi()?);
+    let pool_abi = BaseContract::from(i_algebra_pool_abi()?);
+    let encode_error = |name: &'static str| move |e: AbiError| BotError::Abi(format!("`{}()` method not found in ABI: {}", name, e));
+    let decode_error = |name: &'static str| move |e: AbiError| BotError::Decode(format!("Failed to decode `{}()` output: {}", name, e));
+    let reverted = |name: &'static str| BotError::Execution(format!("`{}()` call reverted", name));
+
+    let mut couples: Vec<(&Token, &Token)> = Vec::new();
+    for i in 0..tokens.len() {
+        for j in i + 1..tokens.len() {
+            let (token_0, token_1) = if tokens[i].address < tokens[j].address { (i, j) } else { (j, i) };
+            couples.push((&tokens[token_0], &tokens[token_1]));
+        }
+    }
+    let calls = couples
+        .iter()
+        .map(|(token_0, token_1)| Ok((
+            factory,
+            factory_abi
+                .encode("poolByPair", (token_0.address, token_1.address))
+                .map_err(encode_error("AlgebraFactory.poolByPair"))?,
+        )))
+        .collect::<BotResult<Vec<(Address, Bytes)>>>()?;
+    let mut pools: Vec<PoolImmutables> = Vec::new();
+    for (&(token_0, token_1), data) in couples.iter().zip(multicall.aggregate(&calls, block).await?) {
+        let data = data.ok_or_else(|| reverted("AlgebraFactory.poolByPair"))?;
+        let address: Address = factory_abi
+            .decode_output("poolByPair", data)
+            .map_err(decode_error("AlgebraFactory.poolByPair"))?;
+        if address != Address::zero() {
+            pools.push(PoolImmutables::new(address, 0, token_0.token_id, token_1.token_id, 0., 0., 0.));
+        }
+    }
+
+    // tick spacing and current fee
+    let mut calls: Vec<(Address, Bytes)> = Vec::with_capacity(2 * pools.len());
+    for pool in &pools {
+        calls.push((pool.address, pool_abi.encode("tickSpacing", ()).map_err(encode_error("Pool.tickSpacing"))?));
+        calls.push((pool.address, pool_abi.encode("globalState", ()).map_err(encode_error("Pool.globalState"))?));
+    }
+    let returns = multicall.aggregate(&calls, block).await?;
+    for (pool, data) in pools.iter_mut().zip(returns.chunks(2)) {
+        let tick_spacing: i32 = pool_abi
+            .decode_output("tickSpacing", data[0].as_ref().ok_or_else(|| reverted("Pool.tickSpacing"))?)
+            .map_err(decode_error("Pool.tickSpacing"))?;
+        let (_, _, fee, _, _, _, _): (U256, i32, u16, u16, u8, u8, bool) = pool_abi
+            .decode_output("globalState", data[1].as_ref().ok_or_else(|| reverted("Pool.globalState"))?)
+            .map_err(decode_error("Pool.globalState"))?;
+        pool.tick_spacing = tick_spacing as f32;
+        pool.fee = fee as f32;
+    }
+    Ok(pools)
+}
+
+/// Serializes `pools` to the json file at `path`.
+fn save_algebra_pools(path: &str, pools: &[PoolImmutables]) -> BotResult<()> {
+    let serialized_pools = serde_json::to_string_pretty(pools)
+        .map_err(|e| BotError::Config(format!("Failed to serialize algebra pools: {}", e)))?;
+    let mut pools_file = File::create(path)
+        .map_err(|e| BotError::Config(format!("Failed to create file {}: {}", path, e)))?;
+    pools_file
+        .write_all(serialized_pools.as_bytes())
+        .map_err(|e| BotError::Config(format!("Failed to write algebra pools to file {}: {}", path, e)))
+}
+
+//------------------------------------- AlgebraPool
+
+/// Algebra pool, as deployed by QuickSwap V3: a Uniswap V3 pool whose fee changes with the volatility.
+/// The state is read from `globalState()` and `tickTable()` instead of `slot0()` and `tickBitmap()`,
+/// and the current fee is tracked through the `Fee` event,
+/// emitted by the first swap of a block that changes it, before its `Swap` event.
+#[derive(Debug, Clone)]
+pub struct AlgebraPool {
+    /// Its `fee` is the one the pool had when discovered.
+    pub immutables: PoolImmutables,
+    /// `None` until the pool is first fetched.
+    pub snapshot: Option<PoolSnapshot>,
+    /// Current fee, in hundredths of a bip.
+    pub fee: u32,
+    token_0_decimals: u8,
+    token_1_decimals: u8,
+    /// Number of tick table words fetched on each side of the current tick.
+    words: i16,
+    fetch: Univ3Fetch,
+    /// Signatures of `POOL_EVENTS` and of `FEE_EVENT`, computed once since `apply_log` checks them for every log of the pool.
+    topics: Vec<H256>,
+    fee_topic: H256,
+    pool_abi: Arc<BaseContract>,
+}
+
+impl AlgebraPool {
+    pub fn new(immutables: PoolImmutables, tokens: &[Token], words: i16, pool_abi: Arc<BaseContract>) -> BotResult<Self> {
+        let fee_topic = pool_abi
+            .abi()
+            .event(FEE_EVENT)
+            .map(|event| event.signature())
+            .map_err(|e| BotError::Abi(format!("`AlgebraPool.{}` event not found in ABI: {}", FEE_EVENT, e)))?;
+        Ok(Self {
+            token_0_decimals: tokens[immutables.token_0_id].decimals,
+            token_1_decimals: tokens[immutables.token_1_id].decimals,
+            fee: immutables.fee as u32,
+            immutables,
+            snapshot: None,
+            words,
+            fetch: Univ3Fetch::Slot0,
+            topics: PoolEvent::topics(&pool_abi, "AlgebraPool")?,
+            fee_topic,
+            pool_abi,
+        })
+    }
+
+    /// Returns the pools of `get_algebra_pools`, yet to be fetched.
+    pub async fn load(
+        chain_id: u32,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        tokens: &[Token],
+        chunk_size: usize,
+    ) -> BotResult<Vec<Self>> {
+        let pool_abi = Arc::new(BaseContract::from(i_algebra_pool_abi()?));
+        get_algebra_pools(chain_id, provider, tokens, chunk_size)
+            .await?
+            .into_iter()
+            .map(|immutables| AlgebraPool::new(immutables, tokens, TICK_BITMAP_WORDS, pool_abi.clone()))
+            .collect()
+    }
+
+    fn encode<T: Tokenize>(&self, name: &str, args: T) -> BotResult<(Address, Bytes)> {
+        let data = self.pool_abi
+            .encode(name, args)
+            .map_err(|e| BotError::Abi(format!("`AlgebraPool.{}()` method not found in ABI: {}", name, e)))?;
+        Ok((self.immutables.address, data))
+    }
+
+    fn decode<D: Detokenize>(&self, name: &str, data: Option<&Option<Bytes>>) -> BotResult<D> {
+        let data = data
+            .cloned()
+            .flatten()
+            .ok_or_else(|| BotError::Execution(format!("`AlgebraPool.{}()` call reverted on pool {:?}", name, self.immutables.address)))?;
+        self.pool_abi
+            .decode_output(name, data)
+            .map_err(|e| BotError::Decode(format!("Failed to decode `AlgebraPool.{}()` output: {}", name, e)))
+    }
+
+    /// Sets the current fee, returns whether it changed.
+    fn set_fee(&mut self, fee: u32) -> bool {
+        let changed = self.fee != fee;
+        self.fee = fee;
+        if let Some(snapshot) = self.snapshot.as_mut() {
+            snapshot.fee = fee;
+        }
+        changed
+    }
+}
+
+impl Quote for AlgebraPool {
+    fn get_amount_out(&self, token_in: usize, _token_out: usize, amount_in: U256) -> Result<U256, &'static str> {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.get_amount_out(token_in == 0, amount_in),
+            None => Err("Pool not fetched"),
+        }
+    }
+}
+
+impl Pool for AlgebraPool {
+    fn address(&self) -> Address {
+        self.immutables.address
+    }
+
+    fn tokens(&self) -> Vec<usize> {
+        vec![self.immutables.token_0_id, self.immutables.token_1_id]
+    }
+
+    /// Also stale when the price moved outside of the fetched tick table words.
+    fn is_stale(&self) -> bool {
+        self.fetch != Univ3Fetch::Done || self.snapshot.as_ref().map(PoolSnapshot::is_stale).unwrap_or(true)
+    }
+
+    fn invalidate(&mut self) {
+        self.fetch = Univ3Fetch::Slot0;
+    }
+
+    /// Fetches `globalState()` and `liquidity()`, then the `words` tick table words on each side of the current tick,
+    /// then `ticks()` for every initialized tick found in them.
+    fn refresh_calls(&self) -> BotResult<Vec<(Address, Bytes)>> {
+        match &self.fetch {
+            Univ3Fetch::Done | Univ3Fetch::Slot0 => {
+                if self.immutables.tick_spacing <= 0. {
+                    return Err(BotError::Config(format!("Pool {:?} has no tick spacing, fetch its immutables first", self.immutables.address)));
+                }
+                Ok(vec![self.encode("globalState", ())?, self.encode("liquidity", ())?])
+            },
+            Univ3Fetch::Bitmap { word_min, word_max, .. } => (*word_min..=*word_max)
+                .map(|word| self.encode("tickTable", word))
+                .collect(),
+            Univ3Fetch::Ticks { indices, .. } => indices
+                .iter()
+                .map(|&index| self.encode("ticks", index))
+                .collect(),
+        }
+    }
+
+    fn apply_refresh(&mut self, returns: &[Option<Bytes>]) -> BotResult<()> {
+        let tick_spacing = self.immutables.tick_spacing as i32;
+        self.fetch = match std::mem::replace(&mut self.fetch, Univ3Fetch::Slot0) {
+            Univ3Fetch::Done | Univ3Fetch::Slot0 => {
+                let (price, tick, fee, timepoint_index, community_fee_0, _, unlocked): (U256, i32, u16, u16, u8, u8, bool) =
+                    self.decode("globalState", returns.first())?;
+                let liquidity: u128 = self.decode("liquidity", returns.get(1))?;
+                // timepoints take the place of the observations, community fees the one of the protocol fee
+                let state = PoolState::new(
+                    (price, tick, timepoint_index, 0, 0, community_fee_0, unlocked),
+                    liquidity,
+                    self.token_0_decimals,
+                    self.token_1_decimals,
+                );
+                self.fee = fee as u32;
+                let (word_min, word_max) = tick_bitmap_word_range(state.tick, tick_spacing, self.words);
+                Univ3Fetch::Bitmap { state, word_min, word_max }
+            },
+            Univ3Fetch::Bitmap { state, word_min, word_max } => {
+                let mut indices: Vec<i32> = Vec::new();
+                for (i, word) in (word_min..=word_max).enumerate() {
+                    let bitmap: U256 = self.decode("tickTable", returns.get(i))?;
+                    indices.extend(initialized_ticks_in_word(word, bitmap, tick_spacing));
+                }
+                Univ3Fetch::Ticks { state, word_min, word_max, indices }
+            },
+            Univ3Fetch::Ticks { state, word_min, word_max, indices } => {
+                let mut ticks: Vec<TickInfo> = Vec::with_capacity(indices.len());
+                for (i, index) in indices.into_iter().enumerate() {
+                    let (liquidity_total, liquidity_delta, _, _, _, _, _, _):
+                        (u128, i128, U256, U256, i64, U256, u32, bool) = self.decode("ticks", returns.get(i))?;
+                    ticks.push(TickInfo { index, liquidity_gross: liquidity_total, liquidity_net: liquidity_delta });
+                }
+                self.snapshot = Some(PoolSnapshot {
+                    state,
+                    fee: self.fee,
+                    tick_spacing,
+                    ticks,
+                    min_tick: ((word_min as i32) << 8) * tick_spacing,
+                    max_tick: (((word_max as i32) << 8) + 255) * tick_spacing,
+                });
+                Univ3Fetch::Done
+            },
+        };
+        Ok(())
+    }
+
+    fn event_topics(&self) -> BotResult<Vec<H256>> {
+        let mut topics = self.topics.clone();
+        topics.push(self.fee_topic);
+        Ok(topics)
+    }
+
+    fn apply_log(&mut self, log: &Log) -> BotResult<bool> {
+        if log.topics.first() == Some(&self.fee_topic) {
+            let fee: u16 = self.pool_abi
+                .decode_event(FEE_EVENT, log.topics.clone(), log.data.clone())
+                .map_err(|e| BotError::Decode(format!("Failed to decode `AlgebraPool.{}` event: {}", FEE_EVENT, e)))?;
+            return Ok(self.set_fee(fee as u32));
+        }
+        match (PoolEvent::decode(&self.pool_abi, &self.topics, log)?, self.snapshot.as_mut()) {
+            (Some(event), Some(snapshot)) => snapshot.apply(&event),
+            _ => Ok(false),
+        }
+    }
+
+    fn log_price(&self, token_in: usize, _token_out: usize) -> Option<f64> {
+        let snapshot = self.snapshot.as_ref()?;
+        // uninitialized pool
+        if snapshot.state.sqrt_price_x96.is_zero() || snapshot.state.liquidity == 0 {
+            return None;
+        }
+        let (p0, p1) = sqrtPriceX86_to_log_price(snapshot.state.sqrt_price_x96, self.token_0_decimals, self.token_1_decimals);
+        let fee_weight = fee_to_log_weight(snapshot.fee as f32);
+        Some(if token_in == 0 { p0 } else { p1 } + fee_weight)
+    }
+
+    /// `AlgebraPool.swap()` of exactly `amount_in`, down to the price limit of the pool.
+    /// The caller must pay `amount_in` in `algebraSwapCallback`.
+    fn swap_calldata(&self, token_in: usize, _token_out: usize, amount_in: U256, _amount_out: U256, recipient: Address) -> BotResult<Bytes> {
+        let zero_to_one = token_in == 0;
+        let amount_required = I256::try_from(amount_in)
+            .map_err(|_| BotError::Math(format!("Swap amount {} overflows int256", amount_in)))?;
+        let limit_sqrt_price = if zero_to_one { min_sqrt_ratio() + 1 } else { max_sqrt_ratio() - 1 };
+        let (_, data) = self.encode("swap", (recipient, zero_to_one, amount_required, limit_sqrt_price, Bytes::default()))?;
+        Ok(data)
+    }
+
+    fn swap_payment(&self) -> SwapPayment {
+        SwapPayment::Callback
+    }
+
+    fn swap_gas(&self, token_in: usize, _token_out: usize, amount_in: U256) -> u64 {
+        let ticks_crossed = self.snapshot
+            .as_ref()
+            .and_then(|snapshot| snapshot.ticks_crossed(token_in == 0, amount_in).ok())
+            .unwrap_or_default();
+        ALGEBRA_SWAP_GAS + TICK_CROSS_GAS * ticks_crossed as u64
+    }
+
+    fn flash_fee(&self, amount: U256) -> Option<U256> {
+        mul_div_rounding_up(amount, U256::from(ALGEBRA_FLASH_FEE), U256::from(1_000_000))
+    }
+
+    fn box_clone(&self) -> Box<dyn Pool> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::Token;
+    use crate::testing::tokens;
+
+    fn pool() -> AlgebraPool {
+        let immutables = PoolImmutables::new(Address::repeat_byte(1), 0, 0, 1, 500., 60., 0.);
+        let pool_abi = Arc::new(BaseContract::from(i_algebra_pool_abi().unwrap()));
+        AlgebraPool::new(immutables, &tokens(&[18; 2]), 1, pool_abi).unwrap()
+    }
+
+    #[test]
+    fn test_algebra_pool_refresh() {
+        let mut pool = pool();
+        let liquidity = 10u128.pow(18);
+        assert!(pool.is_stale());
+
+        // globalState and liquidity, with a fee that changed since discovery
+        assert_eq!(pool.refresh_calls().unwrap().len(), 2);
+        pool.apply_refresh(&[
+            Some(Bytes::from(ethers::abi::encode(&[
+                Token::Uint(get_sqrt_ratio_at_tick(0).unwrap()),
+                Token::Int(U256::zero()),
+                Token::Uint(U256::from(800)),
+                Token::Uint(U256::from(7)),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Bool(true),
+            ]))),
+            Some(Bytes::from(ethers::abi::encode(&[Token::Uint(U256::from(liquidity))]))),
+        ]).unwrap();
+        assert_eq!(pool.fee, 800);
+
+        // one word on each side of the current tick, ticks -600 and 600 in words -1 and 0
+        assert_eq!(pool.refresh_calls().unwrap().len(), 3);
+        let word = |bitmap: U256| Some(Bytes::from(ethers::abi::encode(&[Token::Uint(bitmap)])));
+        pool.apply_refresh(&[word(U256::one() << 246), word(U256::one() << 10), word(U256::zero())]).unwrap();
+
+        let tick = |liquidity_delta: i128| Some(Bytes::from(ethers::abi::encode(&[
+            Token::Uint(U256::from(liquidity)),
+            Token::Int(I256::from(liquidity_delta).into_raw()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Int(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Bool(true),
+        ])));
+        assert_eq!(pool.refresh_calls().unwrap().len(), 2);
+        pool.apply_refresh(&[tick(liquidity as i128), tick(-(liquidity as i128))]).unwrap();
+        assert!(!pool.is_stale());
+
+        let snapshot = pool.snapshot.clone().unwrap();
+        assert_eq!(snapshot.fee, 800);
+        assert_eq!(snapshot.state.observation_index, 7);
+        assert_eq!(snapshot.ticks.iter().map(|t| (t.index, t.liquidity_net)).collect::<Vec<(i32, i128)>>(),
+            vec![(-600, liquidity as i128), (600, -(liquidity as i128))]);
+        assert_eq!((snapshot.min_tick, snapshot.max_tick), (-15360, 30660));
+    }
+
+    #[test]
+    fn test_algebra_fee_event() {
+        let mut pool = pool();
+        pool.snapshot = Some(PoolSnapshot {
+            state: PoolState::new((get_sqrt_ratio_at_tick(0).unwrap(), 0, 0, 0, 0, 0, true), 10u128.pow(18), 18, 18),
+            fee: 500,
+            tick_spacing: 60,
+            ticks: Vec::new(),
+            min_tick: -15360,
+            max_tick: 30660,
+        });
+        pool.fetch = Univ3Fetch::Done;
+        assert_eq!(pool.event_topics().unwrap().len(), 5);
+        let amount_out = pool.get_amount_out(0, 1, U256::exp10(16)).unwrap();
+        let log_price = pool.log_price(0, 1).unwrap();
+
+        let fee_log = Log {
+            address: pool.address(),
+            topics: vec![pool.pool_abi.abi().event(FEE_EVENT).unwrap().signature()],
+            data: Bytes::from(ethers::abi::encode(&[Token::Uint(U256::from(3000))])),
+            ..Default::default()
+        };
+        assert!(pool.apply_log(&fee_log).unwrap());
+        assert!(!pool.apply_log(&fee_log).unwrap());
+        assert_eq!(pool.fee, 3000);
+        assert!(pool.get_amount_out(0, 1, U256::exp10(16)).unwrap() < amount_out);
+        assert!(pool.log_price(0, 1).unwrap() > log_price);
+        assert!(!pool.is_stale());
+    }
+}
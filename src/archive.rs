@@ -0,0 +1,588 @@
+use ethers::{
+    middleware::SignerMiddleware,
+    providers::{Http, Provider, Middleware},
+    signers::LocalWallet,
+    types::{ Address, Bytes, U256, U64, H256, Log, },
+};
+use std::{
+    fs::{ File, OpenOptions, },
+    io::{ Read, Write, },
+    path::Path,
+};
+
+use crate::pool::{ Pool, PoolTracker, BlockHeader, };
+use crate::multicall::Multicall;
+use crate::error::{ BotError, BotResult, };
+
+/// Maximum number of blocks whose logs are fetched with a single `eth_getLogs` while archiving.
+pub const ARCHIVE_LOG_BLOCKS: u64 = 1_000;
+
+/// First bytes of an archive file, followed by the format version and the chain id.
+const MAGIC: &[u8; 4] = b"ARBL";
+const VERSION: u8 = 1;
+/// Kinds of the records following the header, each made of its kind, the length of its payload and its payload.
+const POOLS_RECORD: u8 = 0;
+const BLOCK_RECORD: u8 = 1;
+
+/// Fragments of the node errors caused by an `eth_getLogs` range returning too many logs, after which the range is halved.
+const RANGE_ERRORS: [&str; 5] = ["too many results", "query returned more than", "response size exceeded", "block range", "limit exceeded"];
+
+/// Whether `error` is a node error caused by an `eth_getLogs` range returning too many logs.
+pub fn is_range_error(error: &str) -> bool {
+    let error = error.to_lowercase();
+    RANGE_ERRORS.iter().any(|fragment| error.contains(fragment))
+}
+
+//------------------------------------- ArchivedBlock
+
+/// Return data of the refresh of a pool, one list of call returns per round, as given to `Pool::apply_refresh`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolRefresh {
+    pub pool_id: usize,
+    pub rounds: Vec<Vec<Option<Bytes>>>,
+}
+
+/// A block of the archive, with the logs of the pools it emitted and the pools refetched at its end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivedBlock {
+    pub number: U64,
+    pub hash: H256,
+    pub parent_hash: H256,
+    pub base_fee: U256,
+    pub logs: Vec<Log>,
+    /// Pools whose logs could not be applied, e.g. swaps leaving the fetched ticks, refetched after the logs.
+    pub refreshes: Vec<PoolRefresh>,
+}
+
+impl ArchivedBlock {
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader { number: self.number, hash: self.hash, parent_hash: self.parent_hash }
+    }
+
+    /// Whether its logs were all emitted by this block, and not by a block of the same number reorganised away.
+    pub fn logs_match(&self) -> bool {
+        self.logs.iter().all(|log| log.block_hash == Some(self.hash))
+    }
+
+    /// Brings the pools of `tracker` to the end of the block: applies its logs, then its refreshes.
+    /// A log failing to apply is not an error, the pools it left stale were refetched when archiving.
+    pub fn apply(&self, tracker: &mut PoolTracker) -> BotResult<()> {
+        if let Err(e) = tracker.apply_block(&self.header(), &self.logs) {
+            println!("failed to apply block {}: {}, replaying its refreshes", self.number, e);
+        }
+        for refresh in &self.refreshes {
+            let mut pool = tracker.pools
+                .get(refresh.pool_id)
+                .ok_or_else(|| BotError::Config(format!("Archive refreshes unknown pool {}", refresh.pool_id)))?
+                .clone();
+            pool.invalidate();
+            for round in &refresh.rounds {
+                pool.apply_refresh(round)?;
+            }
+            if pool.is_stale() {
+                return Err(BotError::Config(format!("Archive misses refresh rounds of pool {:?}", pool.address())));
+            }
+            tracker.replace(refresh.pool_id, pool);
+        }
+        Ok(())
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.number.as_u64().to_le_bytes());
+        out.extend_from_slice(self.hash.as_bytes());
+        out.extend_from_slice(self.parent_hash.as_bytes());
+        let mut base_fee = [0u8; 32];
+        self.base_fee.to_big_endian(&mut base_fee);
+        out.extend_from_slice(&base_fee);
+
+        out.extend_from_slice(&(self.logs.len() as u32).to_le_bytes());
+        for log in &self.logs {
+            out.extend_from_slice(log.address.as_bytes());
+            out.push(log.topics.len() as u8);
+            for topic in &log.topics {
+                out.extend_from_slice(topic.as_bytes());
+            }
+            encode_bytes(&log.data, out);
+            out.extend_from_slice(log.transaction_hash.unwrap_or_default().as_bytes());
+            out.extend_from_slice(&log.transaction_index.unwrap_or_default().as_u64().to_le_bytes());
+            out.extend_from_slice(&log.log_index.unwrap_or_default().as_u64().to_le_bytes());
+        }
+
+        out.extend_from_slice(&(self.refreshes.len() as u32).to_le_bytes());
+        for refresh in &self.refreshes {
+            out.extend_from_slice(&(refresh.pool_id as u32).to_le_bytes());
+            out.extend_from_slice(&(refresh.rounds.len() as u32).to_le_bytes());
+            for round in &refresh.rounds {
+                out.extend_from_slice(&(round.len() as u32).to_le_bytes());
+                for data in round {
+                    match data {
+                        Some(data) => {
+                            out.push(1);
+                            encode_bytes(data, out);
+                        },
+                        None => out.push(0),
+                    }
+                }
+            }
+        }
+    }
+
+    fn decode(decoder: &mut Decoder) -> BotResult<Self> {
+        let number = U64::from(decoder.u64()?);
+        let hash = decoder.h256()?;
+        let parent_hash = decoder.h256()?;
+        let base_fee = U256::from_big_endian(decoder.take(32)?);
+
+        let log_count = decoder.u32()?;
+        let mut logs: Vec<Log> = Vec::new();
+        for _ in 0..log_count {
+            let address = Address::from_slice(decoder.take(20)?);
+            let topic_count = decoder.u8()?;
+            let topics = (0..topic_count).map(|_| decoder.h256()).collect::<BotResult<Vec<H256>>>()?;
+            let data = decoder.bytes()?;
+            logs.push(Log {
+                address,
+                topics,
+                data,
+                block_hash: Some(hash),
+                block_number: Some(number),
+                transaction_hash: Some(decoder.h256()?),
+                transaction_index: Some(U64::from(decoder.u64()?)),
+                log_index: Some(U256::from(decoder.u64()?)),
+                ..Default::default()
+            });
+        }
+
+        let refresh_count = decoder.u32()?;
+        let mut refreshes: Vec<PoolRefresh> = Vec::new();
+        for _ in 0..refresh_count {
+            let pool_id = decoder.u32()? as usize;
+            let round_count = decoder.u32()?;
+            let mut rounds: Vec<Vec<Option<Bytes>>> = Vec::new();
+            for _ in 0..round_count {
+                let call_count = decoder.u32()?;
+                let mut round: Vec<Option<Bytes>> = Vec::new();
+                for _ in 0..call_count {
+                    round.push(match decoder.u8()? {
+                        0 => None,
+                        _ => Some(decoder.bytes()?),
+                    });
+                }
+                rounds.push(round);
+            }
+            refreshes.push(PoolRefresh { pool_id, rounds });
+        }
+        Ok(Self { number, hash, parent_hash, base_fee, logs, refreshes })
+    }
+}
+
+//------------------------------------- Archive
+
+/// Pool events of a range of blocks, from which the states of the pools are rebuilt offline.
+/// The first block holds the refresh of every pool at the start of the range, the next ones hold only the blocks emitting logs.
+///
+/// On disk, an archive is a header followed by length-prefixed records, the pools then one record per block,
+/// appended as they are fetched so that an interrupted download resumes from the last complete record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Archive {
+    pub chain_id: u32,
+    /// Address of each pool, by pool id.
+    pub pools: Vec<Address>,
+    pub blocks: Vec<ArchivedBlock>,
+}
+
+impl Archive {
+    /// Last archived block, `None` if the archive has no block.
+    pub fn last_block(&self) -> Option<U64> {
+        self.blocks.last().map(|block| block.number)
+    }
+
+    /// Reads the archive saved at `path`, ignoring an incomplete last record.
+    pub fn load(path: &str) -> BotResult<Self> {
+        Ok(Self::read(path)?.0)
+    }
+
+    /// Reads the archive saved at `path`, returns it along with the length of its complete records.
+    fn read(path: &str) -> BotResult<(Self, usize)> {
+        let mut data: Vec<u8> = Vec::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut data))
+            .map_err(|e| BotError::Config(format!("Failed to read file {}: {}", path, e)))?;
+        Self::decode(&data).map_err(|e| BotError::Config(format!("Invalid archive {}: {}", path, e)))
+    }
+
+    fn decode(data: &[u8]) -> BotResult<(Self, usize)> {
+        let mut decoder = Decoder { data, pos: 0 };
+        if decoder.take(4)? != MAGIC {
+            return Err(BotError::Decode("Not an archive".to_string()));
+        }
+        let version = decoder.u8()?;
+        if version != VERSION {
+            return Err(BotError::Decode(format!("Unsupported archive version {}", version)));
+        }
+        let mut archive = Self { chain_id: decoder.u32()?, pools: Vec::new(), blocks: Vec::new() };
+
+        let mut complete = decoder.pos;
+        while decoder.pos < data.len() {
+            // a record cut by an interrupted write ends the archive
+            let (kind, mut record) = match decoder.record() {
+                Ok(record) => record,
+                Err(_) => break,
+            };
+            match kind {
+                POOLS_RECORD => {
+                    while record.pos < record.data.len() {
+                        archive.pools.push(Address::from_slice(record.take(20)?));
+                    }
+                },
+                BLOCK_RECORD => archive.blocks.push(ArchivedBlock::decode(&mut record)?),
+                kind => return Err(BotError::Decode(format!("Unknown record kind {}", kind))),
+            }
+            complete = decoder.pos;
+        }
+        Ok((archive, complete))
+    }
+}
+
+/// Appends records to an archive file.
+pub struct ArchiveWriter {
+    file: File,
+}
+
+impl ArchiveWriter {
+    /// Creates the archive file at `path` for `pools` on network with chain id `chain_id`.
+    pub fn create(path: &str, chain_id: u32, pools: &[Address]) -> BotResult<Self> {
+        let file = File::create(path)
+            .map_err(|e| BotError::Config(format!("Failed to create file {}: {}", path, e)))?;
+        let mut writer = Self { file };
+        let mut header = MAGIC.to_vec();
+        header.push(VERSION);
+        header.extend_from_slice(&chain_id.to_le_bytes());
+        writer.write(&header)?;
+        let payload: Vec<u8> = pools.iter().flat_map(|pool| pool.as_bytes().to_vec()).collect();
+        writer.write_record(POOLS_RECORD, &payload)?;
+        Ok(writer)
+    }
+
+    /// Opens the archive file at `path` to append blocks, dropping an incomplete last record, and returns its content.
+    pub fn open(path: &str) -> BotResult<(Self, Archive)> {
+        let (archive, complete) = Archive::read(path)?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(|e| BotError::Config(format!("Failed to open file {}: {}", path, e)))?;
+        file.set_len(complete as u64)
+            .map_err(|e| BotError::Config(format!("Failed to truncate file {}: {}", path, e)))?;
+        Ok((Self { file }, archive))
+    }
+
+    /// Appends `block` to the archive.
+    pub fn append(&mut self, block: &ArchivedBlock) -> BotResult<()> {
+        let mut payload: Vec<u8> = Vec::new();
+        block.encode(&mut payload);
+        self.write_record(BLOCK_RECORD, &payload)
+    }
+
+    fn write_record(&mut self, kind: u8, payload: &[u8]) -> BotResult<()> {
+        let mut record = Vec::with_capacity(payload.len() + 5);
+        record.push(kind);
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(payload);
+        self.write(&record)
+    }
+
+    fn write(&mut self, data: &[u8]) -> BotResult<()> {
+        self.file
+            .write_all(data)
+            .and_then(|_| self.file.flush())
+            .map_err(|e| BotError::Config(format!("Failed to write archive: {}", e)))
+    }
+}
+
+fn encode_bytes(data: &Bytes, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+/// Reads the values of an archive in order.
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> BotResult<&'a [u8]> {
+        let end = self.pos
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| BotError::Decode(format!("Archive truncated at byte {}", self.pos)))?;
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> BotResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> BotResult<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().expect("4 bytes")))
+    }
+
+    fn u64(&mut self) -> BotResult<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().expect("8 bytes")))
+    }
+
+    fn h256(&mut self) -> BotResult<H256> {
+        Ok(H256::from_slice(self.take(32)?))
+    }
+
+    fn bytes(&mut self) -> BotResult<Bytes> {
+        let len = self.u32()? as usize;
+        Ok(Bytes::from(self.take(len)?.to_vec()))
+    }
+
+    /// Returns the kind of the next record and a decoder of its payload.
+    fn record(&mut self) -> BotResult<(u8, Decoder<'a>)> {
+        let kind = self.u8()?;
+        let len = self.u32()? as usize;
+        Ok((kind, Decoder { data: self.take(len)?, pos: 0 }))
+    }
+}
+
+//------------------------------------- fetch-logs
+
+/// Archives the states of `pools` at block `from_block` and their logs up to block `to_block` included in the file at `path`.
+/// If the file exists, the download resumes from its last block, which must have been archived from the same pools.
+/// Logs are fetched in ranges of at most `ARCHIVE_LOG_BLOCKS` blocks, halved whenever the node returns too many logs.
+pub async fn fetch_logs(
+    chain_id: u32,
+    provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+    pools: Vec<Box<dyn Pool>>,
+    chunk_size: usize,
+    from_block: U64,
+    to_block: U64,
+    path: &str,
+) -> BotResult<Archive> {
+    let multicall = Multicall::new(chain_id, provider, chunk_size)?;
+    let mut tracker = PoolTracker::new();
+    for pool in pools {
+        tracker.insert(pool);
+    }
+    let addresses: Vec<Address> = tracker.pools.iter().map(|pool| pool.address()).collect();
+
+    let (mut writer, mut archive) = if Path::new(path).exists() {
+        let (writer, archive) = ArchiveWriter::open(path)?;
+        if archive.chain_id != chain_id || archive.pools != addresses {
+            return Err(BotError::Config(format!("Archive {} was recorded from other pools or on another chain", path)));
+        }
+        // rebuild the pools at the last archived block
+        for block in &archive.blocks {
+            block.apply(&mut tracker)?;
+        }
+        (writer, archive)
+    } else {
+        (ArchiveWriter::create(path, chain_id, &addresses)?, Archive { chain_id, pools: addresses, blocks: Vec::new() })
+    };
+
+    let mut from = match archive.last_block() {
+        Some(last) => {
+            println!("resuming archive {} after block {}", path, last);
+            last + 1
+        },
+        None => {
+            // every pool is fetched at the start of the range
+            let ids: Vec<usize> = (0..tracker.pools.len()).collect();
+            let refreshes = fetch_refreshes(&mut tracker, &ids, &multicall, from_block).await?;
+            let block = fetch_block(provider, from_block, Vec::new(), refreshes).await?;
+            writer.append(&block)?;
+            archive.blocks.push(block);
+            println!("archived pools at block {}", from_block);
+            from_block + 1
+        },
+    };
+
+    let mut range = ARCHIVE_LOG_BLOCKS;
+    while from <= to_block {
+        let to = (from + range - 1).min(to_block);
+        let logs = match tracker.fetch_logs(provider, from, to).await {
+            Ok(logs) => logs,
+            Err(e) if range > 1 && is_range_error(&e.to_string()) => {
+                range /= 2;
+                println!("too many logs in blocks {} to {}, fetching {} blocks at once", from, to, range);
+                continue;
+            },
+            Err(e) => return Err(e),
+        };
+        let mut numbers: Vec<U64> = logs.iter().filter_map(|log| log.block_number).collect();
+        numbers.dedup();
+        // every block is fetched before archiving any, so that the whole range is refetched if the chain changed
+        let mut blocks: Vec<ArchivedBlock> = Vec::with_capacity(numbers.len());
+        for number in numbers {
+            let block_logs: Vec<Log> = logs.iter().filter(|log| log.block_number == Some(number)).cloned().collect();
+            blocks.push(fetch_block(provider, number, block_logs, Vec::new()).await?);
+        }
+        if !blocks.iter().all(ArchivedBlock::logs_match) {
+            println!("logs of blocks {} to {} do not match the fetched blocks, refetching them", from, to);
+            continue;
+        }
+        for mut block in blocks {
+            let number = block.number;
+            // the same pools are refetched when replaying the archive
+            let ids: Vec<usize> = match tracker.apply_block(&block.header(), &block.logs) {
+                Ok(()) => (0..tracker.pools.len()).filter(|&id| tracker.pools[id].is_stale()).collect(),
+                Err(_) => (0..tracker.pools.len()).collect(),
+            };
+            if !ids.is_empty() {
+                block.refreshes = fetch_refreshes(&mut tracker, &ids, &multicall, number).await?;
+            }
+            writer.append(&block)?;
+            archive.blocks.push(block);
+        }
+        println!("archived blocks {} to {}", from, to);
+        from = to + 1;
+        range = (range * 2).min(ARCHIVE_LOG_BLOCKS);
+    }
+    Ok(archive)
+}
+
+/// Returns block `number` with `logs` and `refreshes`.
+async fn fetch_block(
+    provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+    number: U64,
+    logs: Vec<Log>,
+    refreshes: Vec<PoolRefresh>,
+) -> BotResult<ArchivedBlock> {
+    let block = provider
+        .get_block(number)
+        .await
+        .map_err(|e| BotError::Rpc(format!("`eth_getBlockByNumber` asynchronous call failed: {}", e)))?
+        .ok_or_else(|| BotError::Rpc(format!("Block {} not found", number)))?;
+    Ok(ArchivedBlock {
+        number,
+        hash: block.hash.ok_or_else(|| BotError::Rpc(format!("Block {} is pending", number)))?,
+        parent_hash: block.parent_hash,
+        base_fee: block.base_fee_per_gas.unwrap_or_default(),
+        logs,
+        refreshes,
+    })
+}
+
+/// Refetches the pools `ids` of `tracker` at block number `block`, batching each round of every pool through `multicall`,
+/// and returns the return data of their refresh.
+async fn fetch_refreshes(
+    tracker: &mut PoolTracker,
+    ids: &[usize],
+    multicall: &Multicall<'_>,
+    block: U64,
+) -> BotResult<Vec<PoolRefresh>> {
+    let mut pools: Vec<Box<dyn Pool>> = ids
+        .iter()
+        .map(|&id| {
+            let mut pool = tracker.pools[id].clone();
+            pool.invalidate();
+            pool
+        })
+        .collect();
+    let mut refreshes: Vec<PoolRefresh> = ids.iter().map(|&pool_id| PoolRefresh { pool_id, rounds: Vec::new() }).collect();
+    while pools.iter().any(|pool| pool.is_stale()) {
+        let mut calls: Vec<(Address, Bytes)> = Vec::new();
+        let mut counts: Vec<usize> = Vec::with_capacity(pools.len());
+        for pool in &pools {
+            let pool_calls = if pool.is_stale() { pool.refresh_calls()? } else { Vec::new() };
+            counts.push(pool_calls.len());
+            calls.extend(pool_calls);
+        }
+        let returns = multicall.aggregate(&calls, block).await?;
+        let mut start = 0;
+        for ((pool, refresh), count) in pools.iter_mut().zip(refreshes.iter_mut()).zip(counts) {
+            if pool.is_stale() {
+                let round = returns[start..start + count].to_vec();
+                pool.apply_refresh(&round)?;
+                refresh.rounds.push(round);
+            }
+            start += count;
+        }
+    }
+    for (&id, pool) in ids.iter().zip(pools) {
+        tracker.replace(id, pool);
+    }
+    Ok(refreshes)
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(number: u64) -> ArchivedBlock {
+        ArchivedBlock {
+            number: U64::from(number),
+            hash: H256::from_low_u64_be(number),
+            parent_hash: H256::from_low_u64_be(number - 1),
+            base_fee: U256::exp10(10),
+            logs: vec![Log {
+                address: Address::repeat_byte(1),
+                topics: vec![H256::repeat_byte(2), H256::repeat_byte(3)],
+                data: Bytes::from(vec![4; 64]),
+                block_hash: Some(H256::from_low_u64_be(number)),
+                block_number: Some(U64::from(number)),
+                transaction_hash: Some(H256::repeat_byte(5)),
+                transaction_index: Some(U64::from(6)),
+                log_index: Some(U256::from(7)),
+                ..Default::default()
+            }],
+            refreshes: vec![PoolRefresh { pool_id: 1, rounds: vec![vec![Some(Bytes::from(vec![8; 96])), None], vec![]] }],
+        }
+    }
+
+    #[test]
+    fn test_archive_file() {
+        let path = std::env::temp_dir().join(format!("archive_{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        let pools = [Address::repeat_byte(1), Address::repeat_byte(9)];
+
+        let mut writer = ArchiveWriter::create(path, 137, &pools).unwrap();
+        writer.append(&block(10)).unwrap();
+        writer.append(&block(12)).unwrap();
+        drop(writer);
+        let archive = Archive::load(path).unwrap();
+        assert_eq!(archive, Archive { chain_id: 137, pools: pools.to_vec(), blocks: vec![block(10), block(12)] });
+        assert_eq!(archive.last_block(), Some(U64::from(12)));
+
+        // a record cut by an interrupted write is dropped when resuming
+        let len = std::fs::metadata(path).unwrap().len();
+        OpenOptions::new().append(true).open(path).unwrap().write_all(&[BLOCK_RECORD, 200, 0, 0, 0, 1, 2]).unwrap();
+        assert_eq!(Archive::load(path).unwrap().blocks.len(), 2);
+        let (mut writer, archive) = ArchiveWriter::open(path).unwrap();
+        assert_eq!(std::fs::metadata(path).unwrap().len(), len);
+        assert_eq!(archive.last_block(), Some(U64::from(12)));
+        writer.append(&block(13)).unwrap();
+        assert_eq!(Archive::load(path).unwrap().blocks, vec![block(10), block(12), block(13)]);
+
+        std::fs::write(path, b"not an archive").unwrap();
+        assert!(Archive::load(path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_logs_match() {
+        assert!(block(10).logs_match());
+        // a log of another block 10, reorganised away
+        let mut reorganised = block(10);
+        reorganised.logs.push(Log { block_hash: Some(H256::repeat_byte(0xaa)), ..block(10).logs[0].clone() });
+        assert!(!reorganised.logs_match());
+        reorganised.logs[1].block_hash = None;
+        assert!(!reorganised.logs_match());
+    }
+
+    #[test]
+    fn test_is_range_error() {
+        assert!(is_range_error("(code: -32005, message: query returned more than 10000 results, data: None)"));
+        assert!(is_range_error("Log response size exceeded. You can make eth_getLogs requests with up to a 2K block range"));
+        assert!(is_range_error("too many results"));
+        assert!(!is_range_error("nonce too low"));
+        assert!(!is_range_error("error sending request for url"));
+    }
+}
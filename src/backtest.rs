@@ -0,0 +1,277 @@
+use ethers::{
+    contract::BaseContract,
+    types::{ U256, I256, U64, },
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+};
+
+use crate::univ3::{ Token, PoolImmutables, Univ3Pool, TICK_BITMAP_WORDS, };
+use crate::pool::{ Pool, PoolTracker, };
+use crate::archive::Archive;
+use crate::pipeline::{ build_graph, find_cycles, opportunity, size_cycle, };
+use crate::profitability::GasPricer;
+use crate::utils::i_univ3_pool_abi;
+use crate::error::{ BotError, BotResult, };
+
+/// Returns the Uniswap V3 pools of `config/{chain_id}/pools.json`, yet to be fetched, without calling the node.
+pub fn backtest_pools(chain_id: u32, tokens: &[Token]) -> BotResult<Vec<Box<dyn Pool>>> {
+    let pool_abi = Arc::new(BaseContract::from(i_univ3_pool_abi()?));
+    PoolImmutables::read_pool_immutables(chain_id)?
+        .into_iter()
+        .map(|immutables| Ok(Box::new(Univ3Pool::new(immutables, tokens, TICK_BITMAP_WORDS, pool_abi.clone())?) as Box<dyn Pool>))
+        .collect()
+}
+
+//------------------------------------- Backtest
+
+/// An arbitrage opportunity found while replaying an archive.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BacktestOpportunity {
+    /// `(from, pool_id)` of every hop of the cycle.
+    pub opportunity: Vec<(usize, usize)>,
+    /// Id of the start token, in which amounts and profits are expressed.
+    pub start: usize,
+    /// Block where the cycle was first found profitable.
+    pub first_block: U64,
+    /// Last block where the cycle was found profitable.
+    pub last_block: U64,
+    /// Block where a pool of the cycle changed and the cycle was no longer profitable, `None` if it still was at the end of the archive.
+    pub closed_block: Option<U64>,
+    /// Best profit net of gas over the life of the opportunity, along with its input amount.
+    pub best_profit: I256,
+    pub best_amount_in: U256,
+    /// Value of `best_profit` in wei, zero if it cannot be priced.
+    pub best_profit_wei: U256,
+}
+
+impl BacktestOpportunity {
+    /// Number of blocks the opportunity lasted, up to `end_block` if it never closed.
+    pub fn persistence(&self, end_block: U64) -> u64 {
+        (self.closed_block.unwrap_or(end_block + 1) - self.first_block).as_u64()
+    }
+}
+
+/// Opportunities found while replaying an archive.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BacktestReport {
+    pub from_block: U64,
+    pub to_block: U64,
+    /// Number of archived blocks replayed, i.e. blocks emitting logs of the pools.
+    pub blocks: usize,
+    /// Opportunities, by first block.
+    pub opportunities: Vec<BacktestOpportunity>,
+}
+
+impl BacktestReport {
+    /// Sum of the best profits of the opportunities, in wei, as if each was executed once.
+    pub fn total_profit_wei(&self) -> U256 {
+        self.opportunities.iter().fold(U256::zero(), |total, opportunity| total.saturating_add(opportunity.best_profit_wei))
+    }
+
+    /// Serializes the report to the json file at `path`.
+    pub fn save(&self, path: &str) -> BotResult<()> {
+        let serialized_report = serde_json::to_string_pretty(self)
+            .map_err(|e| BotError::Config(format!("Failed to serialize backtest report: {}", e)))?;
+        std::fs::write(path, serialized_report)
+            .map_err(|e| BotError::Config(format!("Failed to write backtest report to file {}: {}", path, e)))
+    }
+
+    /// Average number of blocks an opportunity lasted.
+    pub fn average_persistence(&self) -> f64 {
+        if self.opportunities.is_empty() {
+            return 0.;
+        }
+        let total: u64 = self.opportunities.iter().map(|opportunity| opportunity.persistence(self.to_block)).sum();
+        total as f64 / self.opportunities.len() as f64
+    }
+}
+
+/// Replays `archive` on `pools`, the pools it was recorded from, running the detection and sizing of the bot
+/// on every block emitting logs between `from_block` and `to_block` included, by default the first and last blocks of the archive.
+/// The blocks before `from_block` are only applied to the pools. Gas is priced at the base fee of each block, in the wrapped native token `native`.
+/// An opportunity closes on the first block changing one of its pools after which the cycle is no longer found profitable.
+pub fn run_backtest(
+    archive: &Archive,
+    tokens: &[Token],
+    pools: Vec<Box<dyn Pool>>,
+    native: Option<usize>,
+    from_block: Option<U64>,
+    to_block: Option<U64>,
+) -> BotResult<BacktestReport> {
+    if pools.iter().map(|pool| pool.address()).ne(archive.pools.iter().copied()) {
+        return Err(BotError::Config("Archive was recorded from other pools".to_string()));
+    }
+    let (first, last) = match (archive.blocks.first(), archive.blocks.last()) {
+        (Some(first), Some(last)) => (first.number, last.number),
+        _ => return Err(BotError::Config("Archive has no block".to_string())),
+    };
+    let (from_block, to_block) = (from_block.unwrap_or(first).max(first), to_block.unwrap_or(last).min(last));
+    if from_block > to_block {
+        return Err(BotError::Config(format!("Blocks {} to {} are not in the archive of blocks {} to {}", from_block, to_block, first, last)));
+    }
+    let mut tracker = PoolTracker::new();
+    for pool in pools {
+        tracker.insert(pool);
+    }
+
+    let mut report = BacktestReport { from_block, to_block, blocks: 0, opportunities: Vec::new() };
+    let mut open: HashMap<Vec<(usize, usize)>, BacktestOpportunity> = HashMap::new();
+    let mut started = false;
+    for block in archive.blocks.iter().take_while(|block| block.number <= to_block) {
+        block.apply(&mut tracker)?;
+        let mut dirty = tracker.take_dirty();
+        if block.number < from_block {
+            continue;
+        }
+        report.blocks += 1;
+        if !started {
+            // the opportunities standing at the start of the range are found on any of their pools
+            started = true;
+            dirty = (0..tracker.pools.len()).collect();
+        }
+        if dirty.is_empty() {
+            continue;
+        }
+
+        let pools = &tracker.pools;
+        let graph = build_graph(pools, tokens.len());
+        let pricer = GasPricer::new(&graph, tokens, native, block.base_fee);
+        let mut found: Vec<Vec<(usize, usize)>> = Vec::new();
+        for cycle in find_cycles(&graph, pools, &dirty) {
+            let sized = match size_cycle(pools, tokens, &cycle, &pricer) {
+                Ok(sized) => sized,
+                Err(_) => continue,
+            };
+            let key = opportunity(&cycle);
+            let start = &tokens[cycle[0].0];
+            let profit_wei = pricer.native_value(sized.trade.profit.into_raw(), start).unwrap_or_default();
+            let entry = open.entry(key.clone()).or_insert_with(|| BacktestOpportunity {
+                opportunity: key.clone(),
+                start: start.token_id,
+                first_block: block.number,
+                last_block: block.number,
+                closed_block: None,
+                best_profit: sized.trade.profit,
+                best_amount_in: sized.trade.amount_in(),
+                best_profit_wei: profit_wei,
+            });
+            entry.last_block = block.number;
+            if sized.trade.profit > entry.best_profit {
+                entry.best_profit = sized.trade.profit;
+                entry.best_amount_in = sized.trade.amount_in();
+                entry.best_profit_wei = profit_wei;
+            }
+            found.push(key);
+        }
+
+        // the open opportunities whose pools changed without being found again are closed
+        let closed: Vec<Vec<(usize, usize)>> = open
+            .keys()
+            .filter(|key| !found.contains(key) && key.iter().any(|(_, pool_id)| dirty.binary_search(pool_id).is_ok()))
+            .cloned()
+            .collect();
+        for key in closed {
+            let mut opportunity = open.remove(&key).expect("Opportunity is open");
+            opportunity.closed_block = Some(block.number);
+            report.opportunities.push(opportunity);
+        }
+    }
+    report.opportunities.extend(open.into_values());
+    report.opportunities.sort_by(|a, b| (a.first_block, &a.opportunity).cmp(&(b.first_block, &b.opportunity)));
+    Ok(report)
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ tokens, univ2_pool, univ3_pool, };
+    use crate::archive::{ ArchivedBlock, PoolRefresh, };
+    use ethers::{ abi::{ encode, Token as AbiToken, }, types::{ Address, Bytes, H256, Log, }, };
+
+    fn reserves(reserve_0: u64, reserve_1: u64) -> Vec<u8> {
+        encode(&[AbiToken::Uint(U256::from(reserve_0)), AbiToken::Uint(U256::from(reserve_1))])
+    }
+
+    /// Round of `getReserves()` of a pair.
+    fn refresh(pool_id: usize, reserve_0: u64, reserve_1: u64) -> PoolRefresh {
+        let mut data = reserves(reserve_0, reserve_1);
+        data.extend(encode(&[AbiToken::Uint(U256::zero())]));
+        PoolRefresh { pool_id, rounds: vec![vec![Some(Bytes::from(data))]] }
+    }
+
+    fn block(number: u64, logs: Vec<Log>, refreshes: Vec<PoolRefresh>) -> ArchivedBlock {
+        ArchivedBlock {
+            number: U64::from(number),
+            hash: H256::from_low_u64_be(number),
+            parent_hash: H256::from_low_u64_be(number - 1),
+            base_fee: U256::zero(),
+            logs,
+            refreshes,
+        }
+    }
+
+    #[test]
+    fn test_run_backtest() {
+        let tokens = tokens(&[18; 2]);
+        let mut pools: Vec<Box<dyn Pool>> = (0..2)
+            .map(|pool_id| Box::new(univ2_pool(Address::repeat_byte(pool_id as u8 + 1), pool_id, (0, 1), None, &tokens)) as Box<dyn Pool>)
+            .collect();
+        // lends token 0 to the cycles between the pairs
+        pools.push(Box::new(univ3_pool(Address::repeat_byte(3), 2, (0, 1), 500., &tokens)));
+        let sync = |pool: &dyn Pool, reserve_0: u64, reserve_1: u64| Log {
+            address: pool.address(),
+            topics: pool.event_topics().unwrap(),
+            data: Bytes::from(reserves(reserve_0, reserve_1)),
+            ..Default::default()
+        };
+
+        // the second pair moves away at block 11 and back in line at block 14
+        let archive = Archive {
+            chain_id: 137,
+            pools: pools.iter().map(|pool| pool.address()).collect(),
+            blocks: vec![
+                block(10, Vec::new(), vec![refresh(0, 1_000_000_000, 1_000_000_000), refresh(1, 1_000_000_000, 1_000_000_000)]),
+                block(11, vec![sync(&*pools[1], 1_000_000_000, 1_100_000_000)], Vec::new()),
+                block(12, vec![sync(&*pools[1], 1_000_000_000, 1_120_000_000)], Vec::new()),
+                block(14, vec![sync(&*pools[1], 1_000_000_000, 1_000_000_000)], Vec::new()),
+                block(15, vec![sync(&*pools[1], 1_000_000_000, 1_050_000_000)], Vec::new()),
+            ],
+        };
+        let report = run_backtest(&archive, &tokens, pools.clone(), Some(0), None, None).unwrap();
+        assert_eq!((report.from_block, report.to_block, report.blocks), (U64::from(10), U64::from(15), 5));
+
+        // the same cycle twice: found at blocks 11 and 12, closed at 14, then reopened at 15 until the end
+        assert_eq!(report.opportunities.len(), 2);
+        let (first, second) = (&report.opportunities[0], &report.opportunities[1]);
+        assert_eq!(first.opportunity, second.opportunity);
+        assert_eq!((first.first_block, first.last_block, first.closed_block), (U64::from(11), U64::from(12), Some(U64::from(14))));
+        assert_eq!(first.persistence(report.to_block), 3);
+        assert_eq!((second.first_block, second.closed_block), (U64::from(15), None));
+        assert_eq!(second.persistence(report.to_block), 1);
+        assert_eq!(report.average_persistence(), 2.);
+
+        // the best profit is the one of block 12, priced in wei as token 0 is the native token
+        assert!(first.best_profit > second.best_profit && second.best_profit > I256::zero());
+        assert_eq!(report.total_profit_wei(), first.best_profit.into_raw() + second.best_profit.into_raw());
+
+        // from block 12, the opportunity standing since block 11 is found on its first replayed block
+        let report = run_backtest(&archive, &tokens, pools.clone(), Some(0), Some(U64::from(12)), Some(U64::from(14))).unwrap();
+        assert_eq!((report.from_block, report.to_block, report.blocks), (U64::from(12), U64::from(14), 2));
+        assert_eq!(report.opportunities.len(), 1);
+        assert_eq!((report.opportunities[0].first_block, report.opportunities[0].closed_block), (U64::from(12), Some(U64::from(14))));
+        assert_eq!(report.opportunities[0].best_profit, first.best_profit);
+        assert!(run_backtest(&archive, &tokens, pools.clone(), Some(0), Some(U64::from(16)), None).is_err());
+
+        // pools in another order
+        let mut shuffled = pools;
+        shuffled.reverse();
+        assert!(run_backtest(&archive, &tokens, shuffled, Some(0), None, None).is_err());
+    }
+}
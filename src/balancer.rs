@@ -0,0 +1,624 @@
+use ethers::{
+    middleware::SignerMiddleware,
+    providers::{Http, Provider, Middleware},
+    signers::LocalWallet,
+    types::{ Address, Bytes, U256, I256, U64, H256, Log, },
+    contract::{ BaseContract, AbiError, },
+    abi::{ Tokenize, Detokenize, },
+};
+use std::{
+    fs::File,
+    io::{Write, ErrorKind},
+    collections::HashMap,
+    any::Any,
+    sync::Arc,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::*;
+use crate::univ3::Token;
+use crate::balancer_math::*;
+use crate::multicall::Multicall;
+use crate::optimizer::Quote;
+use crate::pool::{ Pool, SwapPayment, };
+use crate::profitability::BALANCER_SWAP_GAS;
+use crate::error::{ BotError, BotResult, };
+
+/// `SwapKind.GIVEN_IN` of `Vault.swap()`.
+const GIVEN_IN: u8 = 0;
+
+/// `SingleSwap` struct of `Vault.swap()`: pool id, kind, asset in, asset out, amount and user data.
+type SingleSwap = (H256, u8, Address, Address, U256, Bytes);
+
+/// `FundManagement` struct of `Vault.swap()`: sender, from internal balance, recipient and to internal balance.
+type FundManagement = (Address, bool, Address, bool);
+
+//------------------------------------- BalancerImmutables
+
+/// Pricing of a Balancer V2 pool.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum BalancerPoolKind {
+    #[default]
+    Weighted,
+    Stable,
+}
+
+/// Balancer V2 pool resolved from its id in the Vault.
+#[derive(Debug, Clone, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BalancerImmutables {
+    pub address: Address,
+    pub pool_id: usize,
+    /// Id of the pool in the Vault.
+    pub vault_pool_id: H256,
+    /// Addresses of the tokens, in Vault order.
+    pub tokens: Vec<Address>,
+    /// Ids of the tokens, in Vault order.
+    pub token_ids: Vec<usize>,
+    pub kind: BalancerPoolKind,
+    /// Normalized weights of a weighted pool, fixed points summing to one. Empty for stable pools.
+    pub weights: Vec<U256>,
+}
+
+impl BalancerImmutables {
+    /// Reads the pools from `config/<chain id>/balancerPools.json`.
+    /// If the file is not found, the pools listed in `config/<chain id>/balancerPoolIds.json` are resolved through
+    /// the Vault and saved to it. No pools are loaded if neither file exists.
+    pub async fn get_pools(
+        chain_id: u32,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        tokens: &[Token],
+        chunk_size: usize,
+    ) -> BotResult<Vec<BalancerImmutables>> {
+        let file_storing_pools = format!("config/{}/balancerPools.json", &chain_id);
+        let file_storing_pool_ids = format!("config/{}/balancerPoolIds.json", &chain_id);
+
+        let file = match File::open(&file_storing_pools) {
+            Ok(f) => f,
+            Err(e) => match e.kind() {
+                ErrorKind::NotFound => {
+                    let ids_file = match File::open(&file_storing_pool_ids) {
+                        Ok(f) => f,
+                        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+                        Err(e) => return Err(BotError::Config(format!("Failed to open file {}: {}", &file_storing_pool_ids, e))),
+                    };
+                    let vault_pool_ids: Vec<H256> = serde_json::from_reader(ids_file)
+                        .map_err(|e| BotError::Config(format!("Failed to extract balancer pool ids from json: {}", e)))?;
+                    println!("{} not found, creating from Vault", &file_storing_pools);
+                    let multicall = Multicall::new(chain_id, provider, chunk_size)?;
+                    let block = provider
+                        .get_block_number()
+                        .await
+                        .map_err(|e| BotError::Rpc(format!("`eth_blockNumber` asynchronous call failed: {}", e)))?;
+                    let vault = balancer_vault_addr(chain_id)?;
+                    let mut pools = BalancerImmutables::discover(vault, &vault_pool_ids, tokens, &multicall, block).await?;
+                    for (pool_id, pool) in pools.iter_mut().enumerate() {
+                        pool.pool_id = pool_id;
+                    }
+                    BalancerImmutables::save_pools(&file_storing_pools, &pools)?;
+                    return Ok(pools);
+                },
+                _ => return Err(BotError::Config(format!("Failed to open file {}: {}", &file_storing_pools, e))),
+            },
+        };
+        serde_json::from_reader(file)
+            .map_err(|e| BotError::Config(format!("Failed to extract balancer pools from json: {}", e)))
+    }
+
+    /// Resolves the pools `vault_pool_ids` of `vault` whose tokens are all in `tokens`, with a `pool_id` of 0.
+    /// Pools are weighted if they expose `getNormalizedWeights()`, stable if they expose `getAmplificationParameter()`,
+    /// and skipped otherwise.
+    pub async fn discover(
+        vault: Address,
+        vault_pool_ids: &[H256],
+        tokens: &[Token],
+        multicall: &Multicall<'_>,
+        block: U64,
+    ) -> BotResult<Vec<BalancerImmutables>> {
+        let vault_abi = BaseContract::from(i_balancer_vault_abi()?);
+        let pool_abi = BaseContract::from(i_balancer_pool_abi()?);
+        let encode_error = |name: &'static str| move |e: AbiError| BotError::Abi(format!("`{}()` method not found in ABI: {}", name, e));
+        let decode_error = |name: &'static str| move |e: AbiError| BotError::Decode(format!("Failed to decode `{}()` output: {}", name, e));
+        let reverted = |name: &'static str| BotError::Execution(format!("`{}()` call reverted", name));
+        let token_ids: HashMap<Address, usize> = tokens.iter().map(|token| (token.address, token.token_id)).collect();
+
+        let mut calls: Vec<(Address, Bytes)> = Vec::with_capacity(2 * vault_pool_ids.len());
+        for &id in vault_pool_ids {
+            calls.push((vault, vault_abi.encode("getPool", id).map_err(encode_error("Vault.getPool"))?));
+            calls.push((vault, vault_abi.encode("getPoolTokens", id).map_err(encode_error("Vault.getPoolTokens"))?));
+        }
+        let mut pools: Vec<BalancerImmutables> = Vec::new();
+        for (&vault_pool_id, data) in vault_pool_ids.iter().zip(multicall.aggregate(&calls, block).await?.chunks(2)) {
+            let (address, _): (Address, u8) = vault_abi
+                .decode_output("getPool", data[0].as_ref().ok_or_else(|| reverted("Vault.getPool"))?)
+                .map_err(decode_error("Vault.getPool"))?;
+            let (pool_tokens, _, _): (Vec<Address>, Vec<U256>, U256) = vault_abi
+                .decode_output("getPoolTokens", data[1].as_ref().ok_or_else(|| reverted("Vault.getPoolTokens"))?)
+                .map_err(decode_error("Vault.getPoolTokens"))?;
+            let ids: Option<Vec<usize>> = pool_tokens.iter().map(|token| token_ids.get(token).copied()).collect();
+            match ids {
+                Some(ids) if ids.len() >= 2 => pools.push(BalancerImmutables {
+                    address,
+                    vault_pool_id,
+                    tokens: pool_tokens,
+                    token_ids: ids,
+                    ..Default::default()
+                }),
+                _ => println!("balancer pool {:?} has unknown tokens, skipped", vault_pool_id),
+            }
+        }
+
+        // tell weighted pools from stable pools
+        let mut calls: Vec<(Address, Bytes)> = Vec::with_capacity(2 * pools.len());
+        for pool in &pools {
+            calls.push((pool.address, pool_abi.encode("getNormalizedWeights", ()).map_err(encode_error("Pool.getNormalizedWeights"))?));
+            calls.push((pool.address, pool_abi.encode("getAmplificationParameter", ()).map_err(encode_error("Pool.getAmplificationParameter"))?));
+        }
+        let returns = multicall.aggregate(&calls, block).await?;
+        let mut resolved: Vec<BalancerImmutables> = Vec::with_capacity(pools.len());
+        for (mut pool, data) in pools.into_iter().zip(returns.chunks(2)) {
+            match (&data[0], &data[1]) {
+                (Some(weights), _) => {
+                    pool.weights = pool_abi
+                        .decode_output("getNormalizedWeights", weights)
+                        .map_err(decode_error("Pool.getNormalizedWeights"))?;
+                    pool.kind = BalancerPoolKind::Weighted;
+                },
+                (None, Some(_)) => pool.kind = BalancerPoolKind::Stable,
+                (None, None) => {
+                    println!("balancer pool {:?} is neither weighted nor stable, skipped", pool.vault_pool_id);
+                    continue;
+                },
+            }
+            resolved.push(pool);
+        }
+        Ok(resolved)
+    }
+
+    /// Serializes `pools` to the json file at `path`.
+    fn save_pools(path: &str, pools: &[BalancerImmutables]) -> BotResult<()> {
+        let serialized_pools = serde_json::to_string_pretty(pools)
+            .map_err(|e| BotError::Config(format!("Failed to serialize balancer pools: {}", e)))?;
+        let mut pools_file = File::create(path)
+            .map_err(|e| BotError::Config(format!("Failed to create file {}: {}", path, e)))?;
+        pools_file
+            .write_all(serialized_pools.as_bytes())
+            .map_err(|e| BotError::Config(format!("Failed to write balancer pools to file {}: {}", path, e)))
+    }
+}
+
+//------------------------------------- BalancerSnapshot
+
+/// State of a Balancer V2 pool, enough to quote swaps between its tokens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalancerSnapshot {
+    pub kind: BalancerPoolKind,
+    /// Balance of each token in the Vault, in raw token units.
+    pub balances: Vec<U256>,
+    /// Fixed point factor of each token upscaling its amounts to 18 decimals.
+    pub scaling_factors: Vec<U256>,
+    /// Normalized weights of a weighted pool.
+    pub weights: Vec<U256>,
+    /// Amplification parameter of a stable pool, including `AMP_PRECISION`.
+    pub amp: U256,
+    /// Swap fee as a fixed point.
+    pub swap_fee: U256,
+}
+
+impl BalancerSnapshot {
+    /// Returns the amount of token `j` received for `amount_in` of token `i`, as `Vault.queryBatchSwap()` would.
+    pub fn get_amount_out(&self, i: usize, j: usize, amount_in: U256) -> Result<U256, &'static str> {
+        if i == j || i >= self.balances.len() || j >= self.balances.len() {
+            return Err("BAL#521");
+        }
+        let amount_in = amount_in.checked_sub(mul_up(amount_in, self.swap_fee)?).ok_or("BAL#001")?;
+        let balances = self.balances
+            .iter()
+            .zip(&self.scaling_factors)
+            .map(|(&balance, &scaling_factor)| mul_down(balance, scaling_factor))
+            .collect::<Result<Vec<U256>, &'static str>>()?;
+        let amount_in = mul_down(amount_in, self.scaling_factors[i])?;
+        let amount_out = match self.kind {
+            BalancerPoolKind::Weighted => {
+                calc_out_given_in(balances[i], self.weights[i], balances[j], self.weights[j], amount_in)?
+            },
+            BalancerPoolKind::Stable => {
+                let invariant = calculate_invariant(self.amp, &balances)?;
+                calc_stable_out_given_in(self.amp, &balances, i, j, amount_in, invariant)?
+            },
+        };
+        div_down(amount_out, self.scaling_factors[j])
+    }
+}
+
+impl Quote for BalancerSnapshot {
+    fn get_amount_out(&self, token_in: usize, token_out: usize, amount_in: U256) -> Result<U256, &'static str> {
+        BalancerSnapshot::get_amount_out(self, token_in, token_out, amount_in)
+    }
+}
+
+//------------------------------------- BalancerPool
+
+/// Balancer V2 pool, made of its immutables and a snapshot of its state.
+/// The balances are held and the swaps executed by the Vault, which emits the logs of every pool.
+#[derive(Debug, Clone)]
+pub struct BalancerPool {
+    pub immutables: BalancerImmutables,
+    /// `None` until the pool is first fetched.
+    pub snapshot: Option<BalancerSnapshot>,
+    decimals: Vec<u8>,
+    vault: Address,
+    /// Signatures of the Vault `Swap` and `PoolBalanceChanged` events, computed once since `apply_log` checks them
+    /// for every log of the Vault.
+    topics: Vec<H256>,
+    vault_abi: Arc<BaseContract>,
+    pool_abi: Arc<BaseContract>,
+}
+
+impl BalancerPool {
+    pub fn new(
+        immutables: BalancerImmutables,
+        tokens: &[Token],
+        vault: Address,
+        vault_abi: Arc<BaseContract>,
+        pool_abi: Arc<BaseContract>,
+    ) -> BotResult<Self> {
+        let topics = ["Swap", "PoolBalanceChanged"]
+            .iter()
+            .map(|name| {
+                vault_abi
+                    .abi()
+                    .event(name)
+                    .map(|event| event.signature())
+                    .map_err(|e| BotError::Abi(format!("`Vault.{}` event not found in ABI: {}", name, e)))
+            })
+            .collect::<BotResult<Vec<H256>>>()?;
+        Ok(Self {
+            decimals: immutables.token_ids.iter().map(|&id| tokens[id].decimals).collect(),
+            immutables,
+            snapshot: None,
+            vault,
+            topics,
+            vault_abi,
+            pool_abi,
+        })
+    }
+
+    /// Returns the pools of `BalancerImmutables::get_pools`, yet to be fetched.
+    pub async fn load(
+        chain_id: u32,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        tokens: &[Token],
+        chunk_size: usize,
+    ) -> BotResult<Vec<Self>> {
+        let vault = balancer_vault_addr(chain_id)?;
+        let vault_abi = Arc::new(BaseContract::from(i_balancer_vault_abi()?));
+        let pool_abi = Arc::new(BaseContract::from(i_balancer_pool_abi()?));
+        BalancerImmutables::get_pools(chain_id, provider, tokens, chunk_size)
+            .await?
+            .into_iter()
+            .map(|immutables| BalancerPool::new(immutables, tokens, vault, vault_abi.clone(), pool_abi.clone()))
+            .collect()
+    }
+
+    fn encode<T: Tokenize>(&self, contract: &BaseContract, target: Address, name: &str, args: T) -> BotResult<(Address, Bytes)> {
+        let data = contract
+            .encode(name, args)
+            .map_err(|e| BotError::Abi(format!("`{}()` method not found in ABI: {}", name, e)))?;
+        Ok((target, data))
+    }
+
+    fn decode<D: Detokenize>(&self, contract: &BaseContract, name: &str, data: Option<&Option<Bytes>>) -> BotResult<D> {
+        let data = data
+            .cloned()
+            .flatten()
+            .ok_or_else(|| BotError::Execution(format!("`{}()` call reverted on balancer pool {:?}", name, self.immutables.vault_pool_id)))?;
+        contract
+            .decode_output(name, data)
+            .map_err(|e| BotError::Decode(format!("Failed to decode `{}()` output: {}", name, e)))
+    }
+
+    /// Index of `token` in the pool.
+    fn index_of(&self, token: Address) -> BotResult<usize> {
+        self.immutables.tokens
+            .iter()
+            .position(|&t| t == token)
+            .ok_or_else(|| BotError::Decode(format!("Token {:?} not in balancer pool {:?}", token, self.immutables.vault_pool_id)))
+    }
+}
+
+impl Quote for BalancerPool {
+    fn get_amount_out(&self, token_in: usize, token_out: usize, amount_in: U256) -> Result<U256, &'static str> {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.get_amount_out(token_in, token_out, amount_in),
+            None => Err("Pool not fetched"),
+        }
+    }
+}
+
+impl Pool for BalancerPool {
+    fn address(&self) -> Address {
+        self.immutables.address
+    }
+
+    fn log_address(&self) -> Address {
+        self.vault
+    }
+
+    fn tokens(&self) -> Vec<usize> {
+        self.immutables.token_ids.clone()
+    }
+
+    fn is_stale(&self) -> bool {
+        self.snapshot.is_none()
+    }
+
+    fn invalidate(&mut self) {
+        self.snapshot = None;
+    }
+
+    /// Fetches the balances from the Vault, then the swap fee and the amplification parameter of a stable pool.
+    fn refresh_calls(&self) -> BotResult<Vec<(Address, Bytes)>> {
+        let mut calls = vec![
+            self.encode(&self.vault_abi, self.vault, "getPoolTokens", self.immutables.vault_pool_id)?,
+            self.encode(&self.pool_abi, self.immutables.address, "getSwapFeePercentage", ())?,
+        ];
+        if self.immutables.kind == BalancerPoolKind::Stable {
+            calls.push(self.encode(&self.pool_abi, self.immutables.address, "getAmplificationParameter", ())?);
+        }
+        Ok(calls)
+    }
+
+    fn apply_refresh(&mut self, returns: &[Option<Bytes>]) -> BotResult<()> {
+        let (_, balances, _): (Vec<Address>, Vec<U256>, U256) = self.decode(&self.vault_abi, "getPoolTokens", returns.first())?;
+        let swap_fee: U256 = self.decode(&self.pool_abi, "getSwapFeePercentage", returns.get(1))?;
+        let amp = match self.immutables.kind {
+            BalancerPoolKind::Weighted => U256::zero(),
+            BalancerPoolKind::Stable => {
+                let (value, _, _): (U256, bool, U256) = self.decode(&self.pool_abi, "getAmplificationParameter", returns.get(2))?;
+                value
+            },
+        };
+        let scaling_factors = self.decimals
+            .iter()
+            .map(|&decimals| {
+                36usize
+                    .checked_sub(decimals as usize)
+                    .map(U256::exp10)
+                    .ok_or_else(|| BotError::Config(format!(
+                        "Token of {} decimals in balancer pool {:?}, at most 36 supported", decimals, self.immutables.address,
+                    )))
+            })
+            .collect::<BotResult<Vec<U256>>>()?;
+        self.snapshot = Some(BalancerSnapshot {
+            kind: self.immutables.kind,
+            balances,
+            scaling_factors,
+            weights: self.immutables.weights.clone(),
+            amp,
+            swap_fee,
+        });
+        Ok(())
+    }
+
+    fn event_topics(&self) -> BotResult<Vec<H256>> {
+        Ok(self.topics.clone())
+    }
+
+    /// Applies the `Swap` and `PoolBalanceChanged` logs of the Vault about this pool.
+    /// Changes of the swap fee or of the amplification parameter are only picked up when the pool is refetched.
+    fn apply_log(&mut self, log: &Log) -> BotResult<bool> {
+        if log.topics.get(1) != Some(&self.immutables.vault_pool_id) {
+            return Ok(false);
+        }
+        let topics = &self.topics;
+        let underflow = || BotError::Math(format!("Balance underflow in balancer pool {:?}", self.immutables.vault_pool_id));
+        if log.topics.first() == Some(&topics[0]) {
+            let (_, token_in, token_out, amount_in, amount_out): (H256, Address, Address, U256, U256) = self.vault_abi
+                .decode_event("Swap", log.topics.clone(), log.data.clone())
+                .map_err(|e| BotError::Decode(format!("Failed to decode `Vault.Swap` event: {}", e)))?;
+            let (i, j) = (self.index_of(token_in)?, self.index_of(token_out)?);
+            let snapshot = match self.snapshot.as_mut() {
+                Some(snapshot) => snapshot,
+                None => return Ok(false),
+            };
+            snapshot.balances[i] = snapshot.balances[i].checked_add(amount_in).ok_or_else(underflow)?;
+            snapshot.balances[j] = snapshot.balances[j].checked_sub(amount_out).ok_or_else(underflow)?;
+            Ok(true)
+        } else if log.topics.first() == Some(&topics[1]) {
+            let (_, _, tokens, deltas, protocol_fees): (H256, Address, Vec<Address>, Vec<I256>, Vec<U256>) = self.vault_abi
+                .decode_event("PoolBalanceChanged", log.topics.clone(), log.data.clone())
+                .map_err(|e| BotError::Decode(format!("Failed to decode `Vault.PoolBalanceChanged` event: {}", e)))?;
+            let indices = tokens.iter().map(|&token| self.index_of(token)).collect::<BotResult<Vec<usize>>>()?;
+            let snapshot = match self.snapshot.as_mut() {
+                Some(snapshot) => snapshot,
+                None => return Ok(false),
+            };
+            for ((i, delta), fee) in indices.into_iter().zip(deltas).zip(protocol_fees) {
+                let balance = if delta.is_negative() {
+                    snapshot.balances[i].checked_sub((-delta).into_raw())
+                } else {
+                    snapshot.balances[i].checked_add(delta.into_raw())
+                };
+                snapshot.balances[i] = balance.and_then(|balance| balance.checked_sub(fee)).ok_or_else(underflow)?;
+            }
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Quotes a swap of a millionth of the input balance, so the weight includes the fee.
+    fn log_price(&self, token_in: usize, token_out: usize) -> Option<f64> {
+        let snapshot = self.snapshot.as_ref()?;
+        let amount_in = (snapshot.balances[token_in] / 1_000_000).max(U256::one());
+        let amount_out = snapshot.get_amount_out(token_in, token_out, amount_in).ok()?;
+        if amount_out.is_zero() {
+            return None;
+        }
+        Some(amounts_to_log_price(amount_in, amount_out, self.decimals[token_in], self.decimals[token_out]))
+    }
+
+    /// `Vault.swap()` of exactly `amount_in`, for at least `amount_out`.
+    /// `recipient` is also the sender, it must approve the Vault to spend `amount_in` beforehand.
+    fn swap_calldata(&self, token_in: usize, token_out: usize, amount_in: U256, amount_out: U256, recipient: Address) -> BotResult<Bytes> {
+        let single_swap: SingleSwap = (
+            self.immutables.vault_pool_id,
+            GIVEN_IN,
+            self.immutables.tokens[token_in],
+            self.immutables.tokens[token_out],
+            amount_in,
+            Bytes::default(),
+        );
+        let funds: FundManagement = (recipient, false, recipient, false);
+        self.vault_abi
+            .encode("swap", (single_swap, funds, amount_out, U256::MAX))
+            .map_err(|e| BotError::Abi(format!("`Vault.swap()` method not found in ABI: {}", e)))
+    }
+
+    fn swap_payment(&self) -> SwapPayment {
+        SwapPayment::Approval
+    }
+
+    fn swap_gas(&self, _token_in: usize, _token_out: usize, _amount_in: U256) -> u64 {
+        BALANCER_SWAP_GAS
+    }
+
+    fn swap_target(&self) -> Address {
+        self.vault
+    }
+
+    fn box_clone(&self) -> Box<dyn Pool> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::Token as AbiToken;
+    use crate::pool::PoolTracker;
+    use crate::testing::tokens;
+
+    fn weighted_pool(vault_pool_id: H256, tokens: &[Token]) -> BalancerPool {
+        let immutables = BalancerImmutables {
+            address: Address::from_low_u64_be(vault_pool_id.to_low_u64_be()),
+            pool_id: 0,
+            vault_pool_id,
+            tokens: (1..=3).map(Address::from_low_u64_be).collect(),
+            token_ids: vec![0, 1, 2],
+            kind: BalancerPoolKind::Weighted,
+            weights: vec![U256::exp10(17) * 5, U256::exp10(17) * 3, U256::exp10(17) * 2],
+        };
+        let vault_abi = Arc::new(BaseContract::from(i_balancer_vault_abi().unwrap()));
+        let pool_abi = Arc::new(BaseContract::from(i_balancer_pool_abi().unwrap()));
+        BalancerPool::new(immutables, tokens, Address::repeat_byte(0xba), vault_abi, pool_abi).unwrap()
+    }
+
+    fn refresh(pool: &mut BalancerPool, balances: &[U256]) -> BotResult<()> {
+        let uints = |values: &[U256]| AbiToken::Array(values.iter().copied().map(AbiToken::Uint).collect());
+        let tokens = AbiToken::Array(pool.immutables.tokens.iter().copied().map(AbiToken::Address).collect());
+        let pool_tokens = ethers::abi::encode(&[tokens, uints(balances), AbiToken::Uint(U256::zero())]);
+        let swap_fee = ethers::abi::encode(&[AbiToken::Uint(U256::exp10(15))]);
+        assert_eq!(pool.refresh_calls().unwrap().len(), 2);
+        pool.apply_refresh(&[Some(Bytes::from(pool_tokens)), Some(Bytes::from(swap_fee))])
+    }
+
+    #[test]
+    fn test_weighted_snapshot_get_amount_out() {
+        // 50/50 pool quotes like a constant product pool with the same fee
+        let snapshot = BalancerSnapshot {
+            kind: BalancerPoolKind::Weighted,
+            balances: vec![U256::exp10(24), U256::exp10(12)],
+            scaling_factors: vec![U256::exp10(18), U256::exp10(30)],
+            weights: vec![U256::exp10(17) * 5, U256::exp10(17) * 5],
+            amp: U256::zero(),
+            swap_fee: U256::exp10(16),
+        };
+        let amount_in = U256::exp10(21);
+        let out = snapshot.get_amount_out(0, 1, amount_in).unwrap().as_u128() as f64;
+        let expected = 1e12 * 0.99e21 / (1e24 + 0.99e21);
+        assert!(out <= expected && expected - out < 2.);
+        assert_eq!(snapshot.get_amount_out(0, 0, amount_in), Err("BAL#521"));
+        assert_eq!(snapshot.get_amount_out(0, 1, U256::exp10(24)), Err("BAL#304"));
+    }
+
+    #[test]
+    fn test_balancer_pool_vault_logs() {
+        let tokens = tokens(&[18; 3]);
+        let id = H256::from_low_u64_be(7);
+        let mut tracker = PoolTracker::new();
+        for vault_pool_id in [id, H256::from_low_u64_be(8)] {
+            let mut pool = weighted_pool(vault_pool_id, &tokens);
+            refresh(&mut pool, &[U256::exp10(21); 3]).unwrap();
+            tracker.insert(Box::new(pool));
+        }
+        tracker.take_dirty();
+        let pool = tracker.pools[0].as_any().downcast_ref::<BalancerPool>().unwrap();
+        // spot price of the weights ratio, net of the 0.1% fee
+        let expected = -(5. / 3. * 0.999f64).ln();
+        assert!((pool.log_price(0, 1).unwrap() - expected).abs() < 1e-5);
+        let topics = pool.event_topics().unwrap();
+
+        // swaps emitted by the vault reach the pool of their id only
+        let swap = Log {
+            address: Address::repeat_byte(0xba),
+            topics: vec![
+                topics[0],
+                id,
+                H256::from(Address::from_low_u64_be(1)),
+                H256::from(Address::from_low_u64_be(3)),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[AbiToken::Uint(U256::exp10(18)), AbiToken::Uint(U256::exp10(17))])),
+            ..Default::default()
+        };
+        assert_eq!(tracker.apply_log(&swap).unwrap(), Some(0));
+        assert_eq!(tracker.take_dirty(), vec![0]);
+        let snapshot = |tracker: &PoolTracker| tracker.pools[0].as_any().downcast_ref::<BalancerPool>().unwrap().snapshot.clone().unwrap();
+        assert_eq!(snapshot(&tracker).balances, vec![U256::exp10(21) + U256::exp10(18), U256::exp10(21), U256::exp10(21) - U256::exp10(17)]);
+
+        // joins and exits net of the protocol fees
+        let uints = |values: Vec<U256>| AbiToken::Array(values.into_iter().map(AbiToken::Uint).collect());
+        let deltas = AbiToken::Array(vec![
+            AbiToken::Int(I256::exp10(18).into_raw()),
+            AbiToken::Int((-I256::exp10(18)).into_raw()),
+        ]);
+        let join = Log {
+            address: Address::repeat_byte(0xba),
+            topics: vec![topics[1], id, H256::zero()],
+            data: Bytes::from(ethers::abi::encode(&[
+                AbiToken::Array(vec![AbiToken::Address(Address::from_low_u64_be(1)), AbiToken::Address(Address::from_low_u64_be(2))]),
+                deltas,
+                uints(vec![U256::one(), U256::zero()]),
+            ])),
+            ..Default::default()
+        };
+        assert_eq!(tracker.apply_log(&join).unwrap(), Some(0));
+        let balances = snapshot(&tracker).balances;
+        assert_eq!(balances[0], U256::exp10(21) + U256::exp10(18) * 2 - 1);
+        assert_eq!(balances[1], U256::exp10(21) - U256::exp10(18));
+
+        let pool = &tracker.pools[0];
+        assert_eq!(pool.swap_target(), Address::repeat_byte(0xba));
+        let calldata = pool.swap_calldata(0, 2, U256::from(10), U256::from(9), Address::repeat_byte(2)).unwrap();
+        let vault_abi = BaseContract::from(i_balancer_vault_abi().unwrap());
+        let ((pool_id, kind, asset_in, asset_out, amount, _), (sender, _, recipient, _), limit, _):
+            (SingleSwap, FundManagement, U256, U256) = vault_abi.decode("swap", calldata).unwrap();
+        assert_eq!((pool_id, kind, asset_in, asset_out, amount), (id, GIVEN_IN, Address::from_low_u64_be(1), Address::from_low_u64_be(3), U256::from(10)));
+        assert_eq!((sender, recipient, limit), (Address::repeat_byte(2), Address::repeat_byte(2), U256::from(9)));
+
+        // a token of more than 36 decimals has no scaling factor
+        let mut tokens = tokens;
+        tokens[2].decimals = 40;
+        let mut pool = weighted_pool(H256::from_low_u64_be(9), &tokens);
+        assert!(matches!(refresh(&mut pool, &[U256::exp10(21); 3]), Err(BotError::Config(_))));
+        assert!(pool.snapshot.is_none());
+    }
+}
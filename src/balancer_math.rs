@@ -0,0 +1,464 @@
+use ethers::types::{ U256, I256, };
+
+//------------------------------------- FixedPoint
+
+/// One in the 18 decimals fixed point of the Balancer V2 math.
+pub const ONE: u64 = 1_000_000_000_000_000_000;
+
+/// Relative error bound of `LogExpMath.pow`, as a fixed point.
+const MAX_POW_RELATIVE_ERROR: u64 = 10_000;
+
+fn one() -> U256 {
+    U256::from(ONE)
+}
+
+fn add(a: U256, b: U256) -> Result<U256, &'static str> {
+    a.checked_add(b).ok_or("BAL#000")
+}
+
+fn sub(a: U256, b: U256) -> Result<U256, &'static str> {
+    a.checked_sub(b).ok_or("BAL#001")
+}
+
+fn mul(a: U256, b: U256) -> Result<U256, &'static str> {
+    a.checked_mul(b).ok_or("BAL#003")
+}
+
+/// Returns `ceil(a / b)` as [Math.divUp](https://github.com/balancer-labs/balancer-v2-monorepo/blob/master/pkg/solidity-utils/contracts/math/Math.sol) does.
+fn div_up_int(a: U256, b: U256) -> Result<U256, &'static str> {
+    if b.is_zero() {
+        return Err("BAL#004");
+    }
+    if a.is_zero() { Ok(U256::zero()) } else { Ok((a - 1) / b + 1) }
+}
+
+pub fn mul_down(a: U256, b: U256) -> Result<U256, &'static str> {
+    Ok(mul(a, b)? / one())
+}
+
+pub fn mul_up(a: U256, b: U256) -> Result<U256, &'static str> {
+    let product = mul(a, b)?;
+    if product.is_zero() { Ok(U256::zero()) } else { Ok((product - 1) / one() + 1) }
+}
+
+pub fn div_down(a: U256, b: U256) -> Result<U256, &'static str> {
+    if b.is_zero() {
+        return Err("BAL#004");
+    }
+    Ok(a.checked_mul(one()).ok_or("BAL#005")? / b)
+}
+
+pub fn div_up(a: U256, b: U256) -> Result<U256, &'static str> {
+    if b.is_zero() {
+        return Err("BAL#004");
+    }
+    if a.is_zero() {
+        return Ok(U256::zero());
+    }
+    Ok((a.checked_mul(one()).ok_or("BAL#005")? - 1) / b + 1)
+}
+
+/// Returns `1 - x`, floored at zero.
+pub fn complement(x: U256) -> U256 {
+    if x < one() { one() - x } else { U256::zero() }
+}
+
+/// Returns `x^y` rounded up, as [FixedPoint.powUp](https://github.com/balancer-labs/balancer-v2-monorepo/blob/master/pkg/solidity-utils/contracts/math/FixedPoint.sol) does:
+/// exact for the exponents 1, 2 and 4, otherwise `LogExpMath.pow` plus its maximum error.
+pub fn pow_up(x: U256, y: U256) -> Result<U256, &'static str> {
+    if y == one() {
+        Ok(x)
+    } else if y == one() * 2 {
+        mul_up(x, x)
+    } else if y == one() * 4 {
+        let square = mul_up(x, x)?;
+        mul_up(square, square)
+    } else {
+        let raw = pow(x, y)?;
+        let max_error = add(mul_up(raw, U256::from(MAX_POW_RELATIVE_ERROR))?, U256::one())?;
+        add(raw, max_error)
+    }
+}
+
+//------------------------------------- LogExpMath
+
+const ONE_18: i128 = 1_000_000_000_000_000_000;
+const ONE_20: i128 = 100_000_000_000_000_000_000;
+const MAX_NATURAL_EXPONENT: i128 = 130 * ONE_18;
+const MIN_NATURAL_EXPONENT: i128 = -41 * ONE_18;
+const LN_36_LOWER_BOUND: i128 = ONE_18 - 100_000_000_000_000_000;
+const LN_36_UPPER_BOUND: i128 = ONE_18 + 100_000_000_000_000_000;
+
+/// `2^7` with 18 decimals.
+const X0: i128 = 128_000_000_000_000_000_000;
+/// `2^6` with 18 decimals and `e^X1` without decimals.
+const X1: i128 = 64_000_000_000_000_000_000;
+const A1: i128 = 6_235_149_080_811_616_882_910_000_000;
+
+/// `2^5` down to `2^-4` with 20 decimals, along with their exponential with 20 decimals.
+const XS: [i128; 10] = [
+    3_200_000_000_000_000_000_000,
+    1_600_000_000_000_000_000_000,
+    800_000_000_000_000_000_000,
+    400_000_000_000_000_000_000,
+    200_000_000_000_000_000_000,
+    100_000_000_000_000_000_000,
+    50_000_000_000_000_000_000,
+    25_000_000_000_000_000_000,
+    12_500_000_000_000_000_000,
+    6_250_000_000_000_000_000,
+];
+const AS: [i128; 10] = [
+    7_896_296_018_268_069_516_100_000_000_000_000,
+    888_611_052_050_787_263_676_000_000,
+    298_095_798_704_172_827_474_000,
+    5_459_815_003_314_423_907_810,
+    738_905_609_893_065_022_723,
+    271_828_182_845_904_523_536,
+    164_872_127_070_012_814_685,
+    128_402_541_668_774_148_407,
+    113_314_845_306_682_631_683,
+    106_449_445_891_785_942_956,
+];
+
+fn int(x: i128) -> I256 {
+    I256::from(x)
+}
+
+/// `e^X0` without decimals, which does not fit in 128 bits.
+fn a0() -> I256 {
+    int(388_770_840_599_459_509_222) * I256::from_raw(U256::exp10(35))
+}
+
+/// Returns `x^y` for 18 decimals fixed points, as [LogExpMath.pow](https://github.com/balancer-labs/balancer-v2-monorepo/blob/master/pkg/solidity-utils/contracts/math/LogExpMath.sol) does,
+/// with a relative error below `MAX_POW_RELATIVE_ERROR`.
+pub fn pow(x: U256, y: U256) -> Result<U256, &'static str> {
+    if y.is_zero() {
+        return Ok(one());
+    }
+    if x.is_zero() {
+        return Ok(U256::zero());
+    }
+    if x.bit(255) {
+        return Err("BAL#006");
+    }
+    // 2^254 / ONE_20
+    if y >= (U256::one() << 254) / U256::from(ONE_20) {
+        return Err("BAL#007");
+    }
+    let (x, y) = (I256::from_raw(x), I256::from_raw(y));
+
+    let logx_times_y = if int(LN_36_LOWER_BOUND) < x && x < int(LN_36_UPPER_BOUND) {
+        let ln_36_x = ln_36(x);
+        (ln_36_x / int(ONE_18)) * y + ((ln_36_x % int(ONE_18)) * y) / int(ONE_18)
+    } else {
+        ln(x) * y
+    } / int(ONE_18);
+    if logx_times_y < int(MIN_NATURAL_EXPONENT) || logx_times_y > int(MAX_NATURAL_EXPONENT) {
+        return Err("BAL#008");
+    }
+    Ok(exp(logx_times_y)?.into_raw())
+}
+
+/// Returns `e^x` for an 18 decimals fixed point.
+pub fn exp(x: I256) -> Result<I256, &'static str> {
+    if x < int(MIN_NATURAL_EXPONENT) || x > int(MAX_NATURAL_EXPONENT) {
+        return Err("BAL#009");
+    }
+    if x.is_negative() {
+        return Ok(int(ONE_18) * int(ONE_18) / exp(-x)?);
+    }
+
+    let mut x = x;
+    let first_an = if x >= int(X0) {
+        x -= int(X0);
+        a0()
+    } else if x >= int(X1) {
+        x -= int(X1);
+        int(A1)
+    } else {
+        int(1)
+    };
+
+    // 20 decimals from here, x10 and x11 are not needed for the precision
+    x *= int(100);
+    let mut product = int(ONE_20);
+    for (&x_n, &a_n) in XS.iter().zip(AS.iter()).take(8) {
+        if x >= int(x_n) {
+            x -= int(x_n);
+            product = product * int(a_n) / int(ONE_20);
+        }
+    }
+
+    // Taylor series up to the 12th term
+    let mut series_sum = int(ONE_20) + x;
+    let mut term = x;
+    for n in 2..=12 {
+        term = term * x / int(ONE_20) / int(n);
+        series_sum += term;
+    }
+    Ok(product * series_sum / int(ONE_20) * first_an / int(100))
+}
+
+/// Returns the natural logarithm of an 18 decimals fixed point.
+fn ln(a: I256) -> I256 {
+    if a < int(ONE_18) {
+        return -ln(int(ONE_18) * int(ONE_18) / a);
+    }
+
+    let mut a = a;
+    let mut sum = int(0);
+    if a >= a0() * int(ONE_18) {
+        a /= a0();
+        sum += int(X0);
+    }
+    if a >= int(A1) * int(ONE_18) {
+        a /= int(A1);
+        sum += int(X1);
+    }
+
+    // 20 decimals from here
+    sum *= int(100);
+    a *= int(100);
+    for (&x_n, &a_n) in XS.iter().zip(AS.iter()) {
+        if a >= int(a_n) {
+            a = a * int(ONE_20) / int(a_n);
+            sum += int(x_n);
+        }
+    }
+
+    // ln(a) = 2 * artanh(z) with z = (a - 1) / (a + 1), up to the 11th power
+    let z = (a - int(ONE_20)) * int(ONE_20) / (a + int(ONE_20));
+    let z_squared = z * z / int(ONE_20);
+    let mut num = z;
+    let mut series_sum = num;
+    for n in [3, 5, 7, 9, 11] {
+        num = num * z_squared / int(ONE_20);
+        series_sum += num / int(n);
+    }
+    (sum + series_sum * int(2)) / int(100)
+}
+
+/// Returns the natural logarithm of an 18 decimals fixed point close to one, with 36 decimals.
+fn ln_36(x: I256) -> I256 {
+    let one_36 = int(ONE_18) * int(ONE_18);
+    let x = x * int(ONE_18);
+    let z = (x - one_36) * one_36 / (x + one_36);
+    let z_squared = z * z / one_36;
+    let mut num = z;
+    let mut series_sum = num;
+    for n in [3, 5, 7, 9, 11, 13, 15] {
+        num = num * z_squared / one_36;
+        series_sum += num / int(n);
+    }
+    series_sum * int(2)
+}
+
+//------------------------------------- WeightedMath
+
+/// Maximum amount in, relative to the balance in, of a swap through a weighted pool.
+const MAX_IN_RATIO: u64 = 300_000_000_000_000_000;
+
+/// Returns the amount out of a weighted pool for `amount_in`, all upscaled to 18 decimals and net of the swap fee,
+/// as [WeightedMath._calcOutGivenIn](https://github.com/balancer-labs/balancer-v2-monorepo/blob/master/pkg/pool-weighted/contracts/WeightedMath.sol) does.
+pub fn calc_out_given_in(
+    balance_in: U256,
+    weight_in: U256,
+    balance_out: U256,
+    weight_out: U256,
+    amount_in: U256,
+) -> Result<U256, &'static str> {
+    if amount_in > mul_down(balance_in, U256::from(MAX_IN_RATIO))? {
+        return Err("BAL#304");
+    }
+    let denominator = add(balance_in, amount_in)?;
+    let base = div_up(balance_in, denominator)?;
+    let exponent = div_down(weight_in, weight_out)?;
+    let power = pow_up(base, exponent)?;
+    mul_down(balance_out, complement(power))
+}
+
+//------------------------------------- StableMath
+
+/// Precision of the amplification parameter of the stable pools.
+pub const AMP_PRECISION: u64 = 1_000;
+
+/// Maximum number of iterations of the Newton methods, as in the stable pools.
+const MAX_ITERATIONS: usize = 255;
+
+/// Returns the StableSwap invariant of `balances` upscaled to 18 decimals, with `amp` including `AMP_PRECISION`,
+/// as [StableMath._calculateInvariant](https://github.com/balancer-labs/balancer-v2-monorepo/blob/master/pkg/pool-stable/contracts/StableMath.sol) does.
+pub fn calculate_invariant(amp: U256, balances: &[U256]) -> Result<U256, &'static str> {
+    let n = U256::from(balances.len());
+    let sum = balances.iter().try_fold(U256::zero(), |sum, &balance| add(sum, balance))?;
+    if sum.is_zero() {
+        return Ok(U256::zero());
+    }
+    let amp_precision = U256::from(AMP_PRECISION);
+    let amp_times_total = mul(amp, n)?;
+
+    let mut invariant = sum;
+    for _ in 0..MAX_ITERATIONS {
+        let mut d_p = invariant;
+        for &balance in balances {
+            d_p = div_down_int(mul(d_p, invariant)?, mul(balance, n)?)?;
+        }
+        let prev_invariant = invariant;
+        let numerator = mul(add(mul(amp_times_total, sum)? / amp_precision, mul(d_p, n)?)?, invariant)?;
+        let denominator = add(mul(sub(amp_times_total, amp_precision)?, invariant)? / amp_precision, mul(n + 1, d_p)?)?;
+        invariant = div_down_int(numerator, denominator)?;
+        if abs_diff(invariant, prev_invariant) <= U256::one() {
+            return Ok(invariant);
+        }
+    }
+    Err("BAL#321")
+}
+
+/// Returns the balance of token `token_index` keeping `invariant` given the other `balances`.
+fn get_token_balance_given_invariant_and_all_other_balances(
+    amp: U256,
+    balances: &[U256],
+    invariant: U256,
+    token_index: usize,
+) -> Result<U256, &'static str> {
+    let n = U256::from(balances.len());
+    let amp_precision = U256::from(AMP_PRECISION);
+    let amp_times_total = mul(amp, n)?;
+
+    let mut sum = balances[0];
+    let mut p_d = mul(balances[0], n)?;
+    for &balance in &balances[1..] {
+        p_d = div_down_int(mul(mul(p_d, balance)?, n)?, invariant)?;
+        sum = add(sum, balance)?;
+    }
+    sum = sub(sum, balances[token_index])?;
+
+    let inv2 = mul(invariant, invariant)?;
+    let c = mul(mul(div_up_int(inv2, mul(amp_times_total, p_d)?)?, amp_precision)?, balances[token_index])?;
+    let b = add(sum, mul(div_down_int(invariant, amp_times_total)?, amp_precision)?)?;
+
+    let mut token_balance = div_up_int(add(inv2, c)?, add(invariant, b)?)?;
+    for _ in 0..MAX_ITERATIONS {
+        let prev_token_balance = token_balance;
+        token_balance = div_up_int(
+            add(mul(token_balance, token_balance)?, c)?,
+            sub(add(mul(token_balance, U256::from(2))?, b)?, invariant)?,
+        )?;
+        if abs_diff(token_balance, prev_token_balance) <= U256::one() {
+            return Ok(token_balance);
+        }
+    }
+    Err("BAL#322")
+}
+
+/// Returns the amount of token `j` out of a stable pool for `amount_in` of token `i`, all upscaled to 18 decimals and
+/// net of the swap fee, as `StableMath._calcOutGivenIn` does.
+pub fn calc_stable_out_given_in(
+    amp: U256,
+    balances: &[U256],
+    i: usize,
+    j: usize,
+    amount_in: U256,
+    invariant: U256,
+) -> Result<U256, &'static str> {
+    let mut balances = balances.to_vec();
+    balances[i] = add(balances[i], amount_in)?;
+    let final_balance_out = get_token_balance_given_invariant_and_all_other_balances(amp, &balances, invariant, j)?;
+    sub(sub(balances[j], final_balance_out)?, U256::one())
+}
+
+fn div_down_int(a: U256, b: U256) -> Result<U256, &'static str> {
+    a.checked_div(b).ok_or("BAL#004")
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b { a - b } else { b - a }
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed(x: f64) -> U256 {
+        U256::from((x * 1e18) as u128)
+    }
+
+    fn float(x: U256) -> f64 {
+        x.as_u128() as f64 / 1e18
+    }
+
+    #[test]
+    fn test_fixed_point_rounding() {
+        let third = div_down(one(), U256::from(3) * one()).unwrap();
+        assert_eq!(div_up(one(), U256::from(3) * one()).unwrap(), third + 1);
+        assert_eq!(mul_down(third, U256::from(3) * one()).unwrap() + 1, one());
+        assert_eq!(mul_up(U256::one(), U256::one()).unwrap(), U256::one());
+        assert_eq!(mul_down(U256::one(), U256::one()).unwrap(), U256::zero());
+        assert_eq!(complement(one() * 2), U256::zero());
+        assert_eq!(div_down(one(), U256::zero()), Err("BAL#004"));
+    }
+
+    #[test]
+    fn test_pow() {
+        for (x, y) in [(2., 0.5), (0.5, 3.), (1.05, 0.25), (0.95, 1.7), (10., 2.5), (1e-3, 0.1)] {
+            let result = float(pow(fixed(x), fixed(y)).unwrap());
+            let expected = f64::powf(x, y);
+            assert!(((result - expected) / expected).abs() < 1e-13, "{}^{}: {} != {}", x, y, result, expected);
+        }
+        // powUp always rounds above the exact value
+        let x = fixed(0.9);
+        let y = fixed(1.3);
+        assert!(pow_up(x, y).unwrap() > pow(x, y).unwrap());
+        assert_eq!(pow_up(x, one() * 2).unwrap(), mul_up(x, x).unwrap());
+        assert_eq!(pow(x, U256::zero()).unwrap(), one());
+    }
+
+    #[test]
+    fn test_exp_and_ln() {
+        assert!((float(exp(int(ONE_18)).unwrap().into_raw()) - std::f64::consts::E).abs() < 1e-15);
+        assert_eq!(exp(int(MAX_NATURAL_EXPONENT + 1)), Err("BAL#009"));
+        // ln of e^x gives x back
+        for x in [ONE_18 / 10, ONE_18 * 3, ONE_18 * 70] {
+            let result = ln(exp(int(x)).unwrap());
+            assert!((result - int(x)).abs() < int(1_000), "ln(e^{}) = {}", x, result);
+        }
+    }
+
+    #[test]
+    fn test_calc_out_given_in() {
+        // 80/20 pool with 1000 of token in and 1000 of token out
+        let balance = fixed(1000.);
+        let amount_in = fixed(10.);
+        let out = calc_out_given_in(balance, fixed(0.8), balance, fixed(0.2), amount_in).unwrap();
+        let expected = 1000. * (1. - f64::powf(1000. / 1010., 4.));
+        assert!((float(out) - expected).abs() < 1e-9);
+        // 50/50 pools are constant product pools
+        let out = calc_out_given_in(balance, fixed(0.5), balance, fixed(0.5), amount_in).unwrap();
+        assert!((float(out) - 1000. * 10. / 1010.).abs() < 1e-9);
+        // rounding always favors the pool
+        assert!(out <= balance * amount_in / (balance + amount_in));
+        assert_eq!(calc_out_given_in(balance, fixed(0.5), balance, fixed(0.5), fixed(301.)), Err("BAL#304"));
+    }
+
+    #[test]
+    fn test_calc_stable_out_given_in() {
+        let amp = U256::from(200 * AMP_PRECISION);
+        let balances = vec![fixed(1_000_000.), fixed(1_200_000.), fixed(800_000.)];
+        let invariant = calculate_invariant(amp, &balances).unwrap();
+        assert!(invariant < fixed(3_000_000.) && invariant > fixed(2_990_000.));
+
+        // cheaper to buy the scarcer token, still close to parity
+        let amount_in = fixed(1000.);
+        let out = calc_stable_out_given_in(amp, &balances, 1, 2, amount_in, invariant).unwrap();
+        assert!(out < amount_in && out > fixed(990.));
+        let out_back = calc_stable_out_given_in(amp, &balances, 2, 1, amount_in, invariant).unwrap();
+        assert!(out_back > amount_in);
+
+        // the invariant is kept, up to rounding in favor of the pool
+        let after = vec![balances[0], balances[1] + amount_in, balances[2] - out];
+        let invariant_after = calculate_invariant(amp, &after).unwrap();
+        assert!(invariant_after >= invariant && invariant_after - invariant < invariant / U256::exp10(18));
+    }
+}
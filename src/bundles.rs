@@ -0,0 +1,241 @@
+use ethers::{
+    signers::{ LocalWallet, Signer, },
+    types::{ Address, Bytes, U256, U64, H256, },
+    utils::keccak256,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{ json, Value, };
+
+use crate::error::{ BotError, BotResult, };
+
+/// Number of blocks after the current one targeted by each bundle, as a bundle is only valid for a single block.
+pub const BUNDLE_TARGET_BLOCKS: u64 = 2;
+
+/// Header holding the signature of the request body by the reputation key.
+pub const SIGNATURE_HEADER: &str = "X-Flashbots-Signature";
+
+//------------------------------------- BundleSimulation
+
+/// Outcome of a transaction of a bundle simulated with `eth_callBundle`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleTxResult {
+    pub tx_hash: H256,
+    pub gas_used: u64,
+    /// Error of a failed transaction, e.g. "execution reverted".
+    pub error: Option<String>,
+    /// Revert reason of a reverted transaction.
+    pub revert: Option<String>,
+}
+
+/// Outcome of a bundle simulated with `eth_callBundle`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleSimulation {
+    pub bundle_hash: H256,
+    pub results: Vec<BundleTxResult>,
+    pub total_gas_used: u64,
+    /// Payment to the block builder, in wei, as a decimal string.
+    pub coinbase_diff: String,
+}
+
+impl BundleSimulation {
+    /// Returns the reason of the first failed transaction, `None` if every transaction succeeded.
+    pub fn failure(&self) -> Option<String> {
+        self.results.iter().find_map(|result| {
+            result.error.as_ref().map(|error| match &result.revert {
+                Some(revert) => format!("{}: {}", error, revert),
+                None => error.clone(),
+            })
+        })
+    }
+
+    /// Payment to the block builder, in wei.
+    pub fn coinbase_diff(&self) -> U256 {
+        U256::from_dec_str(&self.coinbase_diff).unwrap_or_default()
+    }
+}
+
+//------------------------------------- BundleSubmitter
+
+/// Submits signed transactions in bundles to a Flashbots-compatible relay, so that they never reach the public mempool
+/// where they would be front-run, and are only included if none of them reverts.
+/// Requests are signed with a reputation key, distinct from the key of the transactions, which builds up the priority of the bot at the relay.
+#[derive(Debug, Clone)]
+pub struct BundleSubmitter {
+    pub relay_url: String,
+    reputation_key: LocalWallet,
+    client: reqwest::Client,
+    next_id: u64,
+}
+
+impl BundleSubmitter {
+    pub fn new(relay_url: String, reputation_key: LocalWallet) -> Self {
+        Self {
+            relay_url,
+            reputation_key,
+            client: reqwest::Client::new(),
+            next_id: 1,
+        }
+    }
+
+    /// Reads `RELAY_URL` and `RELAY_SIGNER_KEY` from .env, `None` if `RELAY_URL` is not set,
+    /// in which case the transactions are sent to the public mempool.
+    pub fn from_env() -> BotResult<Option<Self>> {
+        let relay_url = match dotenv::var("RELAY_URL") {
+            Ok(relay_url) => relay_url,
+            Err(_) => return Ok(None),
+        };
+        let reputation_key: LocalWallet = dotenv::var("RELAY_SIGNER_KEY")
+            .map_err(|_| BotError::Config("RELAY_SIGNER_KEY not found in .env, it is required along with RELAY_URL".to_string()))?
+            .parse()
+            .map_err(|_| BotError::Config("Invalid RELAY_SIGNER_KEY. Please check it does not begin with '0x'.".to_string()))?;
+        Ok(Some(Self::new(relay_url, reputation_key)))
+    }
+
+    /// Address of the reputation key.
+    pub fn reputation_address(&self) -> Address {
+        self.reputation_key.address()
+    }
+
+    /// Submits the signed transactions `txs` as a bundle for each of the `BUNDLE_TARGET_BLOCKS` blocks after block `block`,
+    /// and returns the bundle hashes.
+    pub async fn send_bundle(&mut self, txs: &[Bytes], block: U64) -> BotResult<Vec<H256>> {
+        let mut hashes: Vec<H256> = Vec::new();
+        for target in 1..=BUNDLE_TARGET_BLOCKS {
+            let params = json!([{
+                "txs": txs,
+                "blockNumber": block + target,
+            }]);
+            let result = self.request("eth_sendBundle", params).await?;
+            let hash = result
+                .get("bundleHash")
+                .cloned()
+                .and_then(|hash| serde_json::from_value::<H256>(hash).ok())
+                .ok_or_else(|| BotError::Decode(format!("`eth_sendBundle` result has no bundle hash: {}", result)))?;
+            hashes.push(hash);
+        }
+        Ok(hashes)
+    }
+
+    /// Simulates the signed transactions `txs` as a bundle included in the block after block `block`, on top of the state of block `block`.
+    pub async fn call_bundle(&mut self, txs: &[Bytes], block: U64) -> BotResult<BundleSimulation> {
+        let params = json!([{
+            "txs": txs,
+            "blockNumber": block + 1,
+            "stateBlockNumber": block,
+        }]);
+        let result = self.request("eth_callBundle", params).await?;
+        serde_json::from_value(result.clone())
+            .map_err(|e| BotError::Decode(format!("Failed to decode `eth_callBundle` result {}: {}", result, e)))
+    }
+
+    /// Sends the JSON-RPC request `method` to the relay, signed with the reputation key, and returns its result.
+    async fn request(&mut self, method: &str, params: Value) -> BotResult<Value> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": self.next_id,
+            "method": method,
+            "params": params,
+        })
+        .to_string();
+        self.next_id += 1;
+
+        let response: Value = self.client
+            .post(&self.relay_url)
+            .header("Content-Type", "application/json")
+            .header(SIGNATURE_HEADER, self.signature(&body).await?)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| BotError::Rpc(format!("`{}` request to the relay failed: {}", method, e)))?
+            .json()
+            .await
+            .map_err(|e| BotError::Decode(format!("`{}` response of the relay is not JSON: {}", method, e)))?;
+        if let Some(error) = response.get("error") {
+            return Err(BotError::Rpc(format!("`{}` rejected by the relay: {}", method, error)));
+        }
+        response
+            .get("result")
+            .cloned()
+            .ok_or_else(|| BotError::Decode(format!("`{}` response of the relay has no result: {}", method, response)))
+    }
+
+    /// Returns the value of the signature header of `body`: the address of the reputation key,
+    /// and its signature of the hex-encoded hash of `body` as an EIP-191 message.
+    async fn signature(&self, body: &str) -> BotResult<String> {
+        let message = format!("{:?}", H256::from(keccak256(body.as_bytes())));
+        let signature = self.reputation_key
+            .sign_message(message)
+            .await
+            .map_err(|e| BotError::Execution(format!("Failed to sign relay request: {}", e)))?;
+        Ok(format!("{:?}:0x{}", self.reputation_key.address(), signature))
+    }
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::Signature;
+    use std::str::FromStr;
+    use crate::testing::mock_rpc;
+
+    fn submitter(url: String) -> BundleSubmitter {
+        let key: LocalWallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318".parse().unwrap();
+        BundleSubmitter::new(url, key)
+    }
+
+    #[tokio::test]
+    async fn test_send_bundle() {
+        let (url, received) = mock_rpc(|body| Ok(json!({ "bundleHash": H256::from_low_u64_be(body["id"].as_u64().unwrap()) }))).await;
+        let mut bundles = submitter(url);
+        let txs = [Bytes::from(vec![2, 0xf8, 0x6a])];
+
+        let hashes = bundles.send_bundle(&txs, U64::from(100)).await.unwrap();
+        assert_eq!(hashes, [H256::from_low_u64_be(1), H256::from_low_u64_be(2)]);
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), BUNDLE_TARGET_BLOCKS as usize);
+        for (i, (signature, raw_body)) in received.iter().enumerate() {
+            let body: Value = serde_json::from_str(raw_body).unwrap();
+            assert_eq!(body["method"], "eth_sendBundle");
+            assert_eq!(body["params"][0]["txs"], json!(["0x02f86a"]));
+            assert_eq!(body["params"][0]["blockNumber"], json!(U64::from(101 + i)));
+
+            // the relay recovers the reputation key from the hash of the body
+            let (address, signature) = signature.split_once(':').unwrap();
+            let message = format!("{:?}", H256::from(keccak256(raw_body.as_bytes())));
+            let signer = Signature::from_str(signature).unwrap().recover(message).unwrap();
+            assert_eq!(signer, Address::from_str(address).unwrap());
+            assert_eq!(signer, bundles.reputation_address());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_call_bundle() {
+        let (url, received) = mock_rpc(|_| Ok(json!({
+            "bundleHash": H256::repeat_byte(1),
+            "coinbaseDiff": "2000000000000000",
+            "totalGasUsed": 190_000,
+            "results": [
+                { "txHash": H256::repeat_byte(2), "gasUsed": 21_000 },
+                { "txHash": H256::repeat_byte(3), "gasUsed": 169_000, "error": "execution reverted", "revert": "FAE: insufficient profit" },
+            ],
+        }))).await;
+        let mut bundles = submitter(url);
+
+        let simulation = bundles.call_bundle(&[Bytes::from(vec![1]), Bytes::from(vec![2])], U64::from(100)).await.unwrap();
+        assert_eq!(simulation.results.len(), 2);
+        assert_eq!(simulation.results[0].error, None);
+        assert_eq!(simulation.total_gas_used, 190_000);
+        assert_eq!(simulation.coinbase_diff(), U256::from(2) * U256::exp10(15));
+        assert_eq!(simulation.failure(), Some("execution reverted: FAE: insufficient profit".to_string()));
+
+        let body: Value = serde_json::from_str(&received.lock().unwrap()[0].1).unwrap();
+        assert_eq!(body["method"], "eth_callBundle");
+        assert_eq!(body["params"][0]["blockNumber"], json!(U64::from(101)));
+        assert_eq!(body["params"][0]["stateBlockNumber"], json!(U64::from(100)));
+    }
+}
@@ -0,0 +1,670 @@
+use ethers::{
+    middleware::SignerMiddleware,
+    providers::{Http, Provider, Middleware},
+    signers::LocalWallet,
+    types::{ Address, Bytes, U256, U64, H256, Log, },
+    contract::{ BaseContract, AbiError, },
+    abi::{ Tokenize, Detokenize, },
+    utils::keccak256,
+};
+use std::{
+    fs::File,
+    io::{Write, ErrorKind},
+    collections::HashMap,
+    any::Any,
+    sync::Arc,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::*;
+use crate::univ3::Token;
+use crate::multicall::Multicall;
+use crate::optimizer::Quote;
+use crate::pool::{ Pool, SwapPayment, };
+use crate::profitability::CURVE_SWAP_GAS;
+use crate::error::{ BotError, BotResult, };
+
+/// Denominator of `StableSwapSnapshot.fee`, as in the StableSwap contracts.
+pub const FEE_DENOMINATOR: u64 = 10_000_000_000;
+
+/// Precision of the rates normalizing the balances to 18 decimals.
+const PRECISION: u64 = 1_000_000_000_000_000_000;
+
+/// Maximum number of iterations of the Newton methods of `get_d` and `get_y`, as in the StableSwap contracts.
+const MAX_ITERATIONS: usize = 255;
+
+//------------------------------------- CurveImmutables
+
+/// StableSwap pool listed in the Curve Registry.
+#[derive(Debug, Clone, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CurveImmutables {
+    pub address: Address,
+    pub pool_id: usize,
+    /// Ids of the coins, in pool order.
+    pub token_ids: Vec<usize>,
+    /// LP token of the base pool of a meta pool, which is its last coin. `None` for plain pools.
+    pub base_lp_token: Option<Address>,
+}
+
+impl CurveImmutables {
+    pub fn new(address: Address, pool_id: usize, token_ids: Vec<usize>, base_lp_token: Option<Address>) -> Self {
+        Self {
+            address,
+            pool_id,
+            token_ids,
+            base_lp_token,
+        }
+    }
+
+    /// Reads the pools from `config/<chain id>/curvePools.json`.
+    /// If the file is not found, the pools between `tokens` are discovered on the registry of `curve_registry_addr`
+    /// and saved to it.
+    pub async fn get_pools(
+        chain_id: u32,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        tokens: &[Token],
+        chunk_size: usize,
+    ) -> BotResult<Vec<CurveImmutables>> {
+        let file_storing_pools = format!("config/{}/curvePools.json", &chain_id);
+
+        let file = match File::open(&file_storing_pools) {
+            Ok(f) => f,
+            Err(e) => match e.kind() {
+                ErrorKind::NotFound => {
+                    let registry = match curve_registry_addr(chain_id)? {
+                        Some(registry) => registry,
+                        None => return Ok(Vec::new()),
+                    };
+                    println!("{} not found, creating from Curve Registry", &file_storing_pools);
+                    let multicall = Multicall::new(chain_id, provider, chunk_size)?;
+                    let block = provider
+                        .get_block_number()
+                        .await
+                        .map_err(|e| BotError::Rpc(format!("`eth_blockNumber` asynchronous call failed: {}", e)))?;
+                    let mut pools = CurveImmutables::discover(registry, tokens, &multicall, block).await?;
+                    for (pool_id, pool) in pools.iter_mut().enumerate() {
+                        pool.pool_id = pool_id;
+                    }
+                    CurveImmutables::save_pools(&file_storing_pools, &pools)?;
+                    return Ok(pools);
+                },
+                _ => return Err(BotError::Config(format!("Failed to open file {}: {}", &file_storing_pools, e))),
+            },
+        };
+        serde_json::from_reader(file)
+            .map_err(|e| BotError::Config(format!("Failed to extract curve pools from json: {}", e)))
+    }
+
+    /// Finds the pools of `registry` whose coins are all in `tokens`, with a `pool_id` of 0.
+    /// Meta pools are only found if the LP token of their base pool is in `tokens`.
+    pub async fn discover(
+        registry: Address,
+        tokens: &[Token],
+        multicall: &Multicall<'_>,
+        block: U64,
+    ) -> BotResult<Vec<CurveImmutables>> {
+        let registry_abi = BaseContract::from(i_curve_registry_abi()?);
+        let encode_error = |name: &'static str| move |e: AbiError| BotError::Abi(format!("`{}()` method not found in ABI: {}", name, e));
+        let decode_error = |name: &'static str| move |e: AbiError| BotError::Decode(format!("Failed to decode `{}()` output: {}", name, e));
+        let reverted = |name: &'static str| BotError::Execution(format!("`{}()` call reverted", name));
+        let token_ids: HashMap<Address, usize> = tokens.iter().map(|token| (token.address, token.token_id)).collect();
+
+        let calls = [(registry, registry_abi.encode("pool_count", ()).map_err(encode_error("Registry.pool_count"))?)];
+        let data = multicall.aggregate(&calls, block).await?.remove(0).ok_or_else(|| reverted("Registry.pool_count"))?;
+        let pool_count: U256 = registry_abi
+            .decode_output("pool_count", data)
+            .map_err(decode_error("Registry.pool_count"))?;
+
+        let calls = (0..pool_count.as_usize())
+            .map(|i| Ok((registry, registry_abi.encode("pool_list", U256::from(i)).map_err(encode_error("Registry.pool_list"))?)))
+            .collect::<BotResult<Vec<(Address, Bytes)>>>()?;
+        let mut addresses: Vec<Address> = Vec::with_capacity(calls.len());
+        for data in multicall.aggregate(&calls, block).await? {
+            let data = data.ok_or_else(|| reverted("Registry.pool_list"))?;
+            addresses.push(registry_abi.decode_output("pool_list", data).map_err(decode_error("Registry.pool_list"))?);
+        }
+
+        // keep the pools between known tokens
+        let mut calls: Vec<(Address, Bytes)> = Vec::with_capacity(3 * addresses.len());
+        for &pool in &addresses {
+            calls.push((registry, registry_abi.encode("get_n_coins", pool).map_err(encode_error("Registry.get_n_coins"))?));
+            calls.push((registry, registry_abi.encode("get_coins", pool).map_err(encode_error("Registry.get_coins"))?));
+            calls.push((registry, registry_abi.encode("is_meta", pool).map_err(encode_error("Registry.is_meta"))?));
+        }
+        let returns = multicall.aggregate(&calls, block).await?;
+        let mut pools: Vec<CurveImmutables> = Vec::new();
+        for (&pool, data) in addresses.iter().zip(returns.chunks(3)) {
+            let n_coins: Vec<U256> = registry_abi
+                .decode_output("get_n_coins", data[0].as_ref().ok_or_else(|| reverted("Registry.get_n_coins"))?)
+                .map_err(decode_error("Registry.get_n_coins"))?;
+            let coins: Vec<Address> = registry_abi
+                .decode_output("get_coins", data[1].as_ref().ok_or_else(|| reverted("Registry.get_coins"))?)
+                .map_err(decode_error("Registry.get_coins"))?;
+            let is_meta: bool = registry_abi
+                .decode_output("is_meta", data[2].as_ref().ok_or_else(|| reverted("Registry.is_meta"))?)
+                .map_err(decode_error("Registry.is_meta"))?;
+            let coins = &coins[..n_coins[0].as_usize().min(coins.len())];
+            let ids: Option<Vec<usize>> = coins.iter().map(|coin| token_ids.get(coin).copied()).collect();
+            if let Some(ids) = ids.filter(|ids| ids.len() >= 2) {
+                let base_lp_token = if is_meta { coins.last().copied() } else { None };
+                pools.push(CurveImmutables::new(pool, 0, ids, base_lp_token));
+            }
+        }
+        Ok(pools)
+    }
+
+    /// Serializes `pools` to the json file at `path`.
+    fn save_pools(path: &str, pools: &[CurveImmutables]) -> BotResult<()> {
+        let serialized_pools = serde_json::to_string_pretty(pools)
+            .map_err(|e| BotError::Config(format!("Failed to serialize curve pools: {}", e)))?;
+        let mut pools_file = File::create(path)
+            .map_err(|e| BotError::Config(format!("Failed to create file {}: {}", path, e)))?;
+        pools_file
+            .write_all(serialized_pools.as_bytes())
+            .map_err(|e| BotError::Config(format!("Failed to write curve pools to file {}: {}", path, e)))
+    }
+}
+
+//------------------------------------- StableSwapSnapshot
+
+/// State of a StableSwap pool, enough to quote swaps between its coins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StableSwapSnapshot {
+    /// Balance of each coin, in raw token units.
+    pub balances: Vec<U256>,
+    /// Rate of each coin normalizing its balance to 18 decimals, times `PRECISION`.
+    pub rates: Vec<U256>,
+    /// Amplification coefficient `A`, without precision.
+    pub amp: U256,
+    /// Swap fee over `FEE_DENOMINATOR`.
+    pub fee: U256,
+}
+
+impl StableSwapSnapshot {
+    /// Returns the amount of coin `j` received for `amount_in` of coin `i`.
+    pub fn get_amount_out(&self, i: usize, j: usize, amount_in: U256) -> Result<U256, &'static str> {
+        get_dy(i, j, amount_in, &self.balances, &self.rates, self.amp, U256::one(), self.fee)
+    }
+}
+
+impl Quote for StableSwapSnapshot {
+    fn get_amount_out(&self, token_in: usize, token_out: usize, amount_in: U256) -> Result<U256, &'static str> {
+        StableSwapSnapshot::get_amount_out(self, token_in, token_out, amount_in)
+    }
+}
+
+//------------------------------------- CurvePool
+
+/// Curve StableSwap pool, made of its immutables and a snapshot of its state.
+/// The state is read from the registry, which exposes the same getters for every pool implementation.
+/// Pools are refetched whenever they emit an event instead of replaying the event,
+/// since the liquidity events do not carry the balances after the admin fee.
+#[derive(Debug, Clone)]
+pub struct CurvePool {
+    pub immutables: CurveImmutables,
+    /// `None` until the pool is first fetched.
+    pub snapshot: Option<StableSwapSnapshot>,
+    decimals: Vec<u8>,
+    registry: Address,
+    stale: bool,
+    /// `event_topics`, computed once since `apply_log` checks them for every log of the pool.
+    topics: Vec<H256>,
+    registry_abi: Arc<BaseContract>,
+    pool_abi: Arc<BaseContract>,
+}
+
+impl CurvePool {
+    pub fn new(
+        immutables: CurveImmutables,
+        tokens: &[Token],
+        registry: Address,
+        registry_abi: Arc<BaseContract>,
+        pool_abi: Arc<BaseContract>,
+    ) -> BotResult<Self> {
+        let mut topics = ["TokenExchange", "TokenExchangeUnderlying", "RampA", "StopRampA", "NewFee"]
+            .iter()
+            .map(|name| {
+                pool_abi
+                    .abi()
+                    .event(name)
+                    .map(|event| event.signature())
+                    .map_err(|e| BotError::Abi(format!("`StableSwap.{}` event not found in ABI: {}", name, e)))
+            })
+            .collect::<BotResult<Vec<H256>>>()?;
+        topics.extend(Self::liquidity_event_topics(immutables.token_ids.len()));
+        Ok(Self {
+            decimals: immutables.token_ids.iter().map(|&id| tokens[id].decimals).collect(),
+            immutables,
+            snapshot: None,
+            registry,
+            stale: true,
+            topics,
+            registry_abi,
+            pool_abi,
+        })
+    }
+
+    /// Returns the pools of `CurveImmutables::get_pools`, yet to be fetched.
+    /// No pools are loaded on networks without a Curve Registry.
+    pub async fn load(
+        chain_id: u32,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        tokens: &[Token],
+        chunk_size: usize,
+    ) -> BotResult<Vec<Self>> {
+        let registry = match curve_registry_addr(chain_id)? {
+            Some(registry) => registry,
+            None => return Ok(Vec::new()),
+        };
+        let registry_abi = Arc::new(BaseContract::from(i_curve_registry_abi()?));
+        let pool_abi = Arc::new(BaseContract::from(i_curve_pool_abi()?));
+        CurveImmutables::get_pools(chain_id, provider, tokens, chunk_size)
+            .await?
+            .into_iter()
+            .map(|immutables| CurvePool::new(immutables, tokens, registry, registry_abi.clone(), pool_abi.clone()))
+            .collect()
+    }
+
+    fn encode<T: Tokenize>(&self, name: &str, args: T) -> BotResult<(Address, Bytes)> {
+        let data = self.registry_abi
+            .encode(name, args)
+            .map_err(|e| BotError::Abi(format!("`Registry.{}()` method not found in ABI: {}", name, e)))?;
+        Ok((self.registry, data))
+    }
+
+    fn decode<D: Detokenize>(&self, name: &str, data: Option<&Option<Bytes>>) -> BotResult<D> {
+        let data = data
+            .cloned()
+            .flatten()
+            .ok_or_else(|| BotError::Execution(format!("`Registry.{}()` call reverted on pool {:?}", name, self.immutables.address)))?;
+        self.registry_abi
+            .decode_output(name, data)
+            .map_err(|e| BotError::Decode(format!("Failed to decode `Registry.{}()` output: {}", name, e)))
+    }
+
+    /// Signatures of the liquidity events, whose amounts are arrays sized by the number of coins `n`.
+    fn liquidity_event_topics(n: usize) -> Vec<H256> {
+        [
+            format!("AddLiquidity(address,uint256[{n}],uint256[{n}],uint256,uint256)", n = n),
+            format!("RemoveLiquidity(address,uint256[{n}],uint256[{n}],uint256)", n = n),
+            format!("RemoveLiquidityImbalance(address,uint256[{n}],uint256[{n}],uint256,uint256)", n = n),
+            "RemoveLiquidityOne(address,uint256,uint256)".to_string(),
+            "RemoveLiquidityOne(address,uint256,uint256,uint256)".to_string(),
+        ]
+        .iter()
+        .map(|signature| H256::from(keccak256(signature.as_bytes())))
+        .collect()
+    }
+}
+
+impl Quote for CurvePool {
+    fn get_amount_out(&self, token_in: usize, token_out: usize, amount_in: U256) -> Result<U256, &'static str> {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.get_amount_out(token_in, token_out, amount_in),
+            None => Err("Pool not fetched"),
+        }
+    }
+}
+
+impl Pool for CurvePool {
+    fn address(&self) -> Address {
+        self.immutables.address
+    }
+
+    fn tokens(&self) -> Vec<usize> {
+        self.immutables.token_ids.clone()
+    }
+
+    fn is_stale(&self) -> bool {
+        self.stale || self.snapshot.is_none()
+    }
+
+    fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    /// Fetches the balances, `A` and fee of the pool, and the virtual price of the base pool of a meta pool.
+    fn refresh_calls(&self) -> BotResult<Vec<(Address, Bytes)>> {
+        let pool = self.immutables.address;
+        let mut calls = vec![
+            self.encode("get_balances", pool)?,
+            self.encode("get_A", pool)?,
+            self.encode("get_fees", pool)?,
+        ];
+        if let Some(base_lp_token) = self.immutables.base_lp_token {
+            calls.push(self.encode("get_virtual_price_from_lp_token", base_lp_token)?);
+        }
+        Ok(calls)
+    }
+
+    fn apply_refresh(&mut self, returns: &[Option<Bytes>]) -> BotResult<()> {
+        let n = self.immutables.token_ids.len();
+        let balances: Vec<U256> = self.decode("get_balances", returns.first())?;
+        let amp: U256 = self.decode("get_A", returns.get(1))?;
+        let fees: Vec<U256> = self.decode("get_fees", returns.get(2))?;
+        // rates scale each balance to 18 decimals, times 1e18 as `StableSwap.RATES`
+        let mut rates: Vec<U256> = self.decimals
+            .iter()
+            .map(|&decimals| {
+                36usize
+                    .checked_sub(decimals as usize)
+                    .map(U256::exp10)
+                    .ok_or_else(|| BotError::Config(format!(
+                        "Token of {} decimals in curve pool {:?}, at most 36 supported", decimals, self.immutables.address,
+                    )))
+            })
+            .collect::<BotResult<Vec<U256>>>()?;
+        if self.immutables.base_lp_token.is_some() {
+            rates[n - 1] = self.decode("get_virtual_price_from_lp_token", returns.get(3))?;
+        }
+        self.snapshot = Some(StableSwapSnapshot {
+            balances: balances.into_iter().take(n).collect(),
+            rates,
+            amp,
+            fee: fees[0],
+        });
+        self.stale = false;
+        Ok(())
+    }
+
+    fn event_topics(&self) -> BotResult<Vec<H256>> {
+        Ok(self.topics.clone())
+    }
+
+    /// Marks the pool stale on any of its `event_topics`, to be refetched at the end of the block.
+    fn apply_log(&mut self, log: &Log) -> BotResult<bool> {
+        match log.topics.first() {
+            Some(topic) if self.topics.contains(topic) => {
+                self.invalidate();
+                Ok(true)
+            },
+            _ => Ok(false),
+        }
+    }
+
+    /// Quotes a swap of a millionth of the input balance, so the weight includes the fee and the curvature at the current balances.
+    fn log_price(&self, token_in: usize, token_out: usize) -> Option<f64> {
+        let snapshot = self.snapshot.as_ref()?;
+        let amount_in = (snapshot.balances[token_in] / 1_000_000).max(U256::one());
+        let amount_out = snapshot.get_amount_out(token_in, token_out, amount_in).ok()?;
+        if amount_out.is_zero() {
+            return None;
+        }
+        Some(amounts_to_log_price(amount_in, amount_out, self.decimals[token_in], self.decimals[token_out]))
+    }
+
+    /// `StableSwap.exchange()` of exactly `amount_in`, for at least `amount_out`.
+    /// The caller must approve the pool to spend `amount_in` beforehand.
+    fn swap_calldata(&self, token_in: usize, token_out: usize, amount_in: U256, amount_out: U256, _recipient: Address) -> BotResult<Bytes> {
+        self.pool_abi
+            .encode("exchange", (token_in as i128, token_out as i128, amount_in, amount_out))
+            .map_err(|e| BotError::Abi(format!("`StableSwap.exchange()` method not found in ABI: {}", e)))
+    }
+
+    fn swap_payment(&self) -> SwapPayment {
+        SwapPayment::Approval
+    }
+
+    fn swap_gas(&self, _token_in: usize, _token_out: usize, _amount_in: U256) -> u64 {
+        CURVE_SWAP_GAS
+    }
+
+    fn box_clone(&self) -> Box<dyn Pool> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+//------------------------------------- StableSwap math
+
+fn add(a: U256, b: U256) -> Result<U256, &'static str> {
+    a.checked_add(b).ok_or("Integer overflow")
+}
+
+fn mul(a: U256, b: U256) -> Result<U256, &'static str> {
+    a.checked_mul(b).ok_or("Integer overflow")
+}
+
+fn div(a: U256, b: U256) -> Result<U256, &'static str> {
+    a.checked_div(b).ok_or("Division by zero")
+}
+
+fn sub(a: U256, b: U256) -> Result<U256, &'static str> {
+    a.checked_sub(b).ok_or("Integer underflow")
+}
+
+fn abs_diff(a: U256, b: U256) -> U256 {
+    if a > b { a - b } else { b - a }
+}
+
+/// Returns the StableSwap invariant `D` of the normalized balances `xp`, as `StableSwap.get_D()` does.
+/// `amp` is `A` times `a_precision`, which is 1 for the pools predating `A_PRECISION` and 100 for the later ones.
+pub fn get_d(xp: &[U256], amp: U256, a_precision: U256) -> Result<U256, &'static str> {
+    let n = U256::from(xp.len());
+    let s = xp.iter().try_fold(U256::zero(), |s, &x| add(s, x))?;
+    if s.is_zero() {
+        return Ok(U256::zero());
+    }
+
+    let mut d = s;
+    let ann = mul(amp, n)?;
+    for _ in 0..MAX_ITERATIONS {
+        let mut d_p = d;
+        for &x in xp {
+            d_p = div(mul(d_p, d)?, mul(x, n)?)?;
+        }
+        let d_prev = d;
+        let numerator = mul(add(div(mul(ann, s)?, a_precision)?, mul(d_p, n)?)?, d)?;
+        let denominator = add(div(mul(sub(ann, a_precision)?, d)?, a_precision)?, mul(n + 1, d_p)?)?;
+        d = div(numerator, denominator)?;
+        if abs_diff(d, d_prev) <= U256::one() {
+            return Ok(d);
+        }
+    }
+    Err("D did not converge")
+}
+
+/// Returns the normalized balance of coin `j` keeping `D` unchanged when the normalized balance of coin `i` becomes `x`,
+/// as `StableSwap.get_y()` does.
+pub fn get_y(i: usize, j: usize, x: U256, xp: &[U256], amp: U256, a_precision: U256) -> Result<U256, &'static str> {
+    if i == j || i >= xp.len() || j >= xp.len() {
+        return Err("Invalid coin index");
+    }
+    let n = U256::from(xp.len());
+    let d = get_d(xp, amp, a_precision)?;
+    let ann = mul(amp, n)?;
+
+    let mut c = d;
+    let mut s = U256::zero();
+    for (k, &xp_k) in xp.iter().enumerate() {
+        let x_k = if k == i {
+            x
+        } else if k != j {
+            xp_k
+        } else {
+            continue;
+        };
+        s = add(s, x_k)?;
+        c = div(mul(c, d)?, mul(x_k, n)?)?;
+    }
+    c = div(mul(mul(c, d)?, a_precision)?, mul(ann, n)?)?;
+    let b = add(s, div(mul(d, a_precision)?, ann)?)?;
+
+    let mut y = d;
+    for _ in 0..MAX_ITERATIONS {
+        let y_prev = y;
+        y = div(add(mul(y, y)?, c)?, sub(add(mul(U256::from(2), y)?, b)?, d)?)?;
+        if abs_diff(y, y_prev) <= U256::one() {
+            return Ok(y);
+        }
+    }
+    Err("y did not converge")
+}
+
+/// Returns the amount of coin `j` received for `dx` of coin `i`, net of the fee, as `StableSwap.get_dy()` does.
+/// `balances` are in raw token units, `rates` normalize them to 18 decimals times `PRECISION`
+/// and `fee` is over `FEE_DENOMINATOR`.
+#[allow(clippy::too_many_arguments)]
+pub fn get_dy(
+    i: usize,
+    j: usize,
+    dx: U256,
+    balances: &[U256],
+    rates: &[U256],
+    amp: U256,
+    a_precision: U256,
+    fee: U256,
+) -> Result<U256, &'static str> {
+    if i >= balances.len() || j >= balances.len() {
+        return Err("Invalid coin index");
+    }
+    let precision = U256::from(PRECISION);
+    let xp = balances
+        .iter()
+        .zip(rates)
+        .map(|(&balance, &rate)| Ok(mul(rate, balance)? / precision))
+        .collect::<Result<Vec<U256>, &'static str>>()?;
+
+    let x = add(xp[i], mul(dx, rates[i])? / precision)?;
+    let y = get_y(i, j, x, &xp, amp, a_precision)?;
+    let dy = div(mul(sub(sub(xp[j], y)?, U256::one())?, precision)?, rates[j])?;
+    let fee = mul(fee, dy)? / U256::from(FEE_DENOMINATOR);
+    sub(dy, fee)
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::tokens;
+
+    /// Balanced 3pool with 100M of each coin, DAI, USDC and USDT.
+    fn three_pool() -> StableSwapSnapshot {
+        let decimals = [18, 6, 6];
+        StableSwapSnapshot {
+            balances: decimals.iter().map(|&d| U256::from(100_000_000u64) * U256::exp10(d)).collect(),
+            rates: decimals.iter().map(|&d| U256::exp10(36 - d)).collect(),
+            amp: U256::from(2000),
+            fee: U256::from(1_000_000),
+        }
+    }
+
+    #[test]
+    fn test_get_d() {
+        // balanced pools hold D in their sum
+        let xp = vec![U256::exp10(24); 3];
+        assert_eq!(get_d(&xp, U256::from(100), U256::one()).unwrap(), U256::exp10(24) * 3);
+        // same invariant with the A_PRECISION of the later pools
+        assert_eq!(
+            get_d(&xp, U256::from(100), U256::one()),
+            get_d(&xp, U256::from(10_000), U256::from(100)),
+        );
+        // imbalance lowers D below the sum, all the more as A is low
+        let xp = vec![U256::exp10(24), U256::exp10(24) * 2];
+        let d_low = get_d(&xp, U256::from(10), U256::one()).unwrap();
+        let d_high = get_d(&xp, U256::from(1000), U256::one()).unwrap();
+        assert!(d_low < d_high && d_high < U256::exp10(24) * 3);
+        assert_eq!(get_d(&[U256::zero(), U256::zero()], U256::from(100), U256::one()), Ok(U256::zero()));
+    }
+
+    #[test]
+    fn test_get_y() {
+        let xp = vec![U256::exp10(24), U256::exp10(24) * 2, U256::exp10(24) * 3];
+        let amp = U256::from(100);
+        // unchanged balance gives back the other balance
+        let y = get_y(0, 2, xp[0], &xp, amp, U256::one()).unwrap();
+        assert!(abs_diff(y, xp[2]) <= U256::from(2));
+        // D is preserved after the swap
+        let x = xp[0] + U256::exp10(22);
+        let y = get_y(0, 2, x, &xp, amp, U256::one()).unwrap();
+        let d = get_d(&xp, amp, U256::one()).unwrap();
+        let d_after = get_d(&[x, xp[1], y], amp, U256::one()).unwrap();
+        assert!(abs_diff(d_after, d) <= U256::from(2));
+        assert_eq!(get_y(1, 1, x, &xp, amp, U256::one()), Err("Invalid coin index"));
+    }
+
+    #[test]
+    fn test_get_dy() {
+        let pool = three_pool();
+        // 1000 DAI for close to 1000 USDC minus the 0.01% fee
+        let dy = pool.get_amount_out(0, 1, U256::from(1000) * U256::exp10(18)).unwrap();
+        let expected = U256::from(999_900_000u64);
+        assert!(dy <= expected && expected - dy < U256::from(1000));
+        // same amounts in the other direction, once normalized
+        let dy_back = pool.get_amount_out(1, 0, U256::from(1000) * U256::exp10(6)).unwrap();
+        assert!(abs_diff(dy_back / U256::exp10(12), dy) < U256::from(1000));
+        // the rate worsens with size
+        let small = pool.get_amount_out(1, 2, U256::exp10(6)).unwrap();
+        let large = pool.get_amount_out(1, 2, U256::exp10(6 + 8)).unwrap() / U256::exp10(8);
+        assert!(large < small);
+        // the output never drains the pool
+        assert!(pool.get_amount_out(1, 2, U256::exp10(6 + 12)).unwrap() < pool.balances[2]);
+        assert_eq!(pool.get_amount_out(0, 3, U256::one()), Err("Invalid coin index"));
+    }
+
+    #[test]
+    fn test_curve_pool_refresh() {
+        let tokens = tokens(&[18, 6, 6]);
+        let registry_abi = Arc::new(BaseContract::from(i_curve_registry_abi().unwrap()));
+        let pool_abi = Arc::new(BaseContract::from(i_curve_pool_abi().unwrap()));
+        let immutables = CurveImmutables::new(Address::repeat_byte(1), 0, vec![0, 1, 2], None);
+        let mut pool = CurvePool::new(immutables, &tokens, Address::repeat_byte(9), registry_abi, pool_abi.clone()).unwrap();
+        let uints = |values: Vec<U256>| Some(Bytes::from(ethers::abi::encode(&[ethers::abi::Token::FixedArray(
+            values.into_iter().map(ethers::abi::Token::Uint).collect(),
+        )])));
+
+        // refreshed in a single round of registry calls
+        assert!(pool.is_stale());
+        let calls = pool.refresh_calls().unwrap();
+        assert_eq!(calls.len(), 3);
+        assert!(calls.iter().all(|(target, _)| *target == Address::repeat_byte(9)));
+        let expected = three_pool();
+        let mut balances = expected.balances.clone();
+        balances.resize(8, U256::zero());
+        pool.apply_refresh(&[
+            uints(balances),
+            Some(Bytes::from(ethers::abi::encode(&[ethers::abi::Token::Uint(expected.amp)]))),
+            uints(vec![expected.fee, U256::from(5_000_000_000u64)]),
+        ]).unwrap();
+        assert!(!pool.is_stale());
+        assert_eq!(pool.snapshot, Some(expected));
+
+        // close to parity in both directions, net of the fee
+        let weight = pool.log_price(0, 1).unwrap();
+        assert!(weight > 0. && weight < 2e-4);
+        assert!(pool.log_price(2, 0).unwrap() > 0.);
+
+        // any event marks the pool for refresh
+        let log = Log {
+            address: pool.address(),
+            topics: vec![pool.event_topics().unwrap()[0]],
+            ..Default::default()
+        };
+        assert!(pool.apply_log(&log).unwrap());
+        assert!(pool.is_stale());
+        let other = Log { topics: vec![H256::zero()], ..log };
+        assert!(!pool.apply_log(&other).unwrap());
+
+        let calldata = pool.swap_calldata(2, 0, U256::from(10), U256::from(9), Address::zero()).unwrap();
+        let (i, j, dx, min_dy): (i128, i128, U256, U256) = pool_abi.decode("exchange", calldata).unwrap();
+        assert_eq!((i, j, dx, min_dy), (2, 0, U256::from(10), U256::from(9)));
+
+        // a token of more than 36 decimals has no rate
+        let tokens = crate::testing::tokens(&[18, 40]);
+        let immutables = CurveImmutables::new(Address::repeat_byte(2), 1, vec![0, 1], None);
+        let registry_abi = Arc::new(BaseContract::from(i_curve_registry_abi().unwrap()));
+        let mut pool = CurvePool::new(immutables, &tokens, Address::repeat_byte(9), registry_abi, pool_abi).unwrap();
+        let result = pool.apply_refresh(&[
+            uints(vec![U256::one(); 8]),
+            Some(Bytes::from(ethers::abi::encode(&[ethers::abi::Token::Uint(U256::from(100))]))),
+            uints(vec![U256::zero(), U256::zero()]),
+        ]);
+        assert!(matches!(result, Err(BotError::Config(_))));
+        assert!(pool.is_stale());
+    }
+}
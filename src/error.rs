@@ -0,0 +1,54 @@
+use thiserror::Error;
+
+//------------------------------------- BotError
+
+/// Errors surfaced by the bot.
+/// `Config` and `Abi` errors come from the bot's own settings and files and are fatal,
+/// the others depend on the node or on the chain state and may not happen again on the next block.
+#[derive(Debug, Error)]
+pub enum BotError {
+    /// Invalid CLI argument, .env variable or config file.
+    #[error("config error: {0}")]
+    Config(String),
+    /// Missing or malformed ABI, or ABI lacking a function or an event.
+    #[error("ABI error: {0}")]
+    Abi(String),
+    /// Failed request to the node.
+    #[error("RPC error: {0}")]
+    Rpc(String),
+    /// Call output or log not matching the ABI.
+    #[error("decoding error: {0}")]
+    Decode(String),
+    /// Pool math failure, along with the reason the pool contract would revert with.
+    #[error("math error: {0}")]
+    Math(String),
+    /// Reverted call or transaction.
+    #[error("execution error: {0}")]
+    Execution(String),
+}
+
+pub type BotResult<T> = Result<T, BotError>;
+
+impl BotError {
+    /// Whether the run loop should carry on with the next block after this error.
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, BotError::Config(_) | BotError::Abi(_))
+    }
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(!BotError::Config("".to_string()).is_retryable());
+        assert!(!BotError::Abi("".to_string()).is_retryable());
+        assert!(BotError::Rpc("".to_string()).is_retryable());
+        assert!(BotError::Decode("".to_string()).is_retryable());
+        assert!(BotError::Math("LS".to_string()).is_retryable());
+        assert!(BotError::Execution("".to_string()).is_retryable());
+    }
+}
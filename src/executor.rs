@@ -0,0 +1,487 @@
+use ethers::{
+    middleware::SignerMiddleware,
+    providers::{Http, Provider, Middleware, ProviderError, HttpClientError, },
+    signers::LocalWallet,
+    types::{ Address, Bytes, U256, BlockNumber, TransactionRequest, transaction::eip2718::TypedTransaction, },
+    contract::BaseContract,
+    abi::{ ParamType, Token as AbiToken, },
+    utils::hex,
+};
+use serde_json::{ json, Value, };
+
+use crate::utils::*;
+use crate::graph::Edge;
+use crate::univ3::Token;
+use crate::optimizer::CycleTrade;
+use crate::pool::Pool;
+use crate::profitability::{ Loan, cycle_loan, };
+use crate::error::{ BotError, BotResult, };
+
+/// `Swap` struct of the executor contract: payment, target, token in, amount in and calldata.
+pub type ExecutorSwap = (u8, Address, Address, U256, Bytes);
+
+/// Selector of `Error(string)`, the revert reason of `require()`.
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// Selector of `Panic(uint256)`, raised by failed assertions and arithmetic errors.
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+//------------------------------------- Simulation
+
+/// Outcome of a cycle simulated with `eth_call` on the pending block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Simulation {
+    /// The cycle succeeds, increasing the executor's balance of the start token by this profit.
+    Profit(U256),
+    /// The cycle reverts with this decoded reason.
+    Reverted(String),
+}
+
+//------------------------------------- Executor
+
+/// Encodes and simulates the calls of a deployed `FlashArbExecutor` contract (see `contracts/`),
+/// which borrows the start token, swaps it through the cycle and reverts unless the profit is high enough.
+/// The calls are sent by `TxManager`.
+#[derive(Debug, Clone)]
+pub struct Executor {
+    pub address: Address,
+    executor_abi: BaseContract,
+}
+
+impl Executor {
+    pub fn new(address: Address) -> BotResult<Self> {
+        Ok(Self {
+            address,
+            executor_abi: BaseContract::from(i_executor_abi()?),
+        })
+    }
+
+    /// Reads `EXECUTOR_ADDRESS` from .env, `None` if it is not set, in which case the cycles are only reported.
+    pub fn from_env() -> BotResult<Option<Self>> {
+        match dotenv::var("EXECUTOR_ADDRESS") {
+            Ok(address) => {
+                let address = address
+                    .parse::<Address>()
+                    .map_err(|_| BotError::Config(format!("EXECUTOR_ADDRESS must be an address, got {}.", address)))?;
+                Ok(Some(Self::new(address)?))
+            },
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Returns the hops of `cycle`, a cycle of `Graph::bellman_ford_cycles` whose edge ids are indices in `pools`,
+    /// swapping the amounts of `trade` with the executor as recipient.
+    pub fn swaps(
+        &self,
+        pools: &[Box<dyn Pool>],
+        tokens: &[Token],
+        cycle: &[(usize, Edge)],
+        trade: &CycleTrade,
+    ) -> BotResult<Vec<ExecutorSwap>> {
+        if trade.amounts.len() != cycle.len() + 1 {
+            return Err(BotError::Execution(format!("Trade of {} amounts does not fit a cycle of {} hops", trade.amounts.len(), cycle.len())));
+        }
+        cycle
+            .iter()
+            .enumerate()
+            .map(|(i, &(from, Edge { to, edge_id: pool_id, .. }))| {
+                let pool = &pools[pool_id];
+                let token_ids = pool.tokens();
+                let coin = |token_id: usize| token_ids
+                    .iter()
+                    .position(|&id| id == token_id)
+                    .ok_or_else(|| BotError::Execution(format!("Token {} is not a coin of pool {}", token_id, pool_id)));
+                let data = pool.swap_calldata(coin(from)?, coin(to)?, trade.amounts[i], trade.amounts[i + 1], self.address)?;
+                Ok((pool.swap_payment() as u8, pool.swap_target(), tokens[from].address, trade.amounts[i], data))
+            })
+            .collect()
+    }
+
+    /// Returns the pool lending `amount` of the start token of `cycle` with `flash()`, the zero address to flash swap on the first hop,
+    /// as chosen by `cycle_loan`.
+    pub fn loan_pool(&self, pools: &[Box<dyn Pool>], cycle: &[(usize, Edge)], amount: U256) -> BotResult<Address> {
+        match cycle_loan(pools, cycle, amount) {
+            Some(Loan::FlashSwap) => Ok(Address::zero()),
+            Some(Loan::Flash { pool_id, .. }) => Ok(pools[pool_id].address()),
+            None => Err(match cycle.first() {
+                Some((start, _)) => BotError::Execution(format!("No pool can lend token {} to the cycle", start)),
+                None => BotError::Execution("Empty cycle".to_string()),
+            }),
+        }
+    }
+
+    /// Returns the calldata of `FlashArbExecutor.execute()` swapping `trade` through `cycle`,
+    /// reverting unless the start token balance grows by at least `min_profit`.
+    pub fn calldata(
+        &self,
+        pools: &[Box<dyn Pool>],
+        tokens: &[Token],
+        cycle: &[(usize, Edge)],
+        trade: &CycleTrade,
+        min_profit: U256,
+    ) -> BotResult<Bytes> {
+        let loan_pool = self.loan_pool(pools, cycle, trade.amount_in())?;
+        let swaps = self.swaps(pools, tokens, cycle, trade)?;
+        self.executor_abi
+            .encode("execute", (loan_pool, min_profit, swaps))
+            .map_err(|e| BotError::Abi(format!("`FlashArbExecutor.execute()` method not found in ABI: {}", e)))
+    }
+
+    /// Returns the start token of the cycle executed by `calldata`, the calldata of `FlashArbExecutor.execute()`.
+    pub fn start_token(&self, calldata: &Bytes) -> Option<Address> {
+        let (_, _, swaps): (Address, U256, Vec<ExecutorSwap>) = self.executor_abi.decode("execute", calldata).ok()?;
+        swaps.first().map(|swap| swap.2)
+    }
+
+    /// Simulates the call of the executor with `calldata` from the bot's account with `eth_call` on block `block`,
+    /// the pending block for a new cycle, so that the state changes between the detection of the cycle and its execution are accounted for.
+    /// If `trace` is set, a reverting call is traced with `debug_traceCall` to name the call it failed in.
+    pub async fn simulate(
+        &self,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        calldata: Bytes,
+        block: BlockNumber,
+        trace: bool,
+    ) -> BotResult<Simulation> {
+        let tx: TypedTransaction = TransactionRequest::new()
+            .from(provider.address())
+            .to(self.address)
+            .data(calldata)
+            .into();
+        let error = match provider.inner().call(&tx, Some(block.into())).await {
+            Ok(data) => {
+                let profit: U256 = self.executor_abi
+                    .decode_output("execute", data)
+                    .map_err(|e| BotError::Decode(format!("Failed to decode `FlashArbExecutor.execute()` output: {}", e)))?;
+                return Ok(Simulation::Profit(profit));
+            },
+            Err(e) => e,
+        };
+        let data = revert_data(&error)
+            .ok_or_else(|| BotError::Rpc(format!("`FlashArbExecutor.execute()` simulation failed: {}", error)))?;
+        let mut reason = decode_revert_reason(&data);
+        if trace {
+            let params = (&tx, block, json!({ "tracer": "callTracer" }));
+            match provider.inner().request::<_, Value>("debug_traceCall", params).await {
+                Ok(trace) => if let Some(call) = failing_call(&trace) {
+                    reason = format!("{} ({})", reason, call);
+                },
+                Err(e) => println!("failed to trace simulation: {}", e),
+            }
+        }
+        Ok(Simulation::Reverted(reason))
+    }
+}
+
+//------------------------------------- revert reasons
+
+/// Returns the revert data carried by the error of an `eth_call` that reverted, `None` if the call failed otherwise.
+/// Nodes omitting the data of reverts without a reason are reported with empty data.
+pub fn revert_data(error: &ProviderError) -> Option<Bytes> {
+    let error = match error {
+        ProviderError::JsonRpcClientError(e) => match e.downcast_ref::<HttpClientError>()? {
+            HttpClientError::JsonRpcError(e) => e,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    match error.data.as_ref().and_then(Value::as_str).and_then(|data| data.parse::<Bytes>().ok()) {
+        Some(data) => Some(data),
+        None if error.message.contains("revert") => Some(Bytes::default()),
+        None => None,
+    }
+}
+
+/// Decodes the revert data of a call: the message of `Error(string)`, the code of `Panic(uint256)`,
+/// or the raw data of a custom error.
+pub fn decode_revert_reason(data: &[u8]) -> String {
+    if data.is_empty() {
+        return "reverted without reason".to_string();
+    }
+    let decoded = |param: ParamType| ethers::abi::decode(&[param], &data[4..]).ok().and_then(|tokens| tokens.into_iter().next());
+    match data.get(..4) {
+        Some(selector) if selector == ERROR_SELECTOR => {
+            if let Some(AbiToken::String(reason)) = decoded(ParamType::String) {
+                return reason;
+            }
+        },
+        Some(selector) if selector == PANIC_SELECTOR => {
+            if let Some(AbiToken::Uint(code)) = decoded(ParamType::Uint(256)) {
+                return format!("panic 0x{:02x}", code);
+            }
+        },
+        _ => {},
+    }
+    format!("custom error 0x{}", hex::encode(data))
+}
+
+/// Returns the innermost failed call of a `callTracer` trace, as its target and error.
+pub fn failing_call(trace: &Value) -> Option<String> {
+    trace.get("error")?;
+    let inner = trace
+        .get("calls")
+        .and_then(Value::as_array)
+        .and_then(|calls| calls.iter().rev().find_map(failing_call));
+    inner.or_else(|| {
+        let to = trace.get("to").and_then(Value::as_str).unwrap_or("unknown");
+        let error = trace.get("revertReason").or_else(|| trace.get("error")).and_then(Value::as_str).unwrap_or("");
+        Some(format!("call to {} failed: {}", to, error))
+    })
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::I256;
+    use std::sync::Arc;
+    use crate::testing::{ tokens, univ2_pool, univ3_pool, };
+    use crate::optimizer::{ Hop, optimize_cycle, };
+    use ethers::{
+        abi::{ Abi, Tokenize, parse_abi, },
+        contract::{ Contract, ContractFactory, },
+        utils::Anvil,
+    };
+
+    /// V2 pairs 0-1 and 1-2, then V3 pools 0-2 and 0-1.
+    fn pools() -> Vec<Box<dyn Pool>> {
+        let tokens = tokens(&[18; 3]);
+        let pair = |id: usize, token_0_id: usize, token_1_id: usize| -> Box<dyn Pool> {
+            Box::new(univ2_pool(Address::repeat_byte(0x10 + id as u8), id, (token_0_id, token_1_id), None, &tokens))
+        };
+        let pool = |id: usize, token_0_id: usize, token_1_id: usize| -> Box<dyn Pool> {
+            Box::new(univ3_pool(Address::repeat_byte(0x10 + id as u8), id, (token_0_id, token_1_id), 500., &tokens))
+        };
+        vec![pair(0, 0, 1), pair(1, 1, 2), pool(2, 0, 2), pool(3, 0, 1)]
+    }
+
+    fn cycle(pool_ids: [usize; 3]) -> Vec<(usize, Edge)> {
+        vec![
+            (0, Edge::new(1, 0., pool_ids[0])),
+            (1, Edge::new(2, 0., pool_ids[1])),
+            (2, Edge::new(0, 0., pool_ids[2])),
+        ]
+    }
+
+    fn trade() -> CycleTrade {
+        CycleTrade {
+            amounts: vec![U256::from(1000), U256::from(990), U256::from(985), U256::from(1010)],
+            profit: I256::from(10),
+        }
+    }
+
+    fn decode(executor: &Executor, calldata: Bytes) -> (Address, U256, Vec<ExecutorSwap>) {
+        executor.executor_abi.decode("execute", calldata).unwrap()
+    }
+
+    #[test]
+    fn test_flash_swap_calldata() {
+        let executor = Executor::new(Address::repeat_byte(0xee)).unwrap();
+        let (pools, tokens) = (pools(), tokens(&[18; 3]));
+        // starts with the V3 pool 0-1, the output of which funds the V2 hops
+        let cycle = cycle([3, 1, 2]);
+        let calldata = executor.calldata(&pools, &tokens, &cycle, &trade(), U256::one()).unwrap();
+        let (loan_pool, min_profit, swaps) = decode(&executor, calldata);
+        assert_eq!(loan_pool, Address::zero());
+        assert_eq!(min_profit, U256::one());
+        assert_eq!(swaps.len(), 3);
+
+        let amounts = trade().amounts;
+        for (i, (payment, target, token_in, amount_in, data)) in swaps.into_iter().enumerate() {
+            let (from, edge) = cycle[i];
+            let pool = &pools[edge.edge_id];
+            assert_eq!(payment, pool.swap_payment() as u8);
+            assert_eq!(target, pool.address());
+            assert_eq!(token_in, tokens[from].address);
+            assert_eq!(amount_in, amounts[i]);
+            let coin = |token_id| pool.tokens().iter().position(|&id| id == token_id).unwrap();
+            assert_eq!(data, pool.swap_calldata(coin(from), coin(edge.to), amounts[i], amounts[i + 1], executor.address).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_decode_revert_reason() {
+        let error = |reason: &str| [&ERROR_SELECTOR[..], &ethers::abi::encode(&[AbiToken::String(reason.to_string())])].concat();
+        assert_eq!(decode_revert_reason(&error("FAE: insufficient profit")), "FAE: insufficient profit");
+        let panic = [&PANIC_SELECTOR[..], &ethers::abi::encode(&[AbiToken::Uint(U256::from(0x11))])].concat();
+        assert_eq!(decode_revert_reason(&panic), "panic 0x11");
+        assert_eq!(decode_revert_reason(&[]), "reverted without reason");
+        assert_eq!(decode_revert_reason(&[0x12, 0x34, 0x56, 0x78]), "custom error 0x12345678");
+
+        // revert data of the node's error
+        let rpc_error = |error: Value| ProviderError::JsonRpcClientError(Box::new(
+            HttpClientError::JsonRpcError(serde_json::from_value(error).unwrap()),
+        ));
+        let data = Bytes::from(error("LOK"));
+        assert_eq!(revert_data(&rpc_error(json!({ "code": 3, "message": "execution reverted: LOK", "data": data }))), Some(data));
+        assert_eq!(revert_data(&rpc_error(json!({ "code": -32000, "message": "execution reverted" }))), Some(Bytes::default()));
+        assert_eq!(revert_data(&rpc_error(json!({ "code": -32000, "message": "header not found" }))), None);
+    }
+
+    #[test]
+    fn test_failing_call() {
+        let trace = json!({
+            "to": "0xee",
+            "error": "execution reverted",
+            "calls": [
+                { "to": "0x10" },
+                {
+                    "to": "0x11",
+                    "error": "execution reverted",
+                    "calls": [{ "to": "0x12", "error": "execution reverted", "revertReason": "UniswapV2: K" }],
+                },
+            ],
+        });
+        assert_eq!(failing_call(&trace), Some("call to 0x12 failed: UniswapV2: K".to_string()));
+        assert_eq!(failing_call(&json!({ "to": "0xee" })), None);
+    }
+
+    #[test]
+    fn test_flash_loan_calldata() {
+        let executor = Executor::new(Address::repeat_byte(0xee)).unwrap();
+        let (pools, tokens) = (pools(), tokens(&[18; 3]));
+
+        // starts with a V2 pair, token 0 is lent by the first V3 pool outside of the cycle
+        let calldata = executor.calldata(&pools, &tokens, &cycle([0, 1, 2]), &trade(), U256::zero()).unwrap();
+        assert_eq!(decode(&executor, calldata).0, pools[3].address());
+
+        // no V3 pool outside of the cycle holds token 0
+        let mut cycle = cycle([0, 1, 2]);
+        cycle.push((0, Edge::new(0, 0., 3)));
+        assert!(matches!(executor.loan_pool(&pools, &cycle, U256::from(1000)), Err(BotError::Execution(_))));
+
+        // amounts not fitting the cycle
+        assert!(matches!(executor.swaps(&pools, &tokens, &cycle, &trade()), Err(BotError::Execution(_))));
+    }
+
+    /// Returns the external functions and public getters of `FlashArbExecutor` in `source`, as `name(inputs)(outputs)`
+    /// with the types as written in the source.
+    fn source_functions(source: &str) -> Vec<String> {
+        let types = |params: &str| params
+            .split(',')
+            .filter_map(|param| param.split_whitespace().next())
+            .collect::<Vec<&str>>()
+            .join(",");
+        let contract = &source[source.find("contract FlashArbExecutor").unwrap()..];
+        let mut functions: Vec<String> = contract
+            .split("function ")
+            .skip(1)
+            .map(|function| &function[..function.find(['{', ';']).unwrap()])
+            .filter(|header| header.contains(" external") || header.contains(" public"))
+            .map(|header| {
+                let (name, rest) = header.split_once('(').unwrap();
+                let (inputs, rest) = rest.split_once(')').unwrap();
+                let outputs = rest.split_once("returns (").map(|(_, outputs)| outputs.split_once(')').unwrap().0).unwrap_or("");
+                format!("{}({})({})", name, types(inputs), types(outputs))
+            })
+            .collect();
+        functions.extend(contract
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with("function") && line.contains(" public ") && line.ends_with(';'))
+            .map(|line| {
+                let mut words = line.trim_end_matches(';').split_whitespace();
+                let kind = words.next().unwrap();
+                format!("{}()({})", words.last().unwrap(), kind)
+            }));
+        functions.sort();
+        functions
+    }
+
+    /// The committed ABI is the one of the current source, i.e. it was regenerated after the last change of the interface.
+    #[test]
+    fn test_executor_abi_matches_source() {
+        let source = std::fs::read_to_string("contracts/FlashArbExecutor.sol").unwrap();
+        let types = |params: &[ethers::abi::Param]| params
+            .iter()
+            .map(|param| param.internal_type.clone().unwrap().replace("struct FlashArbExecutor.", ""))
+            .collect::<Vec<String>>()
+            .join(",");
+        let mut functions: Vec<String> = i_executor_abi()
+            .unwrap()
+            .functions()
+            .map(|function| format!("{}({})({})", function.name, types(&function.inputs), types(&function.outputs)))
+            .collect();
+        functions.sort();
+        assert_eq!(functions, source_functions(&source));
+        assert!(functions.contains(&"execute(address,uint256,Swap[])(uint256)".to_string()));
+    }
+
+    type Client = SignerMiddleware<Provider<Http>, LocalWallet>;
+
+    /// Deploys the contract compiled by `contracts/build.sh` into `path` with `args`.
+    async fn deploy<T: Tokenize>(client: &Arc<Client>, path: &str, abi: Abi, args: T) -> Contract<Client> {
+        let bytecode = std::fs::read_to_string(path).unwrap_or_else(|_| panic!("{} not found, run contracts/build.sh", path));
+        let bytecode = Bytes::from(hex::decode(bytecode.trim()).unwrap());
+        ContractFactory::new(abi, bytecode, client.clone()).deploy(args).unwrap().send().await.unwrap()
+    }
+
+    /// Runs a cycle through the executor deployed on a local chain, between two mock V2 pairs, borrowing from a mock lender.
+    #[tokio::test]
+    #[ignore = "needs anvil and the bytecode of contracts/build.sh"]
+    async fn test_execute_on_anvil() {
+        let anvil = Anvil::new().spawn();
+        let wallet = LocalWallet::from(anvil.keys()[0].clone());
+        let provider = Provider::<Http>::try_from(anvil.endpoint()).unwrap().interval(std::time::Duration::from_millis(10));
+        let client = Arc::new(SignerMiddleware::new(provider, wallet));
+
+        let token_abi = parse_abi(&[
+            "function mint(address to, uint256 amount)",
+            "function balanceOf(address account) view returns (uint256)",
+        ]).unwrap();
+        let pair_abi = parse_abi(&["constructor(address token0, address token1)", "function sync()"]).unwrap();
+        let lender_abi = parse_abi(&["constructor(address token0, address token1, uint24 fee)"]).unwrap();
+        let token_0 = deploy(&client, "contracts/test/out/MockToken.bin", token_abi.clone(), ()).await;
+        let token_1 = deploy(&client, "contracts/test/out/MockToken.bin", token_abi, ()).await;
+        let mint = |token: &Contract<Client>, to: Address, amount: u128| token.method::<_, ()>("mint", (to, U256::from(amount))).unwrap();
+
+        // token 0 is 20% more expensive in the second pair
+        let reserves = [(1_000_000u128 * 10u128.pow(18), 1_000_000u128 * 10u128.pow(18)), (1_000_000 * 10u128.pow(18), 1_200_000 * 10u128.pow(18))];
+        let mut pair_addresses: Vec<Address> = Vec::new();
+        for (reserve_0, reserve_1) in reserves {
+            let pair = deploy(&client, "contracts/test/out/MockPair.bin", pair_abi.clone(), (token_0.address(), token_1.address())).await;
+            mint(&token_0, pair.address(), reserve_0).send().await.unwrap().await.unwrap();
+            mint(&token_1, pair.address(), reserve_1).send().await.unwrap().await.unwrap();
+            pair.method::<_, ()>("sync", ()).unwrap().send().await.unwrap().await.unwrap();
+            pair_addresses.push(pair.address());
+        }
+        let lender = deploy(&client, "contracts/test/out/MockFlashLender.bin", lender_abi, (token_0.address(), token_1.address(), 500u32)).await;
+        mint(&token_0, lender.address(), 10u128.pow(24)).send().await.unwrap().await.unwrap();
+        let executor_contract = deploy(&client, "config/executor/FlashArbExecutor.bin", i_executor_abi().unwrap(), ()).await;
+        let executor = Executor::new(executor_contract.address()).unwrap();
+
+        // the bot's view of the pairs and of the lender, a V3 pool outside of the cycle
+        let mut tokens = tokens(&[18; 2]);
+        tokens[0].address = token_0.address();
+        tokens[1].address = token_1.address();
+        let mut pools: Vec<Box<dyn Pool>> = pair_addresses
+            .iter()
+            .zip(reserves)
+            .enumerate()
+            .map(|(pool_id, (&address, reserves))| Box::new(univ2_pool(address, pool_id, (0, 1), Some(reserves), &tokens)) as Box<dyn Pool>)
+            .collect();
+        pools.push(Box::new(univ3_pool(lender.address(), 2, (0, 1), 500., &tokens)));
+
+        // sells token 0 in the second pair and buys it back in the first one
+        let cycle = [(0, Edge::new(1, 0., 1)), (1, Edge::new(0, 0., 0))];
+        let hops = [Hop::new(&*pools[1], 0, 1), Hop::new(&*pools[0], 1, 0)];
+        let trade = optimize_cycle(&hops, U256::zero(), U256::from(u128::MAX)).unwrap();
+        let loan_fee = cycle_loan(&pools, &cycle, trade.amount_in()).unwrap().fee();
+        assert_eq!(loan_fee, (trade.amount_in() * 500 + 999_999) / 1_000_000);
+        let profit = trade.profit.into_raw() - loan_fee;
+
+        // asking for more than the cycle makes it revert
+        let calldata = executor.calldata(&pools, &tokens, &cycle, &trade, profit + 1).unwrap();
+        let simulation = executor.simulate(&client, calldata, BlockNumber::Pending, false).await.unwrap();
+        assert_eq!(simulation, Simulation::Reverted("FAE: insufficient profit".to_string()));
+
+        let calldata = executor.calldata(&pools, &tokens, &cycle, &trade, profit).unwrap();
+        assert_eq!(executor.simulate(&client, calldata.clone(), BlockNumber::Pending, false).await.unwrap(), Simulation::Profit(profit));
+        let tx = TransactionRequest::new().to(executor.address).data(calldata).gas(1_000_000);
+        let receipt = client.send_transaction(tx, None).await.unwrap().await.unwrap().unwrap();
+        assert_eq!(receipt.status, Some(1.into()));
+        let balance: U256 = token_0.method("balanceOf", executor.address).unwrap().call().await.unwrap();
+        assert_eq!(balance, profit);
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 //------------------------------------- Edge
 
 #[derive(Debug, Clone, Copy)]
@@ -51,7 +53,7 @@ impl Graph {
         // for each node apply relaxation for all the edges
         for _ in 1..n {
         for (from, edges) in self.nodes() {
-        for &Edge { to, weight, edge_id } in edges {
+        for &Edge { to, weight, edge_id: _ } in edges {
             let new_weight = dists[from] + weight;
             if new_weight < dists[to] {
                 dists[to] = new_weight;
@@ -61,7 +63,7 @@ impl Graph {
         // if can still be relaxed => negative cycle
         for _ in 1..n {
         for (from, edges) in self.nodes() {
-        for &Edge { to, weight, edge_id } in edges {
+        for &Edge { to, weight, edge_id: _ } in edges {
             if dists[from] + weight < dists[to] {
                 dists[to] = f64::NEG_INFINITY;
             }
@@ -70,56 +72,91 @@ impl Graph {
         dists
     }
 
-    pub fn bellman_ford_cycles(&self, start: usize) -> Vec<Vec<(usize, usize)>> {
+    /// Returns the weight of the lightest path of at most `max_hops` edges from `start` to each node,
+    /// infinity for the nodes it does not reach. Well defined even when the graph has negative cycles.
+    pub fn bounded_distances(&self, start: usize, max_hops: usize) -> Vec<f64> {
+        let mut dists = vec![f64::INFINITY; self.node_count()];
+        dists[start] = 0.;
+        for _ in 0..max_hops {
+            // relax from the distances of the previous round only, so that each round adds at most one edge
+            let prev = dists.clone();
+            for (from, edges) in self.nodes() {
+            for &Edge { to, weight, edge_id: _ } in edges {
+                let new_weight = prev[from] + weight;
+                if new_weight < dists[to] {
+                    dists[to] = new_weight;
+                }
+            }}
+        }
+        dists
+    }
+
+    /// Returns the negative cycles reachable from `start`.
+    /// Each cycle is the ordered list of its edges along with the node they leave from,
+    /// rotated so that the smallest `(from, edge_id)` comes first. Each cycle is returned once.
+    pub fn bellman_ford_cycles(&self, start: usize) -> Vec<Vec<(usize, Edge)>> {
         // initialize the distance to all nodes to infinity except start node
         let n = self.node_count();
         let mut dists = vec![f64::INFINITY; n];
         dists[start] = 0.;
 
-        // edge to get to previous node in negative cycle
-        let mut prev: Vec<Option<(usize, usize)>> = vec![None; n];
-        let mut negative_cycles: Vec<Vec<(usize, usize)>> = Vec::new();
+        // edge used to get to each node on its shortest path, with the node it leaves from
+        let mut prev: Vec<Option<(usize, Edge)>> = vec![None; n];
+        let mut negative_cycles: Vec<Vec<(usize, Edge)>> = Vec::new();
+        let mut found: HashSet<Vec<(usize, usize)>> = HashSet::new();
 
         // for each node apply relaxation for all the edges
         for _ in 1..n {
         for (from, edges) in self.nodes() {
-        for &Edge { to, weight, edge_id } in edges {
-            let new_weight = dists[from] + weight;
-            if new_weight < dists[to] {
-                dists[to] = new_weight;
-                prev[to] = Some((from, edge_id));
+        for &edge in edges {
+            let new_weight = dists[from] + edge.weight;
+            if new_weight < dists[edge.to] {
+                dists[edge.to] = new_weight;
+                prev[edge.to] = Some((from, edge));
             }
         }}}
 
         // if can still be relaxed => negative cycle
         for (from, edges) in self.nodes() {
-        for &Edge { to, weight, edge_id } in edges {
-            if dists[from] + weight < dists[to] {
-                dists[to] = f64::NEG_INFINITY;
-                let mut cycle: Vec<(usize, usize)> = Vec::new();
-                cycle.push((to, edge_id)); cycle.push((from, edge_id));
-                let mut curr = from;
-                let mut nodes_count = 2;
+        for &edge in edges {
+            if dists[from] + edge.weight < dists[edge.to] {
+                dists[edge.to] = dists[from] + edge.weight;
+                prev[edge.to] = Some((from, edge));
+
+                // walk back n times to land inside the cycle
+                let mut node = Some(edge.to);
+                for _ in 0..n {
+                    node = node.and_then(|v| prev[v].map(|(u, _)| u));
+                }
+                let start = match node {
+                    Some(v) => v,
+                    None => continue,
+                };
+
+                // trace the cycle exactly once
+                let mut cycle: Vec<(usize, Edge)> = Vec::new();
+                let mut node = start;
                 loop {
-                    match prev[curr] {
-                        Some(n) => {
-                            if cycle.contains(&n) || nodes_count > 10 {
-                                cycle.push(n);
-                                cycle.reverse();
-                                negative_cycles.push(cycle);
-                                break;
-                            }
-                            cycle.push(n);
-                            curr = n.0;
-                            nodes_count += 1;
-                        },
-                        None => {
-                            cycle.reverse();
-                            negative_cycles.push(cycle);
-                            break;
-                        },
+                    let (u, e) = prev[node].expect("Node inside a cycle has a predecessor");
+                    cycle.push((u, e));
+                    node = u;
+                    if node == start {
+                        break;
                     }
                 }
+                cycle.reverse();
+
+                // rotate to a canonical start to de-duplicate
+                let first = (0..cycle.len())
+                    .min_by_key(|&i| (cycle[i].0, cycle[i].1.edge_id))
+                    .expect("Cycle is not empty");
+                cycle.rotate_left(first);
+
+                let weight: f64 = cycle.iter().map(|(_, e)| e.weight).sum();
+                let key: Vec<(usize, usize)> = cycle.iter().map(|&(u, e)| (u, e.edge_id)).collect();
+                if weight < 0. && found.insert(key) {
+                    negative_cycles.push(cycle);
+                }
             }
         }}
 
@@ -132,6 +169,7 @@ impl Graph {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::fee_to_log_weight;
 
     #[test]
     fn test_bellman_ford_1() {
@@ -208,10 +246,101 @@ mod tests {
 
         let cycles = graph.bellman_ford_cycles(0);
 
-        assert!(cycles.len() > 0);
+        assert!(!cycles.is_empty());
         for c in &cycles {
-            println!("{:?}", c);
+            assert_cycle(&graph, c);
         }
-        assert!(cycles.contains(&vec![(3, 0), (4, 0), (0, 0), (3, 0)]));
+        let nodes: Vec<Vec<usize>> = cycles
+            .iter()
+            .map(|c| c.iter().map(|&(from, _)| from).collect())
+            .collect();
+        assert!(nodes.contains(&vec![0, 3, 4]));
     }
-}
\ No newline at end of file
+
+    /// Asserts that `cycle` is a closed walk over edges of `graph` visiting each node once with a negative weight.
+    fn assert_cycle(graph: &Graph, cycle: &[(usize, Edge)]) {
+        assert!(!cycle.is_empty());
+        for (i, &(from, edge)) in cycle.iter().enumerate() {
+            let (next_from, _) = cycle[(i + 1) % cycle.len()];
+            assert_eq!(edge.to, next_from);
+            assert!(graph.inner[from]
+                .iter()
+                .any(|e| e.to == edge.to && e.edge_id == edge.edge_id && e.weight == edge.weight));
+        }
+        let mut nodes: Vec<usize> = cycle.iter().map(|&(from, _)| from).collect();
+        nodes.sort_unstable();
+        nodes.dedup();
+        assert_eq!(nodes.len(), cycle.len());
+        assert!(cycle.iter().map(|(_, e)| e.weight).sum::<f64>() < 0.);
+    }
+
+    /// Minimal xorshift generator so the property tests are reproducible without extra dependencies.
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn weight(&mut self) -> f64 {
+            (self.next() % 2000) as f64 / 1000. - 0.8
+        }
+    }
+
+    #[test]
+    fn test_bellman_ford_cycles_properties() {
+        let mut rng = XorShift(0x2545f4914f6cdd1d);
+        for _ in 0..500 {
+            let n = 2 + rng.below(8);
+            let mut graph = Graph::new(n);
+            let edges = rng.below(3 * n);
+            for edge_id in 0..edges {
+                graph.add_edge(rng.below(n), rng.below(n), rng.weight(), edge_id);
+            }
+
+            let cycles = graph.bellman_ford_cycles(0);
+            for cycle in &cycles {
+                assert_cycle(&graph, cycle);
+            }
+
+            // no cycle is returned twice, whatever its rotation
+            let mut keys: Vec<Vec<(usize, usize)>> = cycles
+                .iter()
+                .map(|c| {
+                    let mut key: Vec<(usize, usize)> = c.iter().map(|&(from, e)| (from, e.edge_id)).collect();
+                    key.sort_unstable();
+                    key
+                })
+                .collect();
+            let count = keys.len();
+            keys.sort();
+            keys.dedup();
+            assert_eq!(keys.len(), count);
+
+            // a cycle is found whenever a node reachable from the start has an unbounded distance
+            let has_negative_cycle = graph.bellman_ford(0).contains(&f64::NEG_INFINITY);
+            assert_eq!(has_negative_cycle, !cycles.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_bellman_ford_cycle_fees() {
+        // 0.1% round trip edge at mid price, which does not survive two 0.05% fees
+        let build = |fee: f32| {
+            let mut graph = Graph::new(2);
+            graph.add_edge(0, 1, -f64::ln(2.) + fee_to_log_weight(fee), 0);
+            graph.add_edge(1, 0, -f64::ln(0.5005) + fee_to_log_weight(fee), 1);
+            graph
+        };
+
+        assert!(!build(0.).bellman_ford_cycles(0).is_empty());
+        assert!(build(500.).bellman_ford_cycles(0).is_empty());
+    }
+}
@@ -0,0 +1,25 @@
+pub mod error;
+pub mod utils;
+pub mod graph;
+pub mod univ3;
+pub mod univ2;
+pub mod curve;
+pub mod balancer;
+pub mod algebra;
+pub mod univ3_math;
+pub mod balancer_math;
+pub mod optimizer;
+pub mod pool;
+pub mod multicall;
+pub mod executor;
+pub mod profitability;
+pub mod transactions;
+pub mod nonce;
+pub mod bundles;
+pub mod paper;
+pub mod pipeline;
+pub mod archive;
+pub mod tokens;
+pub mod backtest;
+#[cfg(test)]
+pub mod testing;
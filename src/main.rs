@@ -1,23 +1,45 @@
 use ethers::{
     middleware::SignerMiddleware,
-    providers::{Http, Provider},
+    providers::{Http, Provider, Middleware},
     signers::{LocalWallet, },
-    contract::Contract,
-    abi::Abi,
-    types::U256
+    types::{ Address, Bytes, U256, U64, H256, BlockNumber, },
 };
 use std::convert::TryFrom;
 use dotenv::dotenv;
 use std::env;
 
-mod utils; use utils::*;
-mod graph; use graph::*;
-mod univ3; use univ3::*;
+use bot::{
+    utils::*,
+    univ3::*,
+    pool::*,
+    multicall::*,
+    executor::*,
+    profitability::*,
+    transactions::*,
+    pipeline::*,
+    archive::*,
+    backtest::*,
+    tokens::*,
+    bundles::*,
+    paper::*,
+    error::*,
+};
 
 pub struct Bot {
+    chain_id: u32,
+    config: RunConfig,
     provider: SignerMiddleware<Provider<Http>, LocalWallet>,
     tokens: Vec<Token>,
-    pool_immutables: Vec<PoolImmutables>,
+    tracker: PoolTracker,
+    /// Id of the wrapped native token, in which gas is priced. `None` if it is not among `tokens`.
+    native: Option<usize>,
+    /// `None` to only report the cycles.
+    executor: Option<Executor>,
+    tx_manager: TxManager,
+    /// `None` to send the transactions to the public mempool.
+    bundles: Option<BundleSubmitter>,
+    /// `Some` in paper mode, where the cycles are executed on paper instead of being sent.
+    paper: Option<PaperLedger>,
 }
 
 impl Bot {
@@ -25,92 +47,411 @@ impl Bot {
         chain_id: u32,
         secret_key: String,
         provider_url: String,
-    ) -> Self {
+        config: RunConfig,
+        paper: bool,
+    ) -> BotResult<Self> {
         println!("\n-------------------- create bot instance");
-        println!("creating local wallet...");
-        let wallet: LocalWallet = secret_key.parse().expect("Invalid secret key. Please check it does not begin with '0x'.");
-        println!("creating provider...");
-        let provider_service = Provider::<Http>::try_from(provider_url).expect("Invalid provider url.");
-        let provider: SignerMiddleware<Provider<Http>, LocalWallet> = SignerMiddleware::new(provider_service, wallet);
+        let provider = create_provider(secret_key, provider_url)?;
 
         println!("getting tokens config...");
-        let tokens = Token::get_tokens(chain_id);
-        println!("getting pool immutables config...");
-        let pool_immutables = PoolImmutables::get_pool_immutables(chain_id, &provider).await;
+        let tokens = Token::get_tokens(chain_id)?;
+        let mut tracker = PoolTracker::new();
+        for pool in load_pools(chain_id, &provider, &tokens, config.multicall_chunk_size).await? {
+            tracker.insert(pool);
+        }
+
+        let native = native_token(chain_id, &tokens)?;
+        let (executor, bundles, paper) = if paper {
+            println!("paper mode, cycles are executed on paper and settled on the next block");
+            (None, None, Some(PaperLedger::new(tokens.len())))
+        } else {
+            let executor = Executor::from_env()?;
+            match &executor {
+                Some(executor) => println!("sending cycles to executor {:?}", executor.address),
+                None => println!("EXECUTOR_ADDRESS not set, cycles are only reported"),
+            }
+            let bundles = BundleSubmitter::from_env()?;
+            match &bundles {
+                Some(bundles) => println!("sending transactions in bundles to relay {} signed by {:?}", bundles.relay_url, bundles.reputation_address()),
+                None => println!("RELAY_URL not set, transactions are sent to the public mempool"),
+            }
+            (executor, bundles, None)
+        };
 
-        Self {
+        let mut tx_manager = TxManager::new(config.priority_fee, provider.address());
+        tx_manager.records_path = Some(dotenv::var("TX_RECORDS_PATH").unwrap_or_else(|_| format!("transactions_{}.jsonl", chain_id)));
+        Ok(Self {
+            chain_id,
+            config,
             provider,
             tokens,
-            pool_immutables,
+            tracker,
+            native,
+            executor,
+            tx_manager,
+            bundles,
+            paper,
+        })
+    }
+
+    /// Runs `execute` once for every new block until a shutdown signal (ctrl-c) is received.
+    /// Iterations lasting longer than `config.iteration_timeout` are abandoned.
+    /// Iterations failing with a retryable error are skipped, the others stop the bot.
+    pub async fn run(&mut self) -> BotResult<()> {
+        let config = self.config;
+        println!("\n--------------------- run bot");
+        let shutdown = tokio::signal::ctrl_c();
+        tokio::pin!(shutdown);
+        let mut poll = tokio::time::interval(config.poll_interval);
+        poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let mut last_block: Option<U64> = None;
+
+        loop {
+            // wait for the next poll or a shutdown signal
+            tokio::select! {
+                _ = &mut shutdown => break,
+                _ = poll.tick() => {},
+            }
+
+            let block = match self.provider.get_block_number().await {
+                Ok(block) => block,
+                Err(e) => {
+                    println!("failed to fetch block number: {}", e);
+                    continue;
+                },
+            };
+            if last_block == Some(block) {
+                continue;
+            }
+            last_block = Some(block);
+
+            tokio::select! {
+                _ = &mut shutdown => break,
+                res = tokio::time::timeout(config.iteration_timeout, self.execute(block)) => match res {
+                    Ok(Ok(())) => {},
+                    Ok(Err(e)) if e.is_retryable() => println!("iteration for block {} failed, skipped: {}", block, e),
+                    Ok(Err(e)) => return Err(e),
+                    Err(_) => println!("iteration for block {} took longer than {:?}, skipped", block, config.iteration_timeout),
+                },
+            }
         }
+        println!("\n--------------------- shutdown signal received, stopping bot");
+        if let Some(ledger) = &self.paper {
+            self.report_paper(ledger);
+        }
+        self.report_transactions();
+        Ok(())
     }
 
-    pub async fn execute(&self) {
-        println!("\n--------------------- execute bot");
+    /// Detects and sizes arbitrage cycles on the pool states at block number `block`, and sends the profitable ones to the executor.
+    /// Only the cycles going through a pool that changed since the previous block are reported.
+    pub async fn execute(&mut self, block: U64) -> BotResult<()> {
+        println!("\n--------------------- execute bot (block {})", block);
+        let multicall = Multicall::new(self.chain_id, &self.provider, self.config.multicall_chunk_size)?;
+
+        // bring pool states to the block
+        self.tracker.update(&self.provider, &multicall, block).await?;
 
-        // create pool contracts
-        println!("creating pool contracts...");
-        let mut pool_contracts = Vec::with_capacity(self.pool_immutables.len());
-        let pool_abi: Abi = i_univ3_pool_abi();
-        for pool in &self.pool_immutables {
-            pool_contracts.push(Contract::new(pool.address, pool_abi.clone(), &self.provider));
+        // follow the transactions sent in the previous blocks
+        let fees = self.tx_manager.fees(&self.provider, block).await?;
+        if let Some(executor) = &self.executor {
+            self.tx_manager.on_block(&self.provider, executor, block, fees).await?;
+        }
+        if self.bundles.is_some() {
+            // bundled transactions never reach the mempool, the nonce only moves when one is included
+            self.tx_manager.nonces.sync(&self.provider).await?;
         }
 
-        loop {
-            // create graph instance
-            let mut graph = Graph::new(self.tokens.len());
-
-            // fetch pool state, convert sqrtPriceX96 to token0Price/token1Price, add edge to graph
-            for (contract, immutables) in pool_contracts.iter().zip(&self.pool_immutables) {
-                // fetch pool state
-                let (sqrt_price_x_96, _, _, _, _, _, _):
-                    (U256, i32, u16, u16, u16, u8, bool) = contract
-                    .method::<(), (U256, i32, u16, u16, u16, u8, bool)>("slot0", ())
-                    .expect("`UniswapV3Pool.slot0()` not found in ABI. Incorrect ABI.")
-                    .call()
-                    .await
-                    .expect("`UniswapV3Pool.slot0()` asynchronous call failed.");
-                // convert sqrtPriceX96 to log price
-                let (p0, p1) = sqrtPriceX86_to_log_price(
-                    sqrt_price_x_96,
-                    self.tokens[immutables.token_0_id].decimals,
-                    self.tokens[immutables.token_1_id].decimals,
-                );
-                // add edge to graph
-                graph.add_edge(
-                    immutables.token_0_id, 
-                    immutables.token_1_id,
-                    p0,
-                    immutables.pool_id,
-                );
-                graph.add_edge(
-                    immutables.token_1_id, 
-                    immutables.token_0_id,
-                    p1,
-                    immutables.pool_id,
+        // settle the cycles executed on paper in the previous blocks
+        if let Some(ledger) = &mut self.paper {
+            for record in ledger.settle(&self.tracker.pools, block) {
+                println!(
+                    "paper trade of block {} settled: {} of token {}, expected profit {}, realized profit {}{}",
+                    record.block, record.amount_in, record.start, record.expected_profit, record.realized_profit,
+                    if record.reverted { " (reverted)" } else { "" },
                 );
             }
+        }
+
+        let dirty = self.tracker.take_dirty();
+        if dirty.is_empty() {
+            println!("no pool changed");
+            return Ok(());
+        }
+        println!("{} pools changed", dirty.len());
+
+        // add an edge between every two coins of every pool, weighted by log price net of the pool fee
+        let pools = &self.tracker.pools;
+        let graph = build_graph(pools, self.tokens.len());
 
-            // execute bellman ford
-            let res = graph.bellman_ford_cycles(0);
-            for el in res {
-                println!("{:?}", el);
+        // execute bellman ford from the tokens of the pools that changed
+        let cycles = find_cycles(&graph, pools, &dirty);
+
+        // price gas in every token through the graph
+        let pricer = GasPricer::new(&graph, &self.tokens, self.native, fees.gas_price());
+
+        // size the trade through each cycle on the pools' liquidity
+        for cycle in cycles {
+            println!("{:?}", cycle);
+            let SizedCycle { trade, gas, gas_cost, loan_fee } = match size_cycle(pools, &self.tokens, &cycle, &pricer) {
+                Ok(sized) => sized,
+                Err(reason) => {
+                    println!("cycle dropped, {}", reason);
+                    continue;
+                },
+            };
+            let start = &self.tokens[cycle[0].0];
+
+            // the priority fee of `ProfitShare` is a share of the profit, so the gas is priced again once it is known
+            let profit_wei = pricer.native_value(trade.profit.into_raw(), start).unwrap_or_default();
+            let tx_fees = self.tx_manager.profit_share_fee(fees, profit_wei, gas);
+            let (trade, gas_cost) = match pricer.gas_cost_at(gas, tx_fees.gas_price(), start) {
+                Some(tx_gas_cost) => match net_of_gas(trade, tx_gas_cost.saturating_sub(gas_cost)) {
+                    Some(trade) => (trade, tx_gas_cost),
+                    None => {
+                        println!("cycle dropped, {} gas at a priority fee of {} costs more than the profit", gas, tx_fees.priority_fee);
+                        continue;
+                    },
+                },
+                None => {
+                    println!("cycle dropped, gas cannot be priced in token {}", start.token_id);
+                    continue;
+                },
+            };
+            println!("swap {} of token {} for a profit of {} net of {} gas", trade.amount_in(), start.token_id, trade.profit, gas);
+
+            if let Some(ledger) = &mut self.paper {
+                let opportunity = opportunity(&cycle);
+                if ledger.is_pending(&opportunity) {
+                    println!("cycle already being executed on paper");
+                } else {
+                    ledger.execute(pools, &cycle, &trade, gas_cost, loan_fee, block);
+                }
+                continue;
             }
 
-            break;
+            // the executor reverts unless the cycle covers its gas and makes at least `min_profit_bps` of the expected profit
+            if let Some(executor) = &self.executor {
+                let opportunity = opportunity(&cycle);
+                if self.tx_manager.is_pending(&opportunity) {
+                    println!("cycle already being executed");
+                    continue;
+                }
+                let min_profit = gas_cost + trade.profit.into_raw() * U256::from(self.config.min_profit_bps) / U256::from(10_000);
+                let calldata = match executor.calldata(pools, &self.tokens, &cycle, &trade, min_profit.max(U256::one())) {
+                    Ok(calldata) => calldata,
+                    Err(e) => {
+                        println!("cycle dropped, {}", e);
+                        continue;
+                    },
+                };
+                match executor.simulate(&self.provider, calldata.clone(), BlockNumber::Pending, self.config.trace_simulations).await {
+                    Ok(Simulation::Profit(profit)) => println!("simulated profit of {}", profit),
+                    Ok(Simulation::Reverted(reason)) => {
+                        println!("cycle dropped, simulation reverted: {}", reason);
+                        continue;
+                    },
+                    Err(e) => {
+                        println!("cycle dropped, simulation failed: {}", e);
+                        continue;
+                    },
+                }
+                match &mut self.bundles {
+                    Some(bundles) => match send_bundle(&mut self.tx_manager, bundles, &self.provider, executor.address, calldata, tx_fees, block).await {
+                        Ok(hashes) => println!("cycle sent in bundles {:?}", hashes),
+                        Err(e) => println!("cycle execution failed: {}", e),
+                    },
+                    None => match self.tx_manager.send(&self.provider, executor.address, calldata, tx_fees, opportunity, block).await {
+                        Ok(hash) => println!("cycle sent in transaction {:?}", hash),
+                        Err(e) => println!("cycle execution failed: {}", e),
+                    },
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints the number of mined transactions, the gas they paid and their realized profits, by start token.
+    fn report_transactions(&self) {
+        let manager = &self.tx_manager;
+        if manager.records.is_empty() {
+            return;
+        }
+        let (successes, cost, profits) = manager.totals();
+        println!("{} transactions mined, {} succeeded, {} wei of gas paid, {} pending", manager.records.len(), successes, cost, manager.pending.len());
+        for (token, profit) in profits {
+            println!("token {:?}: realized profit {}", token, profit);
+        }
+    }
+
+    /// Prints the expected and realized profits of the trades executed on paper, by start token.
+    fn report_paper(&self, ledger: &PaperLedger) {
+        println!("{} paper trades settled, {} pending", ledger.records.len(), ledger.pending.len());
+        for token in &self.tokens {
+            let (expected, realized) = ledger.totals(token.token_id);
+            if !expected.is_zero() || !realized.is_zero() {
+                println!("token {} ({:?}): expected profit {}, realized profit {}", token.token_id, token.address, expected, realized);
+            }
         }
     }
 }
 
+/// Creates the local wallet of `secret_key` and the provider of `provider_url` signing with it.
+fn create_provider(secret_key: String, provider_url: String) -> BotResult<SignerMiddleware<Provider<Http>, LocalWallet>> {
+    println!("creating local wallet...");
+    let wallet: LocalWallet = secret_key
+        .parse()
+        .map_err(|_| BotError::Config("Invalid secret key. Please check it does not begin with '0x'.".to_string()))?;
+    println!("creating provider...");
+    let provider_service = Provider::<Http>::try_from(provider_url)
+        .map_err(|e| BotError::Config(format!("Invalid provider url: {}", e)))?;
+    Ok(SignerMiddleware::new(provider_service, wallet))
+}
+
+/// Returns the id of the wrapped native token of network with chain id `chain_id`, `None` if it is not among `tokens`.
+fn native_token(chain_id: u32, tokens: &[Token]) -> BotResult<Option<usize>> {
+    let native_addr = wrapped_native_addr(chain_id)?;
+    let native = tokens.iter().find(|token| token.address == native_addr).map(|token| token.token_id);
+    if native.is_none() {
+        println!("wrapped native token {:?} not in tokens config, gas cannot be priced and no cycle is profitable", native_addr);
+    }
+    Ok(native)
+}
+
+/// Signs the transaction calling `to` with `calldata` at `fees`, simulates it alone in a bundle on top of block `block`,
+/// and submits the bundle to the relay for the next blocks unless it fails.
+async fn send_bundle(
+    tx_manager: &mut TxManager,
+    bundles: &mut BundleSubmitter,
+    provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+    to: Address,
+    calldata: Bytes,
+    fees: Fees,
+    block: U64,
+) -> BotResult<Vec<H256>> {
+    let txs = [tx_manager.sign(provider, to, calldata, fees).await?];
+    let simulation = bundles.call_bundle(&txs, block).await?;
+    if let Some(failure) = simulation.failure() {
+        return Err(BotError::Execution(format!("Bundle simulation failed: {}", failure)));
+    }
+    println!("bundle simulated, {} gas used, {} wei paid to the builder", simulation.total_gas_used, simulation.coinbase_diff());
+    bundles.send_bundle(&txs, block).await
+}
+
 #[tokio::main]
 async fn main() {
     // dotenv and args config
     let args: Vec<String> = env::args().collect();
     dotenv().ok();
-    let (chain_id, provider_url) = read_args(args);
-    let secret_key = dotenv::var("SECRET_KEY_1").unwrap();
+    if let Err(e) = start(args).await {
+        println!("\n-------------------- Trading Bot Stopped: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Creates the bot from the CLI arguments and .env, then runs it until shutdown or a fatal error.
+/// With the `--paper` flag, the cycles are executed on paper instead of being sent.
+/// The `fetch-logs` and `backtest` subcommands record pool events and replay them instead of running the bot,
+/// and the `tokens build` subcommand builds the token file of the network.
+async fn start(args: Vec<String>) -> BotResult<()> {
+    match args.get(2).map(String::as_str) {
+        Some("fetch-logs") => return fetch_logs_command(args).await,
+        Some("backtest") => return backtest(args),
+        Some("tokens") => return tokens_command(args).await,
+        _ => {},
+    }
+    let paper = args.iter().any(|arg| arg == "--paper");
+    let args: Vec<String> = args.into_iter().filter(|arg| arg != "--paper").collect();
+    let (chain_id, provider_url) = read_args(args)?;
+    let secret_key = dotenv::var("SECRET_KEY_1")
+        .map_err(|_| BotError::Config("SECRET_KEY_1 not found in .env".to_string()))?;
     println!("\n-------------------- Trading Bot Started (chain id {})", &chain_id);
 
-    let bot: Bot = Bot::new(chain_id, secret_key, provider_url).await;
-    bot.execute().await;
+    let mut bot: Bot = Bot::new(chain_id, secret_key, provider_url, RunConfig::from_env()?, paper).await?;
+    bot.run().await
+}
+
+/// `<network> fetch-logs <from block> <to block> <archive path>`: records the states and logs of the pools of `config/<chain>/pools.json`
+/// over a range of blocks, to be replayed offline by `backtest`. An existing archive is resumed after its last block.
+async fn fetch_logs_command(args: Vec<String>) -> BotResult<()> {
+    let usage = "Usage: <network> fetch-logs <from block> <to block> <archive path>";
+    let block = |i: usize| -> BotResult<U64> {
+        args.get(i)
+            .and_then(|block| block.parse::<u64>().ok())
+            .map(U64::from)
+            .ok_or_else(|| BotError::Config(usage.to_string()))
+    };
+    let (from_block, to_block) = (block(3)?, block(4)?);
+    let path = args.get(5).ok_or_else(|| BotError::Config(usage.to_string()))?;
+    if from_block > to_block {
+        return Err(BotError::Config(format!("From block {} is after to block {}", from_block, to_block)));
+    }
+    let (chain_id, provider_url) = read_args(args.clone())?;
+    let secret_key = dotenv::var("SECRET_KEY_1")
+        .map_err(|_| BotError::Config("SECRET_KEY_1 not found in .env".to_string()))?;
+    println!("\n-------------------- Archiving blocks {} to {} (chain id {})", from_block, to_block, chain_id);
+
+    let provider = create_provider(secret_key, provider_url)?;
+    let tokens = Token::get_tokens(chain_id)?;
+    let pools = backtest_pools(chain_id, &tokens)?;
+    let archive = fetch_logs(chain_id, &provider, pools, RunConfig::from_env()?.multicall_chunk_size, from_block, to_block, path).await?;
+    println!("{} blocks archived to {}", archive.blocks.len(), path);
+    Ok(())
+}
+
+/// `<network> tokens build [master list path]`: builds `config/<chain>/tokens.json` from the tokens of the network in the master list,
+/// `config/raw/allTokens.json` by default, keeping the ids of the tokens already in the file.
+async fn tokens_command(args: Vec<String>) -> BotResult<()> {
+    if args.get(3).map(String::as_str) != Some("build") {
+        return Err(BotError::Config("Usage: <network> tokens build [master list path]".to_string()));
+    }
+    let master_path = args.get(4).map(String::as_str).unwrap_or(MASTER_TOKENS_PATH);
+    let (chain_id, provider_url) = read_args(args.clone())?;
+    let secret_key = dotenv::var("SECRET_KEY_1")
+        .map_err(|_| BotError::Config("SECRET_KEY_1 not found in .env".to_string()))?;
+    println!("\n-------------------- Building tokens from {} (chain id {})", master_path, chain_id);
+
+    let provider = create_provider(secret_key, provider_url)?;
+    let tokens = build_tokens(chain_id, &provider, RunConfig::from_env()?.multicall_chunk_size, master_path).await?;
+    println!("{} tokens written to {}", tokens.len(), tokens_path(chain_id));
+    Ok(())
+}
+
+/// `<network> backtest <archive path> [from block] [to block]`: replays an archive of `fetch-logs` offline, running the detection
+/// and sizing of the bot on every block of the range, the whole archive by default, and reports the opportunities found,
+/// their profit and how long they lasted.
+fn backtest(args: Vec<String>) -> BotResult<()> {
+    let usage = "Usage: <network> backtest <archive path> [from block] [to block]";
+    let path = args.get(3).ok_or_else(|| BotError::Config(usage.to_string()))?;
+    let block = |i: usize| -> BotResult<Option<U64>> {
+        args.get(i)
+            .map(|block| block.parse::<u64>().map(U64::from).map_err(|_| BotError::Config(usage.to_string())))
+            .transpose()
+    };
+    let (from_block, to_block) = (block(4)?, block(5)?);
+    let (chain_id, _) = read_network(&args)?;
+    let archive = Archive::load(path)?;
+    if archive.chain_id != chain_id {
+        return Err(BotError::Config(format!("Archive {} was recorded on chain id {}", path, archive.chain_id)));
+    }
+    println!("\n-------------------- Backtesting {} (chain id {})", path, chain_id);
+
+    let tokens = Token::get_tokens(chain_id)?;
+    let native = native_token(chain_id, &tokens)?;
+    let report = run_backtest(&archive, &tokens, backtest_pools(chain_id, &tokens)?, native, from_block, to_block)?;
+    for opportunity in &report.opportunities {
+        println!(
+            "{:?} from block {} for {} blocks: best profit {} of token {} swapping {} ({} wei)",
+            opportunity.opportunity, opportunity.first_block, opportunity.persistence(report.to_block),
+            opportunity.best_profit, opportunity.start, opportunity.best_amount_in, opportunity.best_profit_wei,
+        );
+    }
+    println!(
+        "{} opportunities over {} blocks ({} to {}), total profit {} wei, lasting {:.1} blocks on average",
+        report.opportunities.len(), report.blocks, report.from_block, report.to_block, report.total_profit_wei(), report.average_persistence(),
+    );
+    let report_path = format!("{}.report.json", path.trim_end_matches(".bin"));
+    report.save(&report_path)?;
+    println!("report saved to {}", report_path);
+    Ok(())
 }
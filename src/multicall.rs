@@ -0,0 +1,53 @@
+use ethers::{
+    middleware::SignerMiddleware,
+    providers::{Http, Provider},
+    signers::LocalWallet,
+    types::{ Address, Bytes, U64, },
+    contract::Contract,
+};
+
+use crate::utils::*;
+use crate::error::{ BotError, BotResult, };
+
+//------------------------------------- Multicall
+
+/// Batches read-only calls through `Multicall3.aggregate3`, so that every result comes from the same block.
+pub struct Multicall<'a> {
+    contract: Contract<&'a SignerMiddleware<Provider<Http>, LocalWallet>>,
+    chunk_size: usize,
+}
+
+impl<'a> Multicall<'a> {
+    pub fn new(
+        chain_id: u32,
+        provider: &'a SignerMiddleware<Provider<Http>, LocalWallet>,
+        chunk_size: usize,
+    ) -> BotResult<Self> {
+        let contract = Contract::new(multicall_addr(chain_id)?, i_multicall3_abi()?, provider);
+        Ok(Self {
+            contract,
+            chunk_size: chunk_size.max(1),
+        })
+    }
+
+    /// Executes `calls` as `(target, calldata)` pairs at block number `block`, in chunks of at most `chunk_size` calls.
+    /// Returns the return data of each call in order, `None` for calls that reverted.
+    pub async fn aggregate(&self, calls: &[(Address, Bytes)], block: U64) -> BotResult<Vec<Option<Bytes>>> {
+        let mut results: Vec<Option<Bytes>> = Vec::with_capacity(calls.len());
+        for chunk in calls.chunks(self.chunk_size) {
+            let call3: Vec<(Address, bool, Bytes)> = chunk
+                .iter()
+                .map(|(target, calldata)| (*target, true, calldata.clone()))
+                .collect();
+            let returns: Vec<(bool, Bytes)> = self.contract
+                .method::<Vec<(Address, bool, Bytes)>, Vec<(bool, Bytes)>>("aggregate3", call3)
+                .map_err(|e| BotError::Abi(format!("`Multicall3.aggregate3()` method not found in ABI: {}", e)))?
+                .block(block)
+                .call()
+                .await
+                .map_err(|e| BotError::Rpc(format!("`Multicall3.aggregate3()` asynchronous call failed: {}", e)))?;
+            results.extend(returns.into_iter().map(|(success, data)| if success { Some(data) } else { None }));
+        }
+        Ok(results)
+    }
+}
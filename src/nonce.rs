@@ -0,0 +1,137 @@
+use ethers::{
+    middleware::SignerMiddleware,
+    providers::{Http, Provider, Middleware},
+    signers::LocalWallet,
+    types::{ Address, U256, BlockNumber, },
+};
+use std::collections::BTreeSet;
+
+use crate::error::{ BotError, BotResult, };
+
+/// Fragments of the node errors caused by a wrong nonce, after which the nonces are resynced.
+const NONCE_ERRORS: [&str; 4] = ["nonce too low", "nonce too high", "invalid nonce", "already known"];
+
+/// Whether `error` is a node error caused by a wrong nonce.
+pub fn is_nonce_error(error: &str) -> bool {
+    let error = error.to_lowercase();
+    NONCE_ERRORS.iter().any(|fragment| error.contains(fragment))
+}
+
+//------------------------------------- NonceManager
+
+/// Allocates the nonces of the bot's account locally, so that several transactions can be sent in the same block
+/// without asking the node, which races as the node only counts the transactions it already received.
+/// Nonces allocated to transactions that never reached the node, or that were dropped from it, are gaps
+/// that block every later transaction: they are allocated again first.
+#[derive(Debug, Clone)]
+pub struct NonceManager {
+    pub address: Address,
+    /// Next nonce never allocated, `None` until synced with the node.
+    next: Option<U256>,
+    /// Allocated nonces below `next` with no transaction known to the node.
+    gaps: BTreeSet<U256>,
+}
+
+impl NonceManager {
+    pub fn new(address: Address) -> Self {
+        Self {
+            address,
+            next: None,
+            gaps: BTreeSet::new(),
+        }
+    }
+
+    /// Returns the nonce of the next transaction: the lowest gap, else a new nonce.
+    pub async fn next(&mut self, provider: &SignerMiddleware<Provider<Http>, LocalWallet>) -> BotResult<U256> {
+        if self.next.is_none() {
+            self.sync(provider).await?;
+        }
+        Ok(self.allocate())
+    }
+
+    /// Resyncs the nonces with the transaction count of the account including its pending transactions.
+    /// Every allocated nonce above it is forgotten, to be allocated again.
+    pub async fn sync(&mut self, provider: &SignerMiddleware<Provider<Http>, LocalWallet>) -> BotResult<()> {
+        let count = provider
+            .get_transaction_count(self.address, Some(BlockNumber::Pending.into()))
+            .await
+            .map_err(|e| BotError::Rpc(format!("`eth_getTransactionCount` asynchronous call failed: {}", e)))?;
+        self.reset(count);
+        Ok(())
+    }
+
+    /// Marks `nonce` as a gap, when its transaction was rejected or dropped by the node.
+    pub fn release(&mut self, nonce: U256) {
+        match self.next {
+            Some(next) if nonce + 1 == next => {
+                // the last nonce allocated, along with the gaps right below it
+                let mut next = nonce;
+                while next > U256::zero() && self.gaps.remove(&(next - 1)) {
+                    next -= U256::one();
+                }
+                self.next = Some(next);
+            },
+            Some(next) if nonce < next => {
+                self.gaps.insert(nonce);
+            },
+            _ => {},
+        }
+    }
+
+    fn allocate(&mut self) -> U256 {
+        if let Some(gap) = self.gaps.pop_first() {
+            return gap;
+        }
+        let nonce = self.next.unwrap_or_default();
+        self.next = Some(nonce + 1);
+        nonce
+    }
+
+    fn reset(&mut self, count: U256) {
+        self.next = Some(count);
+        self.gaps.clear();
+    }
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nonce_allocation() {
+        let mut nonces = NonceManager::new(Address::zero());
+        nonces.reset(U256::from(5));
+        let allocated: Vec<U256> = (0..4).map(|_| nonces.allocate()).collect();
+        assert_eq!(allocated, [5, 6, 7, 8].map(U256::from));
+
+        // rejected transactions leave gaps, filled first
+        nonces.release(U256::from(7));
+        nonces.release(U256::from(6));
+        assert_eq!(nonces.allocate(), U256::from(6));
+
+        // releasing the last nonce also releases the gaps below it
+        nonces.release(U256::from(8));
+        assert_eq!(nonces.next, Some(U256::from(7)));
+        assert!(nonces.gaps.is_empty());
+        assert_eq!(nonces.allocate(), U256::from(7));
+
+        // nonces never allocated are ignored
+        nonces.release(U256::from(20));
+        assert_eq!(nonces.allocate(), U256::from(8));
+
+        nonces.release(U256::from(5));
+        nonces.reset(U256::from(9));
+        assert_eq!(nonces.allocate(), U256::from(9));
+    }
+
+    #[test]
+    fn test_is_nonce_error() {
+        assert!(is_nonce_error("(code: -32000, message: nonce too low, data: None)"));
+        assert!(is_nonce_error("Nonce too high"));
+        assert!(is_nonce_error("already known"));
+        assert!(!is_nonce_error("replacement transaction underpriced"));
+        assert!(!is_nonce_error("insufficient funds for gas * price + value"));
+    }
+}
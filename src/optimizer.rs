@@ -0,0 +1,189 @@
+use ethers::types::{ U256, I256, };
+use std::convert::TryFrom;
+
+//------------------------------------- Quote
+
+/// Pool able to quote the exact output of a swap.
+pub trait Quote {
+    /// Returns the amount of the pool's coin `token_out` received for `amount_in` of its coin `token_in`,
+    /// in raw token units, or the reason the pool contract would revert with.
+    /// Coins are indexed in pool order, e.g. `(0, 1)` is a zero for one swap.
+    fn get_amount_out(&self, token_in: usize, token_out: usize, amount_in: U256) -> Result<U256, &'static str>;
+}
+
+//------------------------------------- Hop
+
+/// A swap through one pool of a cycle.
+pub struct Hop<'a> {
+    pub pool: &'a dyn Quote,
+    /// Index of the input coin in the pool.
+    pub token_in: usize,
+    /// Index of the output coin in the pool.
+    pub token_out: usize,
+}
+
+impl<'a> Hop<'a> {
+    pub fn new(pool: &'a dyn Quote, token_in: usize, token_out: usize) -> Self {
+        Self { pool, token_in, token_out }
+    }
+}
+
+//------------------------------------- CycleTrade
+
+/// Optimal trade through a cycle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CycleTrade {
+    /// Amount going into each hop followed by the amount coming out of the last hop, all in raw token units.
+    pub amounts: Vec<U256>,
+    /// Amount out minus amount in minus gas cost, in the start token.
+    pub profit: I256,
+}
+
+impl CycleTrade {
+    pub fn amount_in(&self) -> U256 {
+        self.amounts[0]
+    }
+
+    pub fn amount_out(&self) -> U256 {
+        self.amounts[self.amounts.len() - 1]
+    }
+}
+
+//------------------------------------- optimizer
+
+/// Returns the amounts going in and out of each hop when swapping `amount_in` through `hops`,
+/// `None` if any of the swaps would revert.
+pub fn simulate_cycle(hops: &[Hop], amount_in: U256) -> Option<Vec<U256>> {
+    let mut amounts = Vec::with_capacity(hops.len() + 1);
+    amounts.push(amount_in);
+    let mut amount = amount_in;
+    for hop in hops {
+        amount = hop.pool.get_amount_out(hop.token_in, hop.token_out, amount).ok()?;
+        amounts.push(amount);
+    }
+    Some(amounts)
+}
+
+/// Returns the net profit of swapping `amount_in` through `hops`, `None` if any of the swaps would revert.
+fn cycle_profit(hops: &[Hop], amount_in: U256, gas_cost: U256) -> Option<I256> {
+    let amounts = simulate_cycle(hops, amount_in)?;
+    let amount_out = I256::try_from(amounts[amounts.len() - 1]).ok()?;
+    let amount_in = I256::try_from(amount_in).ok()?;
+    let gas_cost = I256::try_from(gas_cost).ok()?;
+    amount_out.checked_sub(amount_in)?.checked_sub(gas_cost)
+}
+
+/// Searches `[1, max_amount_in]` for the input amount maximizing the profit of swapping through `hops`,
+/// net of `gas_cost` expressed in the start token.
+/// Concentrated liquidity makes the profit a concave function of the input amount, so a ternary search
+/// converges to the optimum. Returns `None` if no input amount yields a positive profit.
+pub fn optimize_cycle(hops: &[Hop], gas_cost: U256, max_amount_in: U256) -> Option<CycleTrade> {
+    if hops.is_empty() || max_amount_in.is_zero() {
+        return None;
+    }
+    let profit = |amount_in: U256| cycle_profit(hops, amount_in, gas_cost).unwrap_or(I256::MIN);
+
+    let mut lo = U256::one();
+    let mut hi = max_amount_in;
+    while hi - lo > U256::from(2) {
+        let third = (hi - lo) / 3;
+        let m1 = lo + third;
+        let m2 = hi - third;
+        if profit(m1) < profit(m2) {
+            lo = m1;
+        } else {
+            hi = m2;
+        }
+    }
+
+    // at most three candidates left
+    let mut best_amount = lo;
+    let mut best_profit = profit(lo);
+    let mut amount = lo + 1;
+    while amount <= hi {
+        let p = profit(amount);
+        if p > best_profit {
+            best_amount = amount;
+            best_profit = p;
+        }
+        amount += U256::one();
+    }
+
+    if !best_profit.is_positive() {
+        return None;
+    }
+    Some(CycleTrade {
+        amounts: simulate_cycle(hops, best_amount)?,
+        profit: best_profit,
+    })
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::univ3::{ PoolSnapshot, PoolState, };
+    use crate::univ3_math::{ TickInfo, get_sqrt_ratio_at_tick, };
+
+    /// Full range pool with `liquidity` around `tick`.
+    fn snapshot(tick: i32, liquidity: u128, fee: u32) -> PoolSnapshot {
+        let sqrt_price_x96 = get_sqrt_ratio_at_tick(tick).unwrap();
+        PoolSnapshot {
+            state: PoolState::new((sqrt_price_x96, tick, 0, 1, 1, 0, true), liquidity, 18, 18),
+            fee,
+            tick_spacing: 60,
+            ticks: vec![
+                TickInfo { index: -887220, liquidity_gross: liquidity, liquidity_net: liquidity as i128 },
+                TickInfo { index: 887220, liquidity_gross: liquidity, liquidity_net: -(liquidity as i128) },
+            ],
+            min_tick: -887220,
+            max_tick: 887220,
+        }
+    }
+
+    #[test]
+    fn test_optimize_cycle_profitable() {
+        // token1 is ~2% more expensive in the second pool
+        let cheap = snapshot(0, 10u128.pow(21), 500);
+        let expensive = snapshot(200, 10u128.pow(21), 500);
+        let hops = [Hop::new(&cheap, 1, 0), Hop::new(&expensive, 0, 1)];
+        let max_amount_in = U256::exp10(24);
+
+        let trade = optimize_cycle(&hops, U256::zero(), max_amount_in).unwrap();
+        assert!(trade.profit.is_positive());
+        assert_eq!(trade.amounts.len(), 3);
+        assert_eq!(
+            trade.profit,
+            I256::try_from(trade.amount_out()).unwrap() - I256::try_from(trade.amount_in()).unwrap(),
+        );
+
+        // the optimum beats nearby amounts
+        let step = trade.amount_in() / 100;
+        for amount_in in [trade.amount_in() - step, trade.amount_in() + step] {
+            assert!(cycle_profit(&hops, amount_in, U256::zero()).unwrap() <= trade.profit);
+        }
+
+        // gas reduces the profit by exactly the gas cost at the same size
+        let gas_cost = U256::exp10(15);
+        let with_gas = optimize_cycle(&hops, gas_cost, max_amount_in).unwrap();
+        assert_eq!(with_gas.amount_in(), trade.amount_in());
+        assert_eq!(with_gas.profit, trade.profit - I256::try_from(gas_cost).unwrap());
+    }
+
+    #[test]
+    fn test_optimize_cycle_unprofitable() {
+        // same price in both pools, fees make any size unprofitable
+        let a = snapshot(0, 10u128.pow(21), 500);
+        let b = snapshot(0, 10u128.pow(21), 500);
+        let hops = [Hop::new(&a, 1, 0), Hop::new(&b, 0, 1)];
+        assert_eq!(optimize_cycle(&hops, U256::zero(), U256::exp10(24)), None);
+
+        // profitable before gas only
+        let cheap = snapshot(0, 10u128.pow(18), 500);
+        let expensive = snapshot(200, 10u128.pow(18), 500);
+        let hops = [Hop::new(&cheap, 1, 0), Hop::new(&expensive, 0, 1)];
+        assert!(optimize_cycle(&hops, U256::zero(), U256::exp10(24)).is_some());
+        assert_eq!(optimize_cycle(&hops, U256::exp10(18), U256::exp10(24)), None);
+    }
+}
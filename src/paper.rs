@@ -0,0 +1,207 @@
+use ethers::types::{ U256, I256, U64, };
+use std::convert::TryFrom;
+
+use crate::graph::Edge;
+use crate::optimizer::{ CycleTrade, Hop, simulate_cycle, };
+use crate::pool::Pool;
+
+//------------------------------------- PaperTrade
+
+/// A cycle executed on paper, waiting to be settled on the pool states of the next block, when it would have been included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaperTrade {
+    /// `(from, pool_id)` of every hop of the cycle.
+    pub opportunity: Vec<(usize, usize)>,
+    /// `(pool_id, coin_in, coin_out)` of every hop of the cycle.
+    hops: Vec<(usize, usize, usize)>,
+    /// Id of the start token, in which amounts and profits are expressed.
+    pub start: usize,
+    pub amount_in: U256,
+    /// Profit net of gas and of the loan fee on the pool states the cycle was sized on.
+    pub expected_profit: I256,
+    pub gas_cost: U256,
+    /// Fee of the loan of `amount_in`, only paid if the cycle does not revert.
+    pub loan_fee: U256,
+    /// Block whose pool states the cycle was sized on.
+    pub block: U64,
+}
+
+//------------------------------------- PaperRecord
+
+/// A settled paper trade.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaperRecord {
+    pub opportunity: Vec<(usize, usize)>,
+    pub start: usize,
+    pub amount_in: U256,
+    pub expected_profit: I256,
+    /// Profit net of gas and of the loan fee on the pool states of `settled_block`, minus the gas cost if a swap reverted.
+    pub realized_profit: I256,
+    pub block: U64,
+    pub settled_block: U64,
+    /// Whether a swap of the cycle reverted on the pool states of `settled_block`.
+    pub reverted: bool,
+}
+
+//------------------------------------- PaperLedger
+
+/// Virtual balances of a bot executing its cycles on paper, to evaluate a strategy without risking funds.
+/// A cycle found on the states of a block is settled with the bot's own swap simulation on the states of the next block:
+/// the pools may have moved in between, so the realized profit can differ from the expected one.
+#[derive(Debug, Clone, Default)]
+pub struct PaperLedger {
+    /// Realized profit of the settled trades, by token id.
+    pub balances: Vec<I256>,
+    pub pending: Vec<PaperTrade>,
+    /// Settled trades, oldest first.
+    pub records: Vec<PaperRecord>,
+}
+
+impl PaperLedger {
+    pub fn new(token_count: usize) -> Self {
+        Self {
+            balances: vec![I256::zero(); token_count],
+            pending: Vec::new(),
+            records: Vec::new(),
+        }
+    }
+
+    /// Whether a trade through `opportunity` is already waiting to be settled.
+    pub fn is_pending(&self, opportunity: &[(usize, usize)]) -> bool {
+        self.pending.iter().any(|trade| trade.opportunity == opportunity)
+    }
+
+    /// Executes `trade` through `cycle` on paper, found on the states of block `block`, whose edge ids are indices in `pools`.
+    /// `trade.profit` is net of `gas_cost` and `loan_fee`.
+    pub fn execute(
+        &mut self,
+        pools: &[Box<dyn Pool>],
+        cycle: &[(usize, Edge)],
+        trade: &CycleTrade,
+        gas_cost: U256,
+        loan_fee: U256,
+        block: U64,
+    ) {
+        let hops = cycle
+            .iter()
+            .map(|&(from, Edge { to, edge_id: pool_id, .. })| {
+                let token_ids = pools[pool_id].tokens();
+                let coin = |token_id: usize| token_ids.iter().position(|&id| id == token_id).unwrap_or_default();
+                (pool_id, coin(from), coin(to))
+            })
+            .collect();
+        self.pending.push(PaperTrade {
+            opportunity: cycle.iter().map(|&(from, e)| (from, e.edge_id)).collect(),
+            hops,
+            start: cycle[0].0,
+            amount_in: trade.amount_in(),
+            expected_profit: trade.profit,
+            gas_cost,
+            loan_fee,
+            block,
+        });
+    }
+
+    /// Settles the trades found before block `block` on the states of `pools` at block `block`,
+    /// and returns the records of the trades settled.
+    pub fn settle(&mut self, pools: &[Box<dyn Pool>], block: U64) -> Vec<PaperRecord> {
+        let (due, pending): (Vec<PaperTrade>, Vec<PaperTrade>) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|trade| trade.block < block);
+        self.pending = pending;
+
+        let mut settled: Vec<PaperRecord> = Vec::new();
+        for trade in due {
+            let hops: Vec<Hop> = trade.hops
+                .iter()
+                .map(|&(pool_id, coin_in, coin_out)| Hop::new(&*pools[pool_id], coin_in, coin_out))
+                .collect();
+            let gas_cost = I256::try_from(trade.gas_cost).unwrap_or(I256::MAX);
+            let loan_fee = I256::try_from(trade.loan_fee).unwrap_or(I256::MAX);
+            // a reverted execution still pays for its gas
+            let (realized_profit, reverted) = match simulate_cycle(&hops, trade.amount_in) {
+                Some(amounts) => {
+                    let amount_out = I256::try_from(amounts[amounts.len() - 1]).unwrap_or(I256::MAX);
+                    let amount_in = I256::try_from(trade.amount_in).unwrap_or(I256::MAX);
+                    (amount_out.saturating_sub(amount_in).saturating_sub(loan_fee).saturating_sub(gas_cost), false)
+                },
+                None => (I256::zero().saturating_sub(gas_cost), true),
+            };
+            self.balances[trade.start] = self.balances[trade.start].saturating_add(realized_profit);
+            let record = PaperRecord {
+                opportunity: trade.opportunity,
+                start: trade.start,
+                amount_in: trade.amount_in,
+                expected_profit: trade.expected_profit,
+                realized_profit,
+                block: trade.block,
+                settled_block: block,
+                reverted,
+            };
+            self.records.push(record.clone());
+            settled.push(record);
+        }
+        settled
+    }
+
+    /// Returns the sums of the expected and realized profits of the settled trades starting from token `token_id`.
+    pub fn totals(&self, token_id: usize) -> (I256, I256) {
+        self.records
+            .iter()
+            .filter(|record| record.start == token_id)
+            .fold((I256::zero(), I256::zero()), |(expected, realized), record| {
+                (expected.saturating_add(record.expected_profit), realized.saturating_add(record.realized_profit))
+            })
+    }
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ tokens, univ2_pool, };
+    use ethers::types::Address;
+
+    #[test]
+    fn test_paper_settlement() {
+        let tokens = tokens(&[18; 2]);
+        let pool = |address: u8, reserve_0: u128, reserve_1: u128| -> Box<dyn Pool> {
+            Box::new(univ2_pool(Address::repeat_byte(address), 0, (0, 1), Some((reserve_0, reserve_1)), &tokens))
+        };
+        // token 0 is twice as expensive in the second pool
+        let mut pools = vec![
+            pool(1, 1_000_000_000, 1_000_000_000),
+            pool(2, 1_000_000_000, 2_000_000_000),
+        ];
+        let cycle = [(0, Edge::new(1, 0., 1)), (1, Edge::new(0, 0., 0))];
+        let hops = [Hop::new(&*pools[1], 0, 1), Hop::new(&*pools[0], 1, 0)];
+        let amounts = simulate_cycle(&hops, U256::from(1_000_000)).unwrap();
+        // net of 100 of gas and a loan fee of 30
+        let profit = I256::try_from(amounts[2]).unwrap() - I256::from(1_000_000) - I256::from(100) - I256::from(30);
+        let trade = CycleTrade { amounts, profit };
+
+        let mut ledger = PaperLedger::new(2);
+        ledger.execute(&pools, &cycle, &trade, U256::from(100), U256::from(30), U64::from(10));
+        assert!(ledger.is_pending(&[(0, 1), (1, 0)]));
+
+        // not settled on the block it was found on
+        assert!(ledger.settle(&pools, U64::from(10)).is_empty());
+
+        // nothing moved, the realized profit is the expected one
+        let settled = ledger.settle(&pools, U64::from(11));
+        assert_eq!(settled.len(), 1);
+        assert_eq!(settled[0].realized_profit, profit);
+        assert!(!settled[0].reverted);
+        assert_eq!(ledger.balances[0], profit);
+        assert!(ledger.pending.is_empty());
+
+        // the second pool moved back in line before the next trade settled: the trade loses its fees and gas
+        ledger.execute(&pools, &cycle, &trade, U256::from(100), U256::from(30), U64::from(11));
+        pools[1] = pool(2, 1_000_000_000, 1_000_000_000);
+        let settled = ledger.settle(&pools, U64::from(12));
+        assert!(settled[0].realized_profit < I256::from(-100));
+        assert_eq!(ledger.totals(0), (profit + profit, profit + settled[0].realized_profit));
+        assert_eq!(ledger.records.len(), 2);
+    }
+}
@@ -0,0 +1,110 @@
+use ethers::types::U256;
+use std::collections::HashSet;
+
+use crate::graph::{ Graph, Edge, };
+use crate::univ3::Token;
+use crate::optimizer::{ CycleTrade, Hop, optimize_cycle, };
+use crate::pool::Pool;
+use crate::profitability::{ GasPricer, cycle_gas, cycle_loan, net_of_gas, };
+
+//------------------------------------- detection
+
+/// Returns the graph of `pools` over `token_count` tokens, with an edge between every two coins of every pool
+/// weighted by the log price net of the pool fee. Edge ids are indices in `pools`.
+pub fn build_graph(pools: &[Box<dyn Pool>], token_count: usize) -> Graph {
+    let mut graph = Graph::new(token_count);
+    for (pool_id, pool) in pools.iter().enumerate() {
+        if pool.is_stale() {
+            continue;
+        }
+        let token_ids = pool.tokens();
+        for (i, &token_in_id) in token_ids.iter().enumerate() {
+            for (j, &token_out_id) in token_ids.iter().enumerate() {
+                if i == j {
+                    continue;
+                }
+                if let Some(weight) = pool.log_price(i, j) {
+                    graph.add_edge(token_in_id, token_out_id, weight, pool_id);
+                }
+            }
+        }
+    }
+    graph
+}
+
+/// Returns the negative cycles of `graph` going through at least one of the pools `dirty`, sorted,
+/// found by running Bellman-Ford from the tokens of these pools.
+pub fn find_cycles(graph: &Graph, pools: &[Box<dyn Pool>], dirty: &[usize]) -> Vec<Vec<(usize, Edge)>> {
+    let mut starts: Vec<usize> = dirty
+        .iter()
+        .flat_map(|&pool_id| pools[pool_id].tokens())
+        .collect();
+    starts.sort_unstable();
+    starts.dedup();
+    let mut cycles: Vec<Vec<(usize, Edge)>> = Vec::new();
+    let mut found: HashSet<Vec<(usize, usize)>> = HashSet::new();
+    for start in starts {
+        for cycle in graph.bellman_ford_cycles(start) {
+            let is_affected = cycle.iter().any(|(_, e)| dirty.binary_search(&e.edge_id).is_ok());
+            if is_affected && found.insert(opportunity(&cycle)) {
+                cycles.push(cycle);
+            }
+        }
+    }
+    cycles
+}
+
+/// Identifies `cycle` by the `(from, pool_id)` of its hops.
+pub fn opportunity(cycle: &[(usize, Edge)]) -> Vec<(usize, usize)> {
+    cycle.iter().map(|&(from, e)| (from, e.edge_id)).collect()
+}
+
+//------------------------------------- sizing
+
+/// A cycle sized on the pools' liquidity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizedCycle {
+    /// Optimal trade, its profit net of `gas_cost` and `loan_fee`.
+    pub trade: CycleTrade,
+    pub gas: u64,
+    /// Cost of `gas` in the start token.
+    pub gas_cost: U256,
+    /// Fee of the loan of the start token, see `cycle_loan`.
+    pub loan_fee: U256,
+}
+
+/// Sizes the trade through `cycle`, whose edge ids are indices in `pools`, and prices its gas with `pricer`.
+/// Returns the reason the cycle is dropped if no size is profitable net of gas and of the fee of its loan.
+pub fn size_cycle(
+    pools: &[Box<dyn Pool>],
+    tokens: &[Token],
+    cycle: &[(usize, Edge)],
+    pricer: &GasPricer,
+) -> Result<SizedCycle, String> {
+    let hops: Vec<Hop> = cycle
+        .iter()
+        .map(|&(from, Edge { to, edge_id: pool_id, .. })| {
+            let pool = &*pools[pool_id];
+            let token_ids = pool.tokens();
+            let coin = |token_id| token_ids.iter().position(|&id| id == token_id).unwrap();
+            Hop::new(pool, coin(from), coin(to))
+        })
+        .collect();
+    let trade = optimize_cycle(&hops, U256::zero(), U256::from(u128::MAX))
+        .ok_or_else(|| "no profitable size".to_string())?;
+
+    // the gas used barely depends on the size, so the optimal size net of gas is the same
+    let start = &tokens[cycle[0].0];
+    let gas = cycle_gas(pools, cycle, &trade);
+    let gas_cost = pricer
+        .gas_cost(gas, start)
+        .ok_or_else(|| format!("gas cannot be priced in token {}", start.token_id))?;
+    let loan_fee = cycle_loan(pools, cycle, trade.amount_in())
+        .ok_or_else(|| format!("no pool can lend token {}", start.token_id))?
+        .fee();
+    let trade = net_of_gas(trade, gas_cost.saturating_add(loan_fee))
+        .ok_or_else(|| format!(
+            "{} gas costs {} of token {} and the loan {}, more than the profit", gas, gas_cost, start.token_id, loan_fee,
+        ))?;
+    Ok(SizedCycle { trade, gas, gas_cost, loan_fee })
+}
@@ -0,0 +1,460 @@
+use ethers::{
+    middleware::SignerMiddleware,
+    providers::{Http, Provider, Middleware},
+    signers::LocalWallet,
+    types::{ Address, Bytes, U256, U64, H256, Log, Filter, },
+};
+use std::{
+    any::Any,
+    fmt::Debug,
+    collections::{ HashMap, HashSet, VecDeque, hash_map::Entry, },
+};
+
+use crate::univ3::{ Token, Univ3Pool, };
+use crate::univ2::Univ2Pool;
+use crate::curve::CurvePool;
+use crate::balancer::BalancerPool;
+use crate::algebra::AlgebraPool;
+use crate::multicall::Multicall;
+use crate::optimizer::Quote;
+use crate::error::{ BotError, BotResult, };
+
+//------------------------------------- Pool
+
+/// How a pool is paid the input of a swap, as encoded in the `Swap.payment` field of the executor contract.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapPayment {
+    /// The pool asks for the input in a callback once it sent the output, e.g. Uniswap V3 and Algebra.
+    /// These pools can also lend their tokens with `flash()`.
+    Callback = 0,
+    /// The input is transferred to the pool before the swap, e.g. Uniswap V2.
+    Transfer = 1,
+    /// The swap target pulls the input from the caller, who must approve it beforehand, e.g. Curve and Balancer.
+    Approval = 2,
+}
+
+/// Pool of any venue, as seen by the graph builder, the cycle optimizer and the executor.
+/// The state is refreshed in rounds of batched calls, so that all the pools are fetched together
+/// through `Multicall`, then kept up to date by applying the pool's logs.
+pub trait Pool: Quote + Debug + Send + Sync {
+    /// Address of the pool contract.
+    fn address(&self) -> Address;
+
+    /// Address of the contract emitting the logs of `event_topics`, the pool itself unless overridden.
+    fn log_address(&self) -> Address {
+        self.address()
+    }
+
+    /// Ids of the pool's coins, in pool order.
+    fn tokens(&self) -> Vec<usize>;
+
+    /// Whether the state must be refreshed before the pool can be used.
+    fn is_stale(&self) -> bool;
+
+    /// Marks the state for refresh.
+    fn invalidate(&mut self);
+
+    /// Returns the calls of the next refresh round as `(target, calldata)` pairs.
+    fn refresh_calls(&self) -> BotResult<Vec<(Address, Bytes)>>;
+
+    /// Applies the return data of the calls of `refresh_calls`, `None` for calls that reverted.
+    /// The refresh is complete once the pool is no longer stale.
+    fn apply_refresh(&mut self, returns: &[Option<Bytes>]) -> BotResult<()>;
+
+    /// Signatures of the events changing the state of the pool.
+    fn event_topics(&self) -> BotResult<Vec<H256>>;
+
+    /// Applies `log`, emitted by the pool, to the state. Returns whether the state changed.
+    fn apply_log(&mut self, log: &Log) -> BotResult<bool>;
+
+    /// Returns the weight of the edge from coin `token_in` to coin `token_out`:
+    /// the negative log of the spot price net of the fee. `None` if the pool has no liquidity.
+    fn log_price(&self, token_in: usize, token_out: usize) -> Option<f64>;
+
+    /// Returns the calldata of a swap of `amount_in` of coin `token_in` for `amount_out` of coin `token_out`
+    /// sent to `recipient`, to be sent to `swap_target()`.
+    fn swap_calldata(&self, token_in: usize, token_out: usize, amount_in: U256, amount_out: U256, recipient: Address) -> BotResult<Bytes>;
+
+    /// How the swaps of `swap_calldata` are paid.
+    fn swap_payment(&self) -> SwapPayment;
+
+    /// Estimated gas used by a swap of `amount_in` of coin `token_in` for coin `token_out`.
+    fn swap_gas(&self, token_in: usize, token_out: usize, amount_in: U256) -> u64;
+
+    /// Address of the contract executing the swaps of `swap_calldata`, the pool itself unless overridden.
+    fn swap_target(&self) -> Address {
+        self.address()
+    }
+
+    /// Fee of borrowing `amount` of any of its coins with `flash()`, in the same coin. `None` if the pool cannot lend.
+    fn flash_fee(&self, _amount: U256) -> Option<U256> {
+        None
+    }
+
+    fn box_clone(&self) -> Box<dyn Pool>;
+
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl Clone for Box<dyn Pool> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// Loads the pools of every supported venue on network with chain id `chain_id`.
+pub async fn load_pools(
+    chain_id: u32,
+    provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+    tokens: &[Token],
+    chunk_size: usize,
+) -> BotResult<Vec<Box<dyn Pool>>> {
+    let mut pools: Vec<Box<dyn Pool>> = Vec::new();
+    println!("getting uniswap v3 pools config...");
+    for pool in Univ3Pool::load(chain_id, provider, tokens).await? {
+        pools.push(Box::new(pool));
+    }
+    println!("getting algebra pools config...");
+    for pool in AlgebraPool::load(chain_id, provider, tokens, chunk_size).await? {
+        pools.push(Box::new(pool));
+    }
+    println!("getting uniswap v2 pairs config...");
+    for pool in Univ2Pool::load(chain_id, provider, tokens, chunk_size).await? {
+        pools.push(Box::new(pool));
+    }
+    println!("getting curve pools config...");
+    for pool in CurvePool::load(chain_id, provider, tokens, chunk_size).await? {
+        pools.push(Box::new(pool));
+    }
+    println!("getting balancer pools config...");
+    for pool in BalancerPool::load(chain_id, provider, tokens, chunk_size).await? {
+        pools.push(Box::new(pool));
+    }
+    Ok(pools)
+}
+
+//------------------------------------- PoolTracker
+
+/// Number of blocks kept in the undo journal, i.e. the deepest reorg that can be rolled back.
+pub const JOURNAL_DEPTH: usize = 64;
+
+/// Block identity used to detect chain reorganisations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub number: U64,
+    pub hash: H256,
+    pub parent_hash: H256,
+}
+
+impl BlockHeader {
+    /// Fetches the header of the canonical block at number `number`.
+    pub async fn fetch(provider: &SignerMiddleware<Provider<Http>, LocalWallet>, number: U64) -> BotResult<Self> {
+        let block = provider
+            .get_block(number)
+            .await
+            .map_err(|e| BotError::Rpc(format!("`eth_getBlockByNumber` asynchronous call failed: {}", e)))?
+            .ok_or_else(|| BotError::Rpc(format!("Block {} not found", number)))?;
+        Ok(Self {
+            number,
+            hash: block.hash.ok_or_else(|| BotError::Rpc(format!("Block {} is pending", number)))?,
+            parent_hash: block.parent_hash,
+        })
+    }
+}
+
+/// Pools changed by a block, as they were before the block.
+struct JournalEntry {
+    header: BlockHeader,
+    undo: HashMap<usize, Box<dyn Pool>>,
+}
+
+/// Keeps pools up to date by applying their logs instead of re-reading every pool each block.
+/// Each applied block is journaled so that the pools can be rolled back when the chain reorganises.
+/// Pools are identified by their index in `pools`.
+pub struct PoolTracker {
+    pub pools: Vec<Box<dyn Pool>>,
+    /// Ids of the pools by the address emitting their logs, which several pools share on vault based venues.
+    pool_ids: HashMap<Address, Vec<usize>>,
+    dirty: HashSet<usize>,
+    journal: VecDeque<JournalEntry>,
+}
+
+impl PoolTracker {
+    pub fn new() -> Self {
+        Self {
+            pools: Vec::new(),
+            pool_ids: HashMap::new(),
+            dirty: HashSet::new(),
+            journal: VecDeque::new(),
+        }
+    }
+
+    /// Last block applied to the pools.
+    pub fn block(&self) -> Option<U64> {
+        self.journal.back().map(|entry| entry.header.number)
+    }
+
+    /// Hash of the applied block at number `number`, if it is still in the journal.
+    pub fn block_hash(&self, number: U64) -> Option<H256> {
+        self.journal
+            .iter()
+            .find(|entry| entry.header.number == number)
+            .map(|entry| entry.header.hash)
+    }
+
+    /// Brings the pools to block number `block`.
+    /// The pools are fetched on the first call, later calls only apply the logs emitted since the previous one.
+    /// If the chain reorganised, the pools are rolled back to the last block shared with the canonical chain
+    /// before applying the canonical logs, or refetched if the reorg is deeper than the journal.
+    pub async fn update(
+        &mut self,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        multicall: &Multicall<'_>,
+        block: U64,
+    ) -> BotResult<()> {
+        let last = match self.block() {
+            None => return self.bootstrap(provider, multicall, block).await,
+            Some(last) if block <= last => return Ok(()),
+            Some(last) => last,
+        };
+
+        // everything is fetched before touching the pools, so that a cancelled update leaves them consistent
+        let mut headers: Vec<BlockHeader> = Vec::new();
+        for number in (last.as_u64() + 1)..=block.as_u64() {
+            headers.push(BlockHeader::fetch(provider, U64::from(number)).await?);
+        }
+
+        // find the last block shared with the canonical chain
+        let mut ancestor = last;
+        if Some(headers[0].parent_hash) != self.block_hash(last) {
+            let mut canonical: HashMap<U64, H256> = HashMap::new();
+            for number in self.journal.iter().rev().map(|entry| entry.header.number) {
+                let header = BlockHeader::fetch(provider, number).await?;
+                canonical.insert(number, header.hash);
+                if Some(header.hash) == self.block_hash(number) {
+                    break;
+                }
+                headers.push(header);
+            }
+            match self.common_ancestor(&canonical) {
+                Some(number) => ancestor = number,
+                None => {
+                    println!("chain reorganised deeper than {} blocks, refetching pools", JOURNAL_DEPTH);
+                    return self.bootstrap(provider, multicall, block).await;
+                },
+            }
+            headers.sort_by_key(|header| header.number);
+        }
+
+        let logs = self.fetch_logs(provider, ancestor + 1, block).await?;
+        // the chain changed again while fetching, retry on the next block
+        let hashes: HashSet<H256> = headers.iter().map(|header| header.hash).collect();
+        if logs.iter().any(|log| log.block_hash.map(|hash| !hashes.contains(&hash)).unwrap_or(true)) {
+            println!("logs do not match the fetched blocks, skipping update");
+            return Ok(());
+        }
+
+        if ancestor < last {
+            println!("chain reorganised, rolling back {} blocks", (last - ancestor).as_u64());
+            self.rollback(ancestor);
+        }
+        for header in &headers {
+            let block_logs: Vec<Log> = logs
+                .iter()
+                .filter(|log| log.block_hash == Some(header.hash))
+                .cloned()
+                .collect();
+            if let Err(e) = self.apply_block(header, &block_logs) {
+                println!("failed to apply block {}: {}, refetching pools", header.number, e);
+                return self.bootstrap(provider, multicall, block).await;
+            }
+        }
+
+        // refetch the pools whose logs could not be applied
+        let stale: Vec<usize> = (0..self.pools.len()).filter(|&id| self.pools[id].is_stale()).collect();
+        if !stale.is_empty() {
+            for (id, pool) in self.fetch(&stale, multicall, block).await? {
+                self.replace(id, pool);
+            }
+        }
+        Ok(())
+    }
+
+    /// Refetches all the pools at block number `block`, marks them dirty and resets the journal.
+    async fn bootstrap(
+        &mut self,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        multicall: &Multicall<'_>,
+        block: U64,
+    ) -> BotResult<()> {
+        let header = BlockHeader::fetch(provider, block).await?;
+        let ids: Vec<usize> = (0..self.pools.len()).collect();
+        let pools = self.fetch(&ids, multicall, block).await?;
+        self.journal.clear();
+        for (id, pool) in pools {
+            self.replace(id, pool);
+        }
+        self.journal.push_back(JournalEntry { header, undo: HashMap::new() });
+        Ok(())
+    }
+
+    /// Refreshes copies of the pools `ids` at block number `block`, batching each round of every pool through `multicall`.
+    async fn fetch(
+        &self,
+        ids: &[usize],
+        multicall: &Multicall<'_>,
+        block: U64,
+    ) -> BotResult<Vec<(usize, Box<dyn Pool>)>> {
+        let mut pools: Vec<(usize, Box<dyn Pool>)> = ids
+            .iter()
+            .map(|&id| {
+                let mut pool = self.pools[id].clone();
+                pool.invalidate();
+                (id, pool)
+            })
+            .collect();
+        while pools.iter().any(|(_, pool)| pool.is_stale()) {
+            let mut calls: Vec<(Address, Bytes)> = Vec::new();
+            let mut counts: Vec<usize> = Vec::with_capacity(pools.len());
+            for (_, pool) in &pools {
+                let pool_calls = if pool.is_stale() { pool.refresh_calls()? } else { Vec::new() };
+                counts.push(pool_calls.len());
+                calls.extend(pool_calls);
+            }
+            let returns = multicall.aggregate(&calls, block).await?;
+            let mut start = 0;
+            for ((_, pool), count) in pools.iter_mut().zip(counts) {
+                if pool.is_stale() {
+                    pool.apply_refresh(&returns[start..start + count])?;
+                }
+                start += count;
+            }
+        }
+        Ok(pools)
+    }
+
+    /// Fetches the state changing logs of the tracked pools between blocks `from_block` and `to_block` included,
+    /// in the order they were emitted.
+    pub async fn fetch_logs(
+        &self,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        from_block: U64,
+        to_block: U64,
+    ) -> BotResult<Vec<Log>> {
+        let mut topics: Vec<H256> = Vec::new();
+        for pool in &self.pools {
+            for topic in pool.event_topics()? {
+                if !topics.contains(&topic) {
+                    topics.push(topic);
+                }
+            }
+        }
+        let filter = Filter::new()
+            .address(self.pool_ids.keys().copied().collect::<Vec<Address>>())
+            .topic0(topics)
+            .from_block(from_block)
+            .to_block(to_block);
+        let mut logs = provider
+            .get_logs(&filter)
+            .await
+            .map_err(|e| BotError::Rpc(format!("`eth_getLogs` asynchronous call failed: {}", e)))?;
+        logs.sort_by_key(|log| (log.block_number, log.log_index));
+        Ok(logs)
+    }
+
+    /// Starts tracking `pool`, returns its id.
+    pub fn insert(&mut self, pool: Box<dyn Pool>) -> usize {
+        let id = self.pools.len();
+        self.pool_ids.entry(pool.log_address()).or_default().push(id);
+        self.pools.push(pool);
+        self.dirty.insert(id);
+        id
+    }
+
+    /// Replaces the pool `id`, journaling the previous one in the last applied block.
+    pub fn replace(&mut self, id: usize, pool: Box<dyn Pool>) {
+        if let Some(entry) = self.journal.back_mut() {
+            entry.undo.entry(id).or_insert_with(|| self.pools[id].clone());
+        }
+        self.pools[id] = pool;
+        self.dirty.insert(id);
+    }
+
+    /// Applies the logs of the block with `header`, which must be the child of the last applied block,
+    /// and journals the pools it changes.
+    /// If a log fails to apply, the pools are left as they were before the block.
+    pub fn apply_block(&mut self, header: &BlockHeader, logs: &[Log]) -> BotResult<()> {
+        let mut undo: HashMap<usize, Box<dyn Pool>> = HashMap::new();
+        for log in logs {
+            if let Some(ids) = self.pool_ids.get(&log.address) {
+                for &id in ids {
+                    if let Entry::Vacant(entry) = undo.entry(id) {
+                        entry.insert(self.pools[id].clone());
+                    }
+                }
+                if let Err(e) = self.apply_log(log) {
+                    for (id, pool) in undo {
+                        self.pools[id] = pool;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        self.journal.push_back(JournalEntry { header: *header, undo });
+        while self.journal.len() > JOURNAL_DEPTH {
+            self.journal.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Returns the number of the most recent journaled block whose hash matches `canonical`,
+    /// the canonical hashes by block number. `None` if the reorg is deeper than the journal.
+    pub fn common_ancestor(&self, canonical: &HashMap<U64, H256>) -> Option<U64> {
+        self.journal
+            .iter()
+            .rev()
+            .find(|entry| canonical.get(&entry.header.number) == Some(&entry.header.hash))
+            .map(|entry| entry.header.number)
+    }
+
+    /// Undoes the blocks applied after block number `number` and marks the pools they changed dirty.
+    pub fn rollback(&mut self, number: U64) {
+        while self.journal.back().map(|entry| entry.header.number > number).unwrap_or(false) {
+            let entry = self.journal.pop_back().expect("Journal is not empty");
+            for (id, pool) in entry.undo {
+                self.pools[id] = pool;
+                self.dirty.insert(id);
+            }
+        }
+    }
+
+    /// Applies `log` to the pools whose logs are emitted by its address, marks the pools dirty if they changed.
+    /// Returns the id of the changed pool, the last one if several changed.
+    pub fn apply_log(&mut self, log: &Log) -> BotResult<Option<usize>> {
+        let ids = match self.pool_ids.get(&log.address) {
+            Some(ids) => ids.clone(),
+            None => return Ok(None),
+        };
+        let mut changed = None;
+        for id in ids {
+            if self.pools[id].apply_log(log)? {
+                self.dirty.insert(id);
+                changed = Some(id);
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Returns the ids of the pools that changed since the last call, sorted.
+    pub fn take_dirty(&mut self) -> Vec<usize> {
+        let mut dirty: Vec<usize> = self.dirty.drain().collect();
+        dirty.sort_unstable();
+        dirty
+    }
+}
+
+impl Default for PoolTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
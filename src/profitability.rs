@@ -0,0 +1,243 @@
+use ethers::types::{ U256, I256, };
+use std::convert::TryFrom;
+
+use crate::graph::{ Graph, Edge, };
+use crate::univ3::Token;
+use crate::optimizer::CycleTrade;
+use crate::pool::{ Pool, SwapPayment, };
+
+//------------------------------------- gas estimates
+
+/// Intrinsic gas of a transaction.
+pub const TX_GAS: u64 = 21_000;
+/// Gas of `FlashArbExecutor.execute()` on top of its swaps when flash swapping on the first hop: calldata, balance checks.
+pub const FLASH_SWAP_GAS: u64 = 40_000;
+/// Gas of `FlashArbExecutor.execute()` on top of its swaps when borrowing with `flash()`: the loan pool's lock, transfers and callback.
+pub const FLASH_LOAN_GAS: u64 = 90_000;
+/// Gas of `FlashArbExecutor.execute()` reading the balance of the input token of a hop after the first one,
+/// before and after the cycle, to check that the tokens it holds did not pay for a hop.
+pub const INVENTORY_CHECK_GAS: u64 = 6_000;
+/// Gas of a Uniswap V3 swap staying within its initialized tick.
+pub const V3_SWAP_GAS: u64 = 100_000;
+/// Gas of an Algebra swap staying within its initialized tick, which also writes a timepoint and may update the fee.
+pub const ALGEBRA_SWAP_GAS: u64 = 130_000;
+/// Extra gas of a concentrated liquidity swap for every initialized tick it crosses.
+pub const TICK_CROSS_GAS: u64 = 25_000;
+/// Gas of a Uniswap V2 swap, including the transfer of its input.
+pub const V2_SWAP_GAS: u64 = 70_000;
+/// Gas of a Curve `exchange()`, including the approval of its input.
+pub const CURVE_SWAP_GAS: u64 = 150_000;
+/// Gas of a Balancer `Vault.swap()`, including the approval of its input.
+pub const BALANCER_SWAP_GAS: u64 = 120_000;
+
+/// Maximum number of hops of the path converting the native token into the start token of a cycle.
+pub const MAX_PRICE_HOPS: usize = 3;
+
+/// Returns the gas used by the executor to swap `trade` through `cycle`, whose edge ids are indices in `pools`.
+/// The start token is flash swapped when the first hop is paid in callback, else borrowed with `flash()`.
+pub fn cycle_gas(pools: &[Box<dyn Pool>], cycle: &[(usize, Edge)], trade: &CycleTrade) -> u64 {
+    let flash_gas = match cycle.first() {
+        Some((_, edge)) if pools[edge.edge_id].swap_payment() == SwapPayment::Callback => FLASH_SWAP_GAS,
+        _ => FLASH_LOAN_GAS,
+    };
+    let swaps_gas: u64 = cycle
+        .iter()
+        .enumerate()
+        .map(|(i, &(from, Edge { to, edge_id: pool_id, .. }))| {
+            let pool = &pools[pool_id];
+            let token_ids = pool.tokens();
+            let coin = |token_id: usize| token_ids.iter().position(|&id| id == token_id).unwrap_or_default();
+            pool.swap_gas(coin(from), coin(to), trade.amounts[i])
+        })
+        .sum();
+    let inventory_gas = INVENTORY_CHECK_GAS * cycle.len().saturating_sub(1) as u64;
+    TX_GAS + flash_gas + inventory_gas + swaps_gas
+}
+
+//------------------------------------- Loan
+
+/// How the executor borrows the start token of a cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Loan {
+    /// Flash swap on the first hop, paid back by the cycle without fee.
+    FlashSwap,
+    /// `flash()` on pool `pool_id`, outside of the cycle, for a fee of `fee` of the start token.
+    Flash { pool_id: usize, fee: U256 },
+}
+
+impl Loan {
+    /// Fee of the loan, in the start token.
+    pub fn fee(&self) -> U256 {
+        match self {
+            Loan::FlashSwap => U256::zero(),
+            Loan::Flash { fee, .. } => *fee,
+        }
+    }
+}
+
+/// Returns how the executor borrows `amount` of the start token of `cycle`, whose edge ids are indices in `pools`:
+/// flash swapped on the first hop if it is paid in callback, else lent by the pool outside of the cycle with the lowest fee.
+/// `None` if no pool can lend it.
+pub fn cycle_loan(pools: &[Box<dyn Pool>], cycle: &[(usize, Edge)], amount: U256) -> Option<Loan> {
+    let (start, first) = cycle.first()?;
+    if pools[first.edge_id].swap_payment() == SwapPayment::Callback {
+        return Some(Loan::FlashSwap);
+    }
+    pools
+        .iter()
+        .enumerate()
+        .filter(|(pool_id, pool)| pool.tokens().contains(start) && cycle.iter().all(|(_, edge)| edge.edge_id != *pool_id))
+        .filter_map(|(pool_id, pool)| Some(Loan::Flash { pool_id, fee: pool.flash_fee(amount)? }))
+        .min_by_key(Loan::fee)
+}
+
+//------------------------------------- GasPricer
+
+/// Prices gas in any token, converting its cost in native token through the prices of the graph.
+#[derive(Debug, Clone)]
+pub struct GasPricer {
+    /// Price of a unit of gas, in wei.
+    pub gas_price: U256,
+    native_decimals: u8,
+    /// Weight of the lightest path of at most `MAX_PRICE_HOPS` edges from the wrapped native token to each token,
+    /// the negative log of the best rate converting it.
+    dists: Vec<f64>,
+}
+
+impl GasPricer {
+    /// `native` is the id of the wrapped native token, `None` if it is not among `tokens`, in which case gas cannot be priced.
+    pub fn new(graph: &Graph, tokens: &[Token], native: Option<usize>, gas_price: U256) -> Self {
+        let (native_decimals, dists) = match native {
+            Some(native) => {
+                let mut dists = graph.bounded_distances(native, MAX_PRICE_HOPS);
+                // a cycle through the native token would price it against itself
+                dists[native] = 0.;
+                (tokens[native].decimals, dists)
+            },
+            None => (18, vec![f64::INFINITY; graph.node_count()]),
+        };
+        Self { gas_price, native_decimals, dists }
+    }
+
+    /// Returns the cost of `gas` in raw units of `token`, `None` if no path converts the native token into it.
+    pub fn gas_cost(&self, gas: u64, token: &Token) -> Option<U256> {
+        self.gas_cost_at(gas, self.gas_price, token)
+    }
+
+    /// Returns the cost of `gas` at `gas_price` wei per gas in raw units of `token`, `None` if no path converts the native token into it.
+    pub fn gas_cost_at(&self, gas: u64, gas_price: U256, token: &Token) -> Option<U256> {
+        let cost_wei = gas_price.checked_mul(U256::from(gas))?;
+        f64_to_u256((u256_to_f64(cost_wei) * self.rate(token)?).ceil())
+    }
+
+    /// Returns the value of `amount` raw units of `token` in wei, `None` if no path converts the native token into it.
+    pub fn native_value(&self, amount: U256, token: &Token) -> Option<U256> {
+        f64_to_u256((u256_to_f64(amount) / self.rate(token)?).floor())
+    }
+
+    /// Raw units of `token` per wei.
+    fn rate(&self, token: &Token) -> Option<f64> {
+        let dist = *self.dists.get(token.token_id)?;
+        if !dist.is_finite() {
+            return None;
+        }
+        Some((-dist).exp() * 10f64.powi(token.decimals as i32 - self.native_decimals as i32))
+    }
+}
+
+/// Returns `trade` with its profit net of `gas_cost`, or of any other cost of the cycle, in the start token, `None` if it is not positive.
+pub fn net_of_gas(mut trade: CycleTrade, gas_cost: U256) -> Option<CycleTrade> {
+    trade.profit = trade.profit.checked_sub(I256::try_from(gas_cost).ok()?)?;
+    if trade.profit.is_positive() {
+        Some(trade)
+    } else {
+        None
+    }
+}
+
+fn u256_to_f64(value: U256) -> f64 {
+    value.to_string().parse::<f64>().unwrap_or(f64::INFINITY)
+}
+
+fn f64_to_u256(value: f64) -> Option<U256> {
+    if !value.is_finite() || value < 0. || value >= u256_to_f64(U256::MAX) {
+        return None;
+    }
+    U256::from_dec_str(&format!("{:.0}", value)).ok()
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::Address;
+    use crate::testing::{ tokens, univ2_pool, univ3_pool, };
+
+    #[test]
+    fn test_gas_cost() {
+        // 1 native = 2000 token 1, 1 token 1 = 0.00005 token 2, token 2 is priced through token 1
+        let mut graph = Graph::new(3);
+        graph.add_edge(0, 1, -(2000f64).ln(), 0);
+        graph.add_edge(1, 2, -(0.00005f64).ln(), 1);
+        let tokens = tokens(&[18, 6, 8]);
+        let pricer = GasPricer::new(&graph, &tokens, Some(0), U256::exp10(10));
+
+        // 100k gas at 10 gwei is 0.001 native
+        assert_eq!(pricer.gas_cost(100_000, &tokens[0]), Some(U256::exp10(15)));
+        let cost = pricer.gas_cost(100_000, &tokens[1]).unwrap();
+        assert!(cost.as_u64().abs_diff(2_000_000) <= 1);
+        let cost = pricer.gas_cost(100_000, &tokens[2]).unwrap();
+        assert!(cost.as_u64().abs_diff(10_000) <= 1);
+
+        assert_eq!(pricer.native_value(U256::from(2_000_000), &tokens[1]), Some(U256::exp10(15)));
+        // with a priority fee of 5 gwei on top
+        assert_eq!(pricer.gas_cost_at(100_000, U256::exp10(10) * 3 / 2, &tokens[0]), Some(U256::exp10(15) * 3 / 2));
+
+        // no wrapped native token
+        let pricer = GasPricer::new(&graph, &tokens, None, U256::exp10(10));
+        assert_eq!(pricer.gas_cost(100_000, &tokens[1]), None);
+    }
+
+    #[test]
+    fn test_cycle_gas() {
+        let tokens = tokens(&[18, 6, 8]);
+        let pools: Vec<Box<dyn Pool>> = vec![
+            Box::new(univ2_pool(Address::repeat_byte(1), 0, (0, 1), None, &tokens)),
+            Box::new(univ3_pool(Address::repeat_byte(2), 1, (0, 1), 500., &tokens)),
+        ];
+        let trade = CycleTrade {
+            amounts: vec![U256::from(1000), U256::from(990), U256::from(1010)],
+            profit: I256::from(10),
+        };
+
+        // the V3 pool is not fetched, no tick crossing is counted
+        let cycle = [(0, Edge::new(1, 0., 1)), (1, Edge::new(0, 0., 0))];
+        assert_eq!(cycle_gas(&pools, &cycle, &trade), TX_GAS + FLASH_SWAP_GAS + INVENTORY_CHECK_GAS + V3_SWAP_GAS + V2_SWAP_GAS);
+        let cycle = [(0, Edge::new(1, 0., 0)), (1, Edge::new(0, 0., 1))];
+        assert_eq!(cycle_gas(&pools, &cycle, &trade), TX_GAS + FLASH_LOAN_GAS + INVENTORY_CHECK_GAS + V3_SWAP_GAS + V2_SWAP_GAS);
+
+        assert_eq!(net_of_gas(trade.clone(), U256::from(4)).unwrap().profit, I256::from(6));
+        assert_eq!(net_of_gas(trade, U256::from(10)), None);
+    }
+
+    #[test]
+    fn test_cycle_loan() {
+        let tokens = tokens(&[18, 6, 8]);
+        let pair = |id: u8| -> Box<dyn Pool> { Box::new(univ2_pool(Address::repeat_byte(id), id as usize, (0, 1), None, &tokens)) };
+        let pool = |id: u8, fee: f32| -> Box<dyn Pool> { Box::new(univ3_pool(Address::repeat_byte(id), id as usize, (0, 1), fee, &tokens)) };
+        let pools = vec![pair(0), pair(1), pool(2, 3000.), pool(3, 500.), pool(4, 500.)];
+        let amount = U256::from(1_000_001);
+
+        // the cheapest pool outside of the cycle lends, the fee rounded up
+        let cycle = [(0, Edge::new(1, 0., 0)), (1, Edge::new(0, 0., 1))];
+        assert_eq!(cycle_loan(&pools, &cycle, amount), Some(Loan::Flash { pool_id: 3, fee: U256::from(501) }));
+        let cycle = [(0, Edge::new(1, 0., 0)), (1, Edge::new(0, 0., 3))];
+        assert_eq!(cycle_loan(&pools, &cycle, amount), Some(Loan::Flash { pool_id: 4, fee: U256::from(501) }));
+        // a flash swap on the first hop is free
+        let cycle = [(0, Edge::new(1, 0., 2)), (1, Edge::new(0, 0., 0))];
+        assert_eq!(cycle_loan(&pools, &cycle, amount).map(|loan| loan.fee()), Some(U256::zero()));
+        // V2 pairs cannot lend
+        assert_eq!(cycle_loan(&pools[..2], &[(0, Edge::new(1, 0., 0)), (1, Edge::new(0, 0., 1))], amount), None);
+    }
+}
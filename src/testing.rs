@@ -0,0 +1,106 @@
+use ethers::{
+    contract::BaseContract,
+    types::{ Address, U256, },
+};
+use serde_json::{ json, Value, };
+use std::sync::{ Arc, Mutex, };
+use tokio::{
+    io::{ AsyncReadExt, AsyncWriteExt, },
+    net::TcpListener,
+};
+
+use crate::utils::*;
+use crate::univ3::{ Token, PoolImmutables, Univ3Pool, TICK_BITMAP_WORDS, };
+use crate::univ2::{ Univ2Pair, Univ2Pool, PairSnapshot, };
+
+//------------------------------------- fixtures
+
+/// Tokens of `decimals`, with ids in the same order and token `i` at address `i + 1`.
+pub fn tokens(decimals: &[u8]) -> Vec<Token> {
+    decimals
+        .iter()
+        .enumerate()
+        .map(|(token_id, &decimals)| Token { address: Address::from_low_u64_be(token_id as u64 + 1), decimals, token_id, ..Default::default() })
+        .collect()
+}
+
+/// Uniswap V2 pair `pool_id` of 0.3% fee between `token_0_id` and `token_1_id`, fetched with `reserves` if any.
+pub fn univ2_pool(
+    address: Address,
+    pool_id: usize,
+    (token_0_id, token_1_id): (usize, usize),
+    reserves: Option<(u128, u128)>,
+    tokens: &[Token],
+) -> Univ2Pool {
+    let pair_abi = Arc::new(BaseContract::from(i_univ2_pair_abi().unwrap()));
+    let mut pool = Univ2Pool::new(Univ2Pair::new(address, pool_id, token_0_id, token_1_id, 3000), tokens, pair_abi).unwrap();
+    pool.snapshot = reserves.map(|(reserve_0, reserve_1)| PairSnapshot {
+        reserve_0: U256::from(reserve_0),
+        reserve_1: U256::from(reserve_1),
+        fee: 3000,
+    });
+    pool
+}
+
+/// Uniswap V3 pool `pool_id` of `fee` between `token_0_id` and `token_1_id`, with a tick spacing of 10, yet to be fetched.
+pub fn univ3_pool(address: Address, pool_id: usize, (token_0_id, token_1_id): (usize, usize), fee: f32, tokens: &[Token]) -> Univ3Pool {
+    let pool_abi = Arc::new(BaseContract::from(i_univ3_pool_abi().unwrap()));
+    let immutables = PoolImmutables::new(address, pool_id, token_0_id, token_1_id, fee, 10., 0.);
+    Univ3Pool::new(immutables, tokens, TICK_BITMAP_WORDS, pool_abi).unwrap()
+}
+
+//------------------------------------- mock_rpc
+
+/// Requests received by `mock_rpc`: the `X-Flashbots-Signature` header, empty if absent, and the raw body.
+pub type Received = Arc<Mutex<Vec<(String, String)>>>;
+
+/// Serves a JSON-RPC endpoint on a local port, e.g. a node or a relay, and returns its url.
+/// Each request is answered with the result `Ok` or the error `Err` of `respond(body)`.
+pub async fn mock_rpc(respond: fn(&Value) -> Result<Value, Value>) -> (String, Received) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let received: Received = Arc::new(Mutex::new(Vec::new()));
+    let log = received.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            // read the headers, then a body of `Content-Length` bytes
+            let mut request: Vec<u8> = Vec::new();
+            let mut buf = [0u8; 4096];
+            let (head, body) = loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let head = text[..end].to_lowercase();
+                    let length: usize = head
+                        .lines()
+                        .find_map(|line| line.strip_prefix("content-length:"))
+                        .map(|length| length.trim().parse().unwrap())
+                        .unwrap_or_default();
+                    if request.len() >= end + 4 + length {
+                        break (text[..end].to_string(), text[end + 4..end + 4 + length].to_string());
+                    }
+                }
+            };
+            let signature = head
+                .lines()
+                .find_map(|line| line.to_lowercase().starts_with("x-flashbots-signature:").then(|| line[22..].trim().to_string()))
+                .unwrap_or_default();
+            let request: Value = serde_json::from_str(&body).unwrap();
+            let response = match respond(&request) {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }),
+                Err(error) => json!({ "jsonrpc": "2.0", "id": request["id"], "error": error }),
+            }
+            .to_string();
+            log.lock().unwrap().push((signature, body));
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(),
+                response,
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+    (url, received)
+}
@@ -0,0 +1,235 @@
+use ethers::{
+    middleware::SignerMiddleware,
+    providers::{Http, Provider, Middleware},
+    signers::LocalWallet,
+    types::{ Address, Bytes, H256, },
+    contract::{ BaseContract, AbiError, },
+};
+use std::{
+    fs::{ File, read_to_string, },
+    io::Write,
+    collections::HashMap,
+};
+
+use crate::univ3::Token;
+use crate::multicall::Multicall;
+use crate::utils::i_erc20_metadata_abi;
+use crate::error::{ BotError, BotResult, };
+
+/// Master list of the tokens of every chain, from which the token file of each chain is built.
+pub const MASTER_TOKENS_PATH: &str = "config/raw/allTokens.json";
+
+/// Returns the path of the token file of network with chain id `chain_id`.
+pub fn tokens_path(chain_id: u32) -> String {
+    format!("config/{}/tokens.json", chain_id)
+}
+
+//------------------------------------- build
+
+/// Builds `config/{chain_id}/tokens.json` from the tokens of network with chain id `chain_id` in the master list at `master_path`,
+/// once their `decimals()` and `symbol()` are checked on-chain against the list. Returns the tokens written.
+pub async fn build_tokens(
+    chain_id: u32,
+    provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+    chunk_size: usize,
+    master_path: &str,
+) -> BotResult<Vec<Token>> {
+    let master: Vec<Token> = serde_json::from_str(&read(master_path)?)
+        .map_err(|e| BotError::Config(format!("Failed to extract tokens from json {}: {}", master_path, e)))?;
+    let path = tokens_path(chain_id);
+    let tokens = assign_token_ids(chain_id, master, read_chain_tokens(&path)?)?;
+    if tokens.is_empty() {
+        return Err(BotError::Config(format!("No token of chain id {} in {}", chain_id, master_path)));
+    }
+
+    let block = provider
+        .get_block_number()
+        .await
+        .map_err(|e| BotError::Rpc(format!("`eth_blockNumber` asynchronous call failed: {}", e)))?;
+    let multicall = Multicall::new(chain_id, provider, chunk_size)?;
+    let erc20_abi = BaseContract::from(i_erc20_metadata_abi()?);
+    let encode_error = |name: &'static str| move |e: AbiError| BotError::Abi(format!("`{}()` method not found in ABI: {}", name, e));
+    let calls: Vec<(Address, Bytes)> = tokens
+        .iter()
+        .map(|token| Ok(vec![
+            (token.address, erc20_abi.encode("decimals", ()).map_err(encode_error("ERC20.decimals"))?),
+            (token.address, erc20_abi.encode("symbol", ()).map_err(encode_error("ERC20.symbol"))?),
+        ]))
+        .collect::<BotResult<Vec<Vec<(Address, Bytes)>>>>()?
+        .concat();
+    let returns = multicall.aggregate(&calls, block).await?;
+
+    let mut mismatches: Vec<String> = Vec::new();
+    for (token, data) in tokens.iter().zip(returns.chunks(2)) {
+        let decimals: Option<u8> = data[0].clone().and_then(|data| erc20_abi.decode_output("decimals", data).ok());
+        let symbol = data[1].as_ref().and_then(|data| decode_symbol(&erc20_abi, data));
+        match (decimals, symbol) {
+            (Some(decimals), Some(symbol)) if decimals == token.decimals && symbol == token.symbol => {},
+            (Some(decimals), Some(symbol)) => mismatches.push(format!(
+                "{} {:?}: listed with {} decimals and symbol {}, has {} decimals and symbol {} on-chain",
+                token.token_id, token.address, token.decimals, token.symbol, decimals, symbol,
+            )),
+            _ => mismatches.push(format!("{} {:?} ({}): `decimals()` or `symbol()` failed", token.token_id, token.address, token.symbol)),
+        }
+    }
+    if !mismatches.is_empty() {
+        return Err(BotError::Config(format!(
+            "{} tokens of {} do not match the chain at block {}, {} left unchanged:\n{}",
+            mismatches.len(), master_path, block, path, mismatches.join("\n"),
+        )));
+    }
+
+    write_tokens(&path, &tokens)?;
+    Ok(tokens)
+}
+
+/// Returns the tokens of network with chain id `chain_id` in `master`, with the ids of the tokens already in `existing`,
+/// the token file of the chain, the new tokens being appended in the order of `master`.
+/// The ids of `existing` are kept since the pools reference tokens by id, so all of its tokens must be in `master`.
+pub fn assign_token_ids(chain_id: u32, master: Vec<Token>, existing: Vec<Token>) -> BotResult<Vec<Token>> {
+    let mut ids: HashMap<Address, usize> = HashMap::new();
+    for (i, token) in existing.iter().enumerate() {
+        if token.token_id != i {
+            return Err(BotError::Config(format!("Token {:?} has id {} at position {} of the token file", token.address, token.token_id, i)));
+        }
+        if ids.insert(token.address, i).is_some() {
+            return Err(BotError::Config(format!("Token {:?} is twice in the token file", token.address)));
+        }
+    }
+
+    let mut tokens: Vec<Option<Token>> = vec![None; existing.len()];
+    for mut token in master.into_iter().filter(|token| token.chain_id == chain_id) {
+        let token_id = match ids.get(&token.address) {
+            Some(&token_id) => token_id,
+            None => {
+                tokens.push(None);
+                ids.insert(token.address, tokens.len() - 1);
+                tokens.len() - 1
+            },
+        };
+        if tokens[token_id].is_some() {
+            return Err(BotError::Config(format!("Token {:?} is twice in the master list", token.address)));
+        }
+        token.token_id = token_id;
+        tokens[token_id] = Some(token);
+    }
+
+    tokens
+        .into_iter()
+        .enumerate()
+        .map(|(token_id, token)| token.ok_or_else(|| BotError::Config(format!(
+            "Token {} {:?} ({}) of the token file is not in the master list",
+            token_id, existing[token_id].address, existing[token_id].symbol,
+        ))))
+        .collect::<BotResult<Vec<Token>>>()
+}
+
+/// Decodes the return data of `symbol()`, a string or, for older tokens like MKR, a null-padded `bytes32`.
+fn decode_symbol(erc20_abi: &BaseContract, data: &Bytes) -> Option<String> {
+    if let Ok(symbol) = erc20_abi.decode_output::<String, _>("symbol", data) {
+        return Some(symbol);
+    }
+    if data.len() != 32 {
+        return None;
+    }
+    let symbol = H256::from_slice(data);
+    let len = symbol.as_bytes().iter().position(|&byte| byte == 0).unwrap_or(32);
+    String::from_utf8(symbol.as_bytes()[..len].to_vec()).ok()
+}
+
+/// Reads the token file at `path`, with no token if it does not exist or is empty.
+fn read_chain_tokens(path: &str) -> BotResult<Vec<Token>> {
+    match read_to_string(path) {
+        Ok(content) if content.trim().is_empty() => Ok(Vec::new()),
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| BotError::Config(format!("Failed to extract tokens from json {}: {}", path, e))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(BotError::Config(format!("Failed to read file {}: {}", path, e))),
+    }
+}
+
+fn read(path: &str) -> BotResult<String> {
+    read_to_string(path).map_err(|e| BotError::Config(format!("Failed to read file {}: {}", path, e)))
+}
+
+/// Writes `tokens` to the json file at `path` through a temporary file renamed over it,
+/// so that the bot never reads a partly written token file.
+pub fn write_tokens(path: &str, tokens: &[Token]) -> BotResult<()> {
+    let serialized_tokens = serde_json::to_string_pretty(tokens)
+        .map_err(|e| BotError::Config(format!("Failed to serialize tokens: {}", e)))?;
+    let temp_path = format!("{}.tmp", path);
+    File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(serialized_tokens.as_bytes())?;
+            file.write_all(b"\n")?;
+            file.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&temp_path, path))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&temp_path);
+            BotError::Config(format!("Failed to write tokens to file {}: {}", path, e))
+        })
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(chain_id: u32, address: u8, symbol: &str, token_id: usize) -> Token {
+        Token {
+            chain_id,
+            address: Address::repeat_byte(address),
+            symbol: symbol.to_string(),
+            decimals: 18,
+            token_id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_assign_token_ids() {
+        let master = vec![token(1, 1, "A", 7), token(137, 2, "B", 0), token(1, 3, "C", 7), token(1, 4, "D", 7)];
+
+        // built from scratch, in the order of the master list
+        let tokens = assign_token_ids(1, master.clone(), Vec::new()).unwrap();
+        assert_eq!(tokens, vec![token(1, 1, "A", 0), token(1, 3, "C", 1), token(1, 4, "D", 2)]);
+
+        // the ids of the token file are kept, the new tokens are appended
+        let existing = vec![token(1, 4, "D", 0), token(1, 1, "A", 1)];
+        let tokens = assign_token_ids(1, master.clone(), existing).unwrap();
+        assert_eq!(tokens, vec![token(1, 4, "D", 0), token(1, 1, "A", 1), token(1, 3, "C", 2)]);
+
+        // a token of the token file missing from the master list would shift the ids of the pools
+        assert!(assign_token_ids(1, master.clone(), vec![token(1, 9, "Z", 0)]).is_err());
+        assert!(assign_token_ids(1, master.clone(), vec![token(1, 1, "A", 1)]).is_err());
+        let mut duplicated = master;
+        duplicated.push(token(1, 3, "C", 7));
+        assert!(assign_token_ids(1, duplicated, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_decode_symbol() {
+        let erc20_abi = BaseContract::from(i_erc20_metadata_abi().unwrap());
+        let data = Bytes::from(ethers::abi::encode(&[ethers::abi::Token::String("WETH".to_string())]));
+        assert_eq!(decode_symbol(&erc20_abi, &data), Some("WETH".to_string()));
+        let mut mkr = [0u8; 32];
+        mkr[..3].copy_from_slice(b"MKR");
+        assert_eq!(decode_symbol(&erc20_abi, &Bytes::from(mkr.to_vec())), Some("MKR".to_string()));
+        assert_eq!(decode_symbol(&erc20_abi, &Bytes::from(vec![1; 3])), None);
+    }
+
+    #[test]
+    fn test_write_tokens() {
+        let path = std::env::temp_dir().join(format!("tokens_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let tokens = vec![token(1, 1, "A", 0), token(1, 3, "C", 1)];
+        write_tokens(path, &tokens).unwrap();
+        assert_eq!(read_chain_tokens(path).unwrap(), tokens);
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+        std::fs::write(path, "").unwrap();
+        assert!(read_chain_tokens(path).unwrap().is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+}
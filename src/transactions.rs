@@ -0,0 +1,680 @@
+use ethers::{
+    middleware::SignerMiddleware,
+    providers::{Http, Provider, Middleware},
+    signers::{ LocalWallet, Signer, },
+    types::{ Address, Bytes, U256, I256, U64, H256, BlockNumber, Eip1559TransactionRequest, TransactionReceipt,
+        transaction::eip2718::TypedTransaction, },
+    utils::keccak256,
+};
+use serde::Serialize;
+use std::{
+    fs::OpenOptions,
+    io::Write,
+};
+
+use crate::executor::{ Executor, Simulation, };
+use crate::nonce::{ NonceManager, is_nonce_error, };
+use crate::error::{ BotError, BotResult, };
+
+/// Number of blocks whose priority fees are sampled by `PriorityFeeStrategy::Percentile`.
+pub const FEE_HISTORY_BLOCKS: u64 = 10;
+
+/// Number of blocks a transaction stays pending before its fees are bumped.
+pub const REPLACE_AFTER_BLOCKS: u64 = 2;
+
+/// Minimum increase of both fees of a replacement transaction, in basis points, required by the nodes to accept it.
+pub const REPLACEMENT_BUMP_BPS: u64 = 1_250;
+
+//------------------------------------- PriorityFeeStrategy
+
+/// How the priority fee of the executor transactions is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriorityFeeStrategy {
+    /// Fixed priority fee, in wei per gas.
+    Fixed(u64),
+    /// Percentile of the priority fees paid in the last `FEE_HISTORY_BLOCKS` blocks.
+    Percentile(u8),
+    /// Share of the expected profit of the cycle, in basis points, paid as priority fee.
+    ProfitShare(u64),
+}
+
+impl PriorityFeeStrategy {
+    /// Reads `PRIORITY_FEE_STRATEGY` from .env, one of `fixed` (the default), `percentile` and `profit`,
+    /// along with its parameter: `PRIORITY_FEE_WEI` (default 1 gwei), `PRIORITY_FEE_PERCENTILE` (default 50)
+    /// or `PRIORITY_FEE_PROFIT_BPS` (default 10%).
+    pub fn from_env() -> BotResult<Self> {
+        Self::parse(|key| dotenv::var(key).ok())
+    }
+
+    /// `from_env` with the variables looked up by `var`.
+    pub fn parse(var: impl Fn(&str) -> Option<String>) -> BotResult<Self> {
+        let read = |key: &str, default: u64| -> BotResult<u64> {
+            match var(key) {
+                Some(value) => value
+                    .parse::<u64>()
+                    .map_err(|_| BotError::Config(format!("{} must be a positive integer, got {}.", key, value))),
+                None => Ok(default),
+            }
+        };
+        match var("PRIORITY_FEE_STRATEGY").as_deref() {
+            None | Some("fixed") => Ok(PriorityFeeStrategy::Fixed(read("PRIORITY_FEE_WEI", 1_000_000_000)?)),
+            Some("percentile") => match read("PRIORITY_FEE_PERCENTILE", 50)? {
+                percentile if percentile <= 100 => Ok(PriorityFeeStrategy::Percentile(percentile as u8)),
+                percentile => Err(BotError::Config(format!("PRIORITY_FEE_PERCENTILE must be at most 100, got {}.", percentile))),
+            },
+            Some("profit") => match read("PRIORITY_FEE_PROFIT_BPS", 1_000)? {
+                bps if bps < 10_000 => Ok(PriorityFeeStrategy::ProfitShare(bps)),
+                bps => Err(BotError::Config(format!("PRIORITY_FEE_PROFIT_BPS must be below 10000, got {}.", bps))),
+            },
+            Some(strategy) => Err(BotError::Config(format!("Invalid PRIORITY_FEE_STRATEGY {}. Must be one of 'fixed', 'percentile', 'profit'.", strategy))),
+        }
+    }
+}
+
+//------------------------------------- Fees
+
+/// Base fee of a block along with the priority fee to pay on top of it, in wei per gas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fees {
+    pub base_fee: U256,
+    pub priority_fee: U256,
+}
+
+impl Fees {
+    /// Gas price paid if the transaction is included in the next block.
+    pub fn gas_price(&self) -> U256 {
+        self.base_fee + self.priority_fee
+    }
+
+    /// Maximum fee per gas, leaving room for the base fee to double before the transaction is priced out.
+    pub fn max_fee(&self) -> U256 {
+        self.base_fee * 2 + self.priority_fee
+    }
+}
+
+/// Returns `fee` increased enough for a replacement transaction.
+fn bump(fee: U256) -> U256 {
+    fee + fee * REPLACEMENT_BUMP_BPS / 10_000 + 1
+}
+
+//------------------------------------- TxRecord
+
+/// Final state of a transaction sent by the bot, for the PnL.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TxRecord {
+    pub hash: H256,
+    pub nonce: U256,
+    pub block_number: Option<U64>,
+    pub gas_used: U256,
+    pub effective_gas_price: U256,
+    /// Whether the transaction succeeded.
+    pub success: bool,
+    /// Whether the transaction was the cancellation of an arbitrage.
+    pub cancelled: bool,
+    /// Start token of the cycle executed, `None` for a cancellation.
+    pub start_token: Option<Address>,
+    /// Change of the executor's balance of `start_token` made by the transaction, i.e. the profit before gas.
+    pub realized_profit: Option<I256>,
+}
+
+impl TxRecord {
+    /// `start_token` is the start token of the cycle executed by `executor`, `None` for a cancellation.
+    pub fn new(receipt: &TransactionReceipt, nonce: U256, start_token: Option<Address>, executor: Address) -> Self {
+        Self {
+            hash: receipt.transaction_hash,
+            nonce,
+            block_number: receipt.block_number,
+            gas_used: receipt.gas_used.unwrap_or_default(),
+            effective_gas_price: receipt.effective_gas_price.unwrap_or_default(),
+            success: receipt.status == Some(U64::one()),
+            cancelled: start_token.is_none(),
+            start_token,
+            realized_profit: start_token.map(|token| balance_change(receipt, token, executor)),
+        }
+    }
+
+    /// Gas fees paid, in wei.
+    pub fn cost(&self) -> U256 {
+        self.gas_used * self.effective_gas_price
+    }
+}
+
+//------------------------------------- PendingTx
+
+/// Transaction sent by the bot, not mined yet.
+#[derive(Debug, Clone)]
+pub struct PendingTx {
+    /// Hash of the last version sent.
+    pub hash: H256,
+    /// Hashes of every version sent, any of which may be mined.
+    pub hashes: Vec<H256>,
+    pub tx: Eip1559TransactionRequest,
+    /// Block the last version was sent in.
+    pub sent_block: U64,
+    /// Key of the opportunity of the transaction, e.g. the pools of the cycle. `None` once cancelled.
+    pub opportunity: Option<Vec<(usize, usize)>>,
+}
+
+impl PendingTx {
+    pub fn nonce(&self) -> U256 {
+        self.tx.nonce.unwrap_or_default()
+    }
+}
+
+//------------------------------------- TxManager
+
+/// Sends the executor transactions as EIP-1559 transactions and follows them until they are mined:
+/// the ones whose opportunity disappeared are cancelled, the ones left behind by the base fee are repriced.
+#[derive(Debug, Clone)]
+pub struct TxManager {
+    pub strategy: PriorityFeeStrategy,
+    pub nonces: NonceManager,
+    pub pending: Vec<PendingTx>,
+    /// Mined transactions, oldest first.
+    pub records: Vec<TxRecord>,
+    /// JSON lines file each record is appended to when its transaction is mined, so that the PnL outlives the bot.
+    pub records_path: Option<String>,
+}
+
+impl TxManager {
+    /// `address` is the account sending the transactions.
+    pub fn new(strategy: PriorityFeeStrategy, address: Address) -> Self {
+        Self {
+            strategy,
+            nonces: NonceManager::new(address),
+            pending: Vec::new(),
+            records: Vec::new(),
+            records_path: None,
+        }
+    }
+
+    /// Returns the base fee of block `block` and the priority fee of the strategy.
+    /// The priority fee of `ProfitShare` depends on the cycle, see `profit_share_fee`, it is zero here.
+    pub async fn fees(&self, provider: &SignerMiddleware<Provider<Http>, LocalWallet>, block: U64) -> BotResult<Fees> {
+        let base_fee = provider
+            .get_block(block)
+            .await
+            .map_err(|e| BotError::Rpc(format!("`eth_getBlockByNumber` asynchronous call failed: {}", e)))?
+            .and_then(|block| block.base_fee_per_gas)
+            .ok_or_else(|| BotError::Rpc(format!("Block {} has no base fee", block)))?;
+        let priority_fee = match self.strategy {
+            PriorityFeeStrategy::Fixed(fee) => U256::from(fee),
+            PriorityFeeStrategy::Percentile(percentile) => {
+                let history = provider
+                    .fee_history(FEE_HISTORY_BLOCKS, BlockNumber::Number(block), &[percentile as f64])
+                    .await
+                    .map_err(|e| BotError::Rpc(format!("`eth_feeHistory` asynchronous call failed: {}", e)))?;
+                median(history.reward.iter().filter_map(|rewards| rewards.first().copied()).collect())
+            },
+            PriorityFeeStrategy::ProfitShare(_) => U256::zero(),
+        };
+        Ok(Fees { base_fee, priority_fee })
+    }
+
+    /// Returns `fees` with the priority fee of a transaction using `gas` for a profit worth `profit` wei,
+    /// which only differs from `fees` with the `ProfitShare` strategy.
+    pub fn profit_share_fee(&self, fees: Fees, profit: U256, gas: u64) -> Fees {
+        match self.strategy {
+            PriorityFeeStrategy::ProfitShare(bps) if gas > 0 => Fees {
+                priority_fee: profit * bps / 10_000 / gas,
+                ..fees
+            },
+            _ => fees,
+        }
+    }
+
+    /// Whether a transaction for `opportunity` is already pending.
+    pub fn is_pending(&self, opportunity: &[(usize, usize)]) -> bool {
+        self.pending.iter().any(|tx| tx.opportunity.as_deref() == Some(opportunity))
+    }
+
+    /// Sends a transaction calling `to` with `calldata` at `fees`, and tracks it as the execution of `opportunity`.
+    /// The nonce is allocated locally, resynced and allocated again once if the node rejects it, the gas limit is filled by the node.
+    pub async fn send(
+        &mut self,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        to: Address,
+        calldata: Bytes,
+        fees: Fees,
+        opportunity: Vec<(usize, usize)>,
+        block: U64,
+    ) -> BotResult<H256> {
+        let mut tx = self.fill(provider, to, calldata, fees).await?;
+        let nonce = tx.nonce.unwrap_or_default();
+        let hash = match self.broadcast(provider, &tx).await {
+            Ok(hash) => hash,
+            Err(e) if is_nonce_error(&e.to_string()) => {
+                println!("nonce {} rejected, resyncing nonces: {}", nonce, e);
+                self.nonces.sync(provider).await?;
+                let nonce = self.nonces.next(provider).await?;
+                tx = tx.nonce(nonce);
+                self.broadcast(provider, &tx).await.inspect_err(|_| self.nonces.release(nonce))?
+            },
+            Err(e) => {
+                self.nonces.release(nonce);
+                return Err(e);
+            },
+        };
+        self.pending.push(PendingTx { hash, hashes: vec![hash], tx, sent_block: block, opportunity: Some(opportunity) });
+        Ok(hash)
+    }
+
+    /// Returns the signed raw transaction calling `to` with `calldata` at `fees`, to be sent in a bundle.
+    /// Bundled transactions never reach the mempool, so its nonce is released right away:
+    /// the bundles of a block compete for the same nonce, only one of them can be included.
+    pub async fn sign(
+        &mut self,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        to: Address,
+        calldata: Bytes,
+        fees: Fees,
+    ) -> BotResult<Bytes> {
+        let tx: TypedTransaction = self.fill(provider, to, calldata, fees).await?.into();
+        self.nonces.release(tx.nonce().copied().unwrap_or_default());
+        let signature = provider
+            .signer()
+            .sign_transaction(&tx)
+            .await
+            .map_err(|e| BotError::Execution(format!("Failed to sign transaction: {}", e)))?;
+        Ok(tx.rlp_signed(&signature))
+    }
+
+    /// Returns the transaction calling `to` with `calldata` at `fees`, with a nonce allocated locally and the gas limit filled by the node.
+    async fn fill(
+        &mut self,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        to: Address,
+        calldata: Bytes,
+        fees: Fees,
+    ) -> BotResult<Eip1559TransactionRequest> {
+        let nonce = self.nonces.next(provider).await?;
+        let mut tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .from(provider.address())
+            .to(to)
+            .data(calldata)
+            .nonce(nonce)
+            .max_priority_fee_per_gas(fees.priority_fee)
+            .max_fee_per_gas(fees.max_fee())
+            .into();
+        if let Err(e) = provider.fill_transaction(&mut tx, None).await {
+            self.nonces.release(nonce);
+            return Err(BotError::Execution(format!("Failed to fill transaction: {}", e)));
+        }
+        match tx {
+            TypedTransaction::Eip1559(tx) => Ok(tx),
+            _ => {
+                self.nonces.release(nonce);
+                Err(BotError::Execution("Filled transaction is not an EIP-1559 transaction".to_string()))
+            },
+        }
+    }
+
+    async fn broadcast(
+        &self,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        tx: &Eip1559TransactionRequest,
+    ) -> BotResult<H256> {
+        let pending = provider
+            .send_transaction(tx.clone(), None)
+            .await
+            .map_err(|e| BotError::Execution(format!("Failed to send transaction of nonce {:?}: {}", tx.nonce, e)))?;
+        Ok(*pending)
+    }
+
+    /// Appends `record` as a json line to `records_path`, if set.
+    fn save_record(&self, record: &TxRecord) -> BotResult<()> {
+        let path = match &self.records_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let line = serde_json::to_string(record)
+            .map_err(|e| BotError::Config(format!("Failed to serialize transaction record: {}", e)))?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", line))
+            .map_err(|e| BotError::Config(format!("Failed to write transaction record to file {}: {}", path, e)))
+    }
+
+    /// Returns the number of successful records, the gas fees paid in wei by all of them,
+    /// and the sums of the realized profits by start token.
+    pub fn totals(&self) -> (usize, U256, Vec<(Address, I256)>) {
+        let mut profits: Vec<(Address, I256)> = Vec::new();
+        for record in &self.records {
+            if let (Some(token), Some(profit)) = (record.start_token, record.realized_profit) {
+                match profits.iter_mut().find(|(t, _)| *t == token) {
+                    Some((_, total)) => *total = total.saturating_add(profit),
+                    None => profits.push((token, profit)),
+                }
+            }
+        }
+        let successes = self.records.iter().filter(|record| record.success).count();
+        let cost = self.records.iter().fold(U256::zero(), |cost, record| cost.saturating_add(record.cost()));
+        (successes, cost, profits)
+    }
+
+    /// Whether the node no longer knows any version of `pending`, which leaves a gap at its nonce.
+    async fn is_dropped(
+        &self,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        pending: &PendingTx,
+    ) -> BotResult<bool> {
+        for &hash in &pending.hashes {
+            let tx = provider
+                .get_transaction(hash)
+                .await
+                .map_err(|e| BotError::Rpc(format!("`eth_getTransactionByHash` asynchronous call failed: {}", e)))?;
+            if tx.is_some() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Follows the pending transactions at block `block`, whose fees are `fees`:
+    /// records the mined ones, cancels the ones whose calldata no longer simulates successfully on `executor`,
+    /// and reprices the ones priced out by the base fee, pending for `REPLACE_AFTER_BLOCKS` blocks or dropped by the node,
+    /// so that their nonce is not left as a gap blocking the later transactions.
+    /// A transaction whose follow-up fails, e.g. on a failed RPC call, stays pending as is and is followed again next block.
+    pub async fn on_block(
+        &mut self,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        executor: &Executor,
+        block: U64,
+        fees: Fees,
+    ) -> BotResult<()> {
+        let mut still_pending: Vec<PendingTx> = Vec::new();
+        for mut pending in std::mem::take(&mut self.pending) {
+            match self.follow(provider, executor, block, fees, &mut pending).await {
+                Ok(true) => still_pending.push(pending),
+                Ok(false) => {},
+                Err(e) => {
+                    println!("failed to follow transaction {:?}, retrying next block: {}", pending.hash, e);
+                    still_pending.push(pending);
+                },
+            }
+        }
+        self.pending = still_pending;
+        Ok(())
+    }
+
+    /// Follows `pending` at block `block` as described in `on_block`. Returns whether it is still pending.
+    async fn follow(
+        &mut self,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        executor: &Executor,
+        block: U64,
+        fees: Fees,
+        pending: &mut PendingTx,
+    ) -> BotResult<bool> {
+        // any version sent may have been mined
+        let mut receipt: Option<TransactionReceipt> = None;
+        for &hash in &pending.hashes {
+            receipt = provider
+                .get_transaction_receipt(hash)
+                .await
+                .map_err(|e| BotError::Rpc(format!("`eth_getTransactionReceipt` asynchronous call failed: {}", e)))?;
+            if receipt.is_some() {
+                break;
+            }
+        }
+        if let Some(receipt) = receipt {
+            let start_token = match (&pending.opportunity, &pending.tx.data) {
+                (Some(_), Some(calldata)) => executor.start_token(calldata),
+                _ => None,
+            };
+            let record = TxRecord::new(&receipt, pending.nonce(), start_token, executor.address);
+            println!("transaction {:?} mined in block {:?}, success: {}, cost: {} wei", record.hash, record.block_number, record.success, record.cost());
+            if let Err(e) = self.save_record(&record) {
+                println!("failed to save record of transaction {:?}: {}", record.hash, e);
+            }
+            self.records.push(record);
+            return Ok(false);
+        }
+
+        let priced_out = pending.tx.max_fee_per_gas.unwrap_or_default() < fees.base_fee;
+        let stuck = block >= pending.sent_block + REPLACE_AFTER_BLOCKS;
+        let dropped = self.is_dropped(provider, pending).await?;
+        if dropped {
+            let mined_count = provider
+                .get_transaction_count(self.nonces.address, Some(BlockNumber::Latest.into()))
+                .await
+                .map_err(|e| BotError::Rpc(format!("`eth_getTransactionCount` asynchronous call failed: {}", e)))?;
+            if mined_count > pending.nonce() {
+                // another transaction took its nonce
+                println!("transaction {:?} dropped and its nonce {} used, forgetting it", pending.hash, pending.nonce());
+                return Ok(false);
+            }
+        }
+        // on the latest block, since the pending block of the node may already trade the cycle in this very transaction
+        let invalidated = match (&pending.opportunity, pending.tx.data.clone()) {
+            (Some(_), Some(calldata)) => matches!(
+                executor.simulate(provider, calldata, BlockNumber::Latest, false).await?,
+                Simulation::Reverted(_),
+            ),
+            _ => false,
+        };
+        let tx = if invalidated {
+            println!("opportunity of transaction {:?} disappeared, cancelling it", pending.hash);
+            cancellation(&pending.tx, fees)
+        } else if priced_out || stuck || dropped {
+            println!("transaction {:?} still pending, repricing it", pending.hash);
+            replacement(&pending.tx, fees)
+        } else {
+            return Ok(true);
+        };
+        match self.broadcast(provider, &tx).await {
+            Ok(hash) => {
+                pending.hash = hash;
+                pending.hashes.push(hash);
+                pending.sent_block = block;
+                pending.tx = tx;
+                if invalidated {
+                    pending.opportunity = None;
+                }
+            },
+            // the previous version may have been mined meanwhile, which the next block tells
+            Err(e) => {
+                println!("failed to replace transaction {:?}: {}", pending.hash, e);
+                if is_nonce_error(&e.to_string()) {
+                    self.nonces.sync(provider).await?;
+                }
+            },
+        }
+        Ok(true)
+    }
+}
+
+/// Returns `tx` with fees high enough to replace it, and at least `fees`.
+pub fn replacement(tx: &Eip1559TransactionRequest, fees: Fees) -> Eip1559TransactionRequest {
+    let priority_fee = bump(tx.max_priority_fee_per_gas.unwrap_or_default()).max(fees.priority_fee);
+    let max_fee = bump(tx.max_fee_per_gas.unwrap_or_default()).max(Fees { priority_fee, ..fees }.max_fee());
+    tx.clone()
+        .max_priority_fee_per_gas(priority_fee)
+        .max_fee_per_gas(max_fee)
+}
+
+/// Returns an empty transfer to self replacing `tx`, with fees high enough to replace it.
+pub fn cancellation(tx: &Eip1559TransactionRequest, fees: Fees) -> Eip1559TransactionRequest {
+    let mut cancellation = replacement(tx, fees)
+        .data(Bytes::default())
+        .value(U256::zero())
+        .gas(21_000);
+    cancellation.to = tx.from.map(Into::into);
+    cancellation
+}
+
+/// Returns the change of the balance of `account` in `token` made by the transaction of `receipt`, from its `Transfer` logs.
+pub fn balance_change(receipt: &TransactionReceipt, token: Address, account: Address) -> I256 {
+    let transfer_topic = H256::from(keccak256("Transfer(address,address,uint256)"));
+    let account_topic = H256::from(account);
+    receipt.logs
+        .iter()
+        .filter(|log| log.address == token && log.topics.len() == 3 && log.topics[0] == transfer_topic && log.data.len() == 32)
+        .fold(I256::zero(), |change, log| {
+            let amount = I256::from_raw(U256::from_big_endian(&log.data));
+            match (log.topics[1] == account_topic, log.topics[2] == account_topic) {
+                (false, true) => change.saturating_add(amount),
+                (true, false) => change.saturating_sub(amount),
+                _ => change,
+            }
+        })
+}
+
+fn median(mut values: Vec<U256>) -> U256 {
+    values.sort_unstable();
+    values.get(values.len() / 2).copied().unwrap_or_default()
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::Transaction;
+    use serde_json::{ json, Value, };
+    use crate::testing::mock_rpc;
+
+    #[test]
+    fn test_replacement_fees() {
+        let fees = Fees { base_fee: U256::from(100), priority_fee: U256::from(10) };
+        let tx = Eip1559TransactionRequest::new()
+            .from(Address::repeat_byte(1))
+            .to(Address::repeat_byte(2))
+            .data(Bytes::from(vec![1, 2, 3]))
+            .nonce(7)
+            .gas(300_000)
+            .max_priority_fee_per_gas(fees.priority_fee)
+            .max_fee_per_gas(fees.max_fee());
+
+        // both fees bumped by at least 12.5%
+        let replaced = replacement(&tx, fees);
+        assert_eq!(replaced.max_priority_fee_per_gas, Some(U256::from(12)));
+        assert_eq!(replaced.max_fee_per_gas, Some(U256::from(237)));
+        assert_eq!((replaced.nonce, replaced.data.clone()), (tx.nonce, tx.data.clone()));
+
+        // or up to the current fees if they rose more
+        let fees = Fees { base_fee: U256::from(300), priority_fee: U256::from(50) };
+        let replaced = replacement(&tx, fees);
+        assert_eq!(replaced.max_priority_fee_per_gas, Some(U256::from(50)));
+        assert_eq!(replaced.max_fee_per_gas, Some(U256::from(650)));
+
+        let cancelled = cancellation(&tx, fees);
+        assert_eq!(cancelled.to, Some(Address::repeat_byte(1).into()));
+        assert_eq!(cancelled.data, Some(Bytes::default()));
+        assert_eq!(cancelled.gas, Some(U256::from(21_000)));
+        assert_eq!(cancelled.nonce, tx.nonce);
+    }
+
+    #[test]
+    fn test_profit_share_fee() {
+        let fees = Fees { base_fee: U256::from(100), priority_fee: U256::zero() };
+        let manager = TxManager::new(PriorityFeeStrategy::ProfitShare(1_000), Address::zero());
+        // a tenth of 1e9 wei over 100k gas
+        assert_eq!(manager.profit_share_fee(fees, U256::exp10(9), 100_000).priority_fee, U256::from(1_000));
+        let manager = TxManager::new(PriorityFeeStrategy::Fixed(5), Address::zero());
+        assert_eq!(manager.profit_share_fee(fees, U256::exp10(9), 100_000), fees);
+        assert_eq!(median(vec![U256::from(3), U256::from(1), U256::from(2)]), U256::from(2));
+    }
+
+    #[test]
+    fn test_transaction_records() {
+        let (token, executor, pool) = (Address::repeat_byte(1), Address::repeat_byte(0xee), Address::repeat_byte(2));
+        let transfer = |token: Address, from: Address, to: Address, amount: u64| ethers::types::Log {
+            address: token,
+            topics: vec![H256::from(keccak256("Transfer(address,address,uint256)")), H256::from(from), H256::from(to)],
+            data: Bytes::from(ethers::abi::encode(&[ethers::abi::Token::Uint(U256::from(amount))])),
+            ..Default::default()
+        };
+        let receipt = TransactionReceipt {
+            status: Some(U64::one()),
+            gas_used: Some(U256::from(200_000)),
+            effective_gas_price: Some(U256::from(10)),
+            // borrowed, swapped out and back in, repaid with the fee
+            logs: vec![
+                transfer(token, pool, executor, 1_000),
+                transfer(token, executor, pool, 1_000),
+                transfer(Address::repeat_byte(3), pool, executor, 990),
+                transfer(token, pool, executor, 1_020),
+                transfer(token, executor, pool, 1_001),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(balance_change(&receipt, token, executor), I256::from(19));
+
+        let mut manager = TxManager::new(PriorityFeeStrategy::Fixed(5), Address::zero());
+        manager.records.push(TxRecord::new(&receipt, U256::zero(), Some(token), executor));
+        manager.records.push(TxRecord::new(&receipt, U256::one(), None, executor));
+        assert!(manager.records[1].cancelled && manager.records[1].realized_profit.is_none());
+        assert_eq!(manager.totals(), (2, U256::from(4_000_000), vec![(token, I256::from(19))]));
+
+        let path = std::env::temp_dir().join(format!("transactions_{}.jsonl", std::process::id()));
+        manager.records_path = Some(path.to_str().unwrap().to_string());
+        manager.save_record(&manager.records[0]).unwrap();
+        manager.save_record(&manager.records[1]).unwrap();
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["realizedProfit"], serde_json::json!(I256::from(19)));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Node of `test_on_block_failed_lookup`: the transaction 0xa1.. is mined, the receipt lookup of 0xb1.. fails,
+    /// the others are pending and their calldata simulates a profit on the latest block.
+    fn node(body: &Value) -> Result<Value, Value> {
+        let hash = |byte: u8| json!(H256::repeat_byte(byte));
+        match body["method"].as_str().unwrap() {
+            "eth_getTransactionReceipt" if body["params"][0] == hash(0xa1) => Ok(json!(TransactionReceipt {
+                transaction_hash: H256::repeat_byte(0xa1),
+                block_number: Some(U64::from(100)),
+                status: Some(U64::one()),
+                ..Default::default()
+            })),
+            "eth_getTransactionReceipt" if body["params"][0] == hash(0xb1) => Err(json!({ "code": -32000, "message": "header not found" })),
+            "eth_getTransactionReceipt" => Ok(Value::Null),
+            "eth_getTransactionByHash" => Ok(json!(Transaction { hash: H256::repeat_byte(0xc1), ..Default::default() })),
+            "eth_call" if body["params"][1] == "latest" => Ok(json!(Bytes::from(ethers::abi::encode(&[ethers::abi::Token::Uint(U256::one())])))),
+            method => Err(json!({ "code": -32601, "message": format!("unexpected {} {}", method, body["params"]) })),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_on_block_failed_lookup() {
+        let (url, received) = mock_rpc(node).await;
+        let wallet: LocalWallet = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318".parse().unwrap();
+        let provider = SignerMiddleware::new(Provider::<Http>::try_from(url.as_str()).unwrap(), wallet);
+        let executor = Executor::new(Address::repeat_byte(0xee)).unwrap();
+        let block = U64::from(100);
+        let fees = Fees { base_fee: U256::from(100), priority_fee: U256::from(10) };
+        let mut manager = TxManager::new(PriorityFeeStrategy::Fixed(10), provider.address());
+        for (nonce, byte) in [0xa1u8, 0xb1, 0xc1].into_iter().enumerate() {
+            let tx = Eip1559TransactionRequest::new()
+                .to(executor.address)
+                .data(Bytes::from(vec![byte]))
+                .nonce(nonce)
+                .max_priority_fee_per_gas(fees.priority_fee)
+                .max_fee_per_gas(fees.max_fee());
+            let hash = H256::repeat_byte(byte);
+            manager.pending.push(PendingTx { hash, hashes: vec![hash], tx, sent_block: block, opportunity: Some(vec![(nonce, 0)]) });
+        }
+
+        // the failed lookup of the second transaction neither drops it nor stops the third one from being followed
+        manager.on_block(&provider, &executor, block, fees).await.unwrap();
+        assert_eq!(manager.records.iter().map(|record| record.hash).collect::<Vec<H256>>(), vec![H256::repeat_byte(0xa1)]);
+        let pending: Vec<H256> = manager.pending.iter().map(|tx| tx.hash).collect();
+        assert_eq!(pending, vec![H256::repeat_byte(0xb1), H256::repeat_byte(0xc1)]);
+        assert!(manager.is_pending(&[(1, 0)]) && manager.is_pending(&[(2, 0)]));
+        let simulations = received
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, body)| body.contains("eth_call"))
+            .count();
+        assert_eq!(simulations, 1);
+    }
+}
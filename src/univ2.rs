@@ -0,0 +1,478 @@
+use ethers::{
+    middleware::SignerMiddleware,
+    providers::{Http, Provider, Middleware},
+    signers::LocalWallet,
+    types::{ Address, Bytes, U256, U64, H256, Log, },
+    contract::{ BaseContract, AbiError, },
+    abi::ethereum_types::U512,
+};
+use std::{
+    fs::File,
+    io::{Write, ErrorKind},
+    collections::HashMap,
+    convert::TryFrom,
+    any::Any,
+    sync::Arc,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::*;
+use crate::univ3::Token;
+use crate::multicall::Multicall;
+use crate::optimizer::Quote;
+use crate::pool::{ Pool, SwapPayment, };
+use crate::profitability::V2_SWAP_GAS;
+use crate::error::{ BotError, BotResult, };
+
+/// Denominator of `Univ2Pair.fee`, which is expressed in hundredths of a bip like `UniswapV3Pool.fee()`.
+const FEE_DENOMINATOR: u32 = 1_000_000;
+
+//------------------------------------- Univ2Pair
+
+/// Pair of a Uniswap V2 fork.
+#[derive(Debug, Clone, Deserialize, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Univ2Pair {
+    pub address: Address,
+    pub pool_id: usize,
+    pub token_0_id: usize,
+    pub token_1_id: usize,
+    /// Swap fee in hundredths of a bip, e.g. `3000` for the 0.3% of Uniswap V2.
+    pub fee: u32,
+}
+
+impl Univ2Pair {
+    pub fn new(
+        address: Address,
+        pool_id: usize,
+        token_0_id: usize,
+        token_1_id: usize,
+        fee: u32,
+    ) -> Self {
+        Self {
+            address,
+            pool_id,
+            token_0_id,
+            token_1_id,
+            fee,
+        }
+    }
+
+    /// Reads the pairs from `config/<chain id>/pairs.json`.
+    /// If the file is not found, the pairs between `tokens` are discovered on the factories of `univ2_factories`
+    /// and saved to it.
+    pub async fn get_pairs(
+        chain_id: u32,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        tokens: &[Token],
+        chunk_size: usize,
+    ) -> BotResult<Vec<Univ2Pair>> {
+        let file_storing_pairs = format!("config/{}/pairs.json", &chain_id);
+
+        let file = match File::open(&file_storing_pairs) {
+            Ok(f) => f,
+            Err(e) => match e.kind() {
+                ErrorKind::NotFound => {
+                    println!("{} not found, creating from UniswapV2Factory", &file_storing_pairs);
+                    let multicall = Multicall::new(chain_id, provider, chunk_size)?;
+                    let block = provider
+                        .get_block_number()
+                        .await
+                        .map_err(|e| BotError::Rpc(format!("`eth_blockNumber` asynchronous call failed: {}", e)))?;
+                    let mut pairs: Vec<Univ2Pair> = Vec::new();
+                    for (factory, fee) in univ2_factories(chain_id)? {
+                        pairs.extend(Univ2Pair::discover(factory, fee, tokens, &multicall, block).await?);
+                    }
+                    for (pool_id, pair) in pairs.iter_mut().enumerate() {
+                        pair.pool_id = pool_id;
+                    }
+                    Univ2Pair::save_pairs(&file_storing_pairs, &pairs)?;
+                    return Ok(pairs);
+                },
+                _ => return Err(BotError::Config(format!("Failed to open file {}: {}", &file_storing_pairs, e))),
+            },
+        };
+        let pairs: Vec<Univ2Pair> = serde_json::from_reader(file)
+            .map_err(|e| BotError::Config(format!("Failed to extract pairs from json: {}", e)))?;
+        for pair in &pairs {
+            pair.validate()?;
+        }
+        Ok(pairs)
+    }
+
+    /// Checks that the fee of the pair is below 100%.
+    pub fn validate(&self) -> BotResult<()> {
+        if self.fee >= FEE_DENOMINATOR {
+            return Err(BotError::Config(format!(
+                "Pair {:?} has a fee of {} hundredths of a bip, it must be below {}",
+                self.address, self.fee, FEE_DENOMINATOR,
+            )));
+        }
+        Ok(())
+    }
+
+    /// Finds the pairs of `factory` between `tokens`, with `fee` and a `pool_id` of 0.
+    /// Pairs are looked up with `getPair()` for every couple of tokens, or by walking `allPairs()`
+    /// when the factory holds fewer pairs than there are couples of tokens.
+    pub async fn discover(
+        factory: Address,
+        fee: u32,
+        tokens: &[Token],
+        multicall: &Multicall<'_>,
+        block: U64,
+    ) -> BotResult<Vec<Univ2Pair>> {
+        let factory_abi = BaseContract::from(i_univ2_factory_abi()?);
+        let pair_abi = BaseContract::from(i_univ2_pair_abi()?);
+        let encode_error = |name: &'static str| move |e: AbiError| BotError::Abi(format!("`{}()` method not found in ABI: {}", name, e));
+        let decode_error = |name: &'static str| move |e: AbiError| BotError::Decode(format!("Failed to decode `{}()` output: {}", name, e));
+        let reverted = |name: &'static str| BotError::Execution(format!("`{}()` call reverted", name));
+        let token_ids: HashMap<Address, usize> = tokens.iter().map(|token| (token.address, token.token_id)).collect();
+
+        let calls = [(factory, factory_abi.encode("allPairsLength", ()).map_err(encode_error("UniswapV2Factory.allPairsLength"))?)];
+        let data = multicall.aggregate(&calls, block).await?.remove(0).ok_or_else(|| reverted("UniswapV2Factory.allPairsLength"))?;
+        let all_pairs_length: U256 = factory_abi
+            .decode_output("allPairsLength", data)
+            .map_err(decode_error("UniswapV2Factory.allPairsLength"))?;
+        let couples = tokens.len() * tokens.len().saturating_sub(1) / 2;
+
+        let mut addresses: Vec<Address> = Vec::new();
+        if all_pairs_length < U256::from(couples) {
+            // walk allPairs
+            let calls = (0..all_pairs_length.as_usize())
+                .map(|i| Ok((factory, factory_abi.encode("allPairs", U256::from(i)).map_err(encode_error("UniswapV2Factory.allPairs"))?)))
+                .collect::<BotResult<Vec<(Address, Bytes)>>>()?;
+            for data in multicall.aggregate(&calls, block).await? {
+                let data = data.ok_or_else(|| reverted("UniswapV2Factory.allPairs"))?;
+                addresses.push(factory_abi.decode_output("allPairs", data).map_err(decode_error("UniswapV2Factory.allPairs"))?);
+            }
+        } else {
+            // look up every couple of tokens
+            let mut calls: Vec<(Address, Bytes)> = Vec::with_capacity(couples);
+            for i in 0..tokens.len() {
+                for j in (i + 1)..tokens.len() {
+                    let data = factory_abi
+                        .encode("getPair", (tokens[i].address, tokens[j].address))
+                        .map_err(encode_error("UniswapV2Factory.getPair"))?;
+                    calls.push((factory, data));
+                }
+            }
+            for data in multicall.aggregate(&calls, block).await? {
+                let data = data.ok_or_else(|| reverted("UniswapV2Factory.getPair"))?;
+                let pair: Address = factory_abi.decode_output("getPair", data).map_err(decode_error("UniswapV2Factory.getPair"))?;
+                if pair != Address::zero() {
+                    addresses.push(pair);
+                }
+            }
+        }
+
+        // keep the pairs between known tokens
+        let mut calls: Vec<(Address, Bytes)> = Vec::with_capacity(2 * addresses.len());
+        for &pair in &addresses {
+            calls.push((pair, pair_abi.encode("token0", ()).map_err(encode_error("UniswapV2Pair.token0"))?));
+            calls.push((pair, pair_abi.encode("token1", ()).map_err(encode_error("UniswapV2Pair.token1"))?));
+        }
+        let returns = multicall.aggregate(&calls, block).await?;
+        let mut pairs: Vec<Univ2Pair> = Vec::new();
+        for (&pair, data) in addresses.iter().zip(returns.chunks(2)) {
+            let token_0: Address = pair_abi
+                .decode_output("token0", data[0].as_ref().ok_or_else(|| reverted("UniswapV2Pair.token0"))?)
+                .map_err(decode_error("UniswapV2Pair.token0"))?;
+            let token_1: Address = pair_abi
+                .decode_output("token1", data[1].as_ref().ok_or_else(|| reverted("UniswapV2Pair.token1"))?)
+                .map_err(decode_error("UniswapV2Pair.token1"))?;
+            if let (Some(&token_0_id), Some(&token_1_id)) = (token_ids.get(&token_0), token_ids.get(&token_1)) {
+                pairs.push(Univ2Pair::new(pair, 0, token_0_id, token_1_id, fee));
+            }
+        }
+        Ok(pairs)
+    }
+
+    /// Serializes `pairs` to the json file at `path`.
+    fn save_pairs(path: &str, pairs: &[Univ2Pair]) -> BotResult<()> {
+        let serialized_pairs = serde_json::to_string_pretty(pairs)
+            .map_err(|e| BotError::Config(format!("Failed to serialize pairs: {}", e)))?;
+        let mut pairs_file = File::create(path)
+            .map_err(|e| BotError::Config(format!("Failed to create file {}: {}", path, e)))?;
+        pairs_file
+            .write_all(serialized_pairs.as_bytes())
+            .map_err(|e| BotError::Config(format!("Failed to write pairs to file {}: {}", path, e)))
+    }
+}
+
+//------------------------------------- PairSnapshot
+
+/// Reserves of a pair along with its fee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PairSnapshot {
+    pub reserve_0: U256,
+    pub reserve_1: U256,
+    pub fee: u32,
+}
+
+impl PairSnapshot {
+    /// Returns the amount of output token received for `amount_in` of input token.
+    pub fn get_amount_out(&self, zero_for_one: bool, amount_in: U256) -> Result<U256, &'static str> {
+        if zero_for_one {
+            get_amount_out(amount_in, self.reserve_0, self.reserve_1, self.fee)
+        } else {
+            get_amount_out(amount_in, self.reserve_1, self.reserve_0, self.fee)
+        }
+    }
+}
+
+impl Quote for PairSnapshot {
+    fn get_amount_out(&self, token_in: usize, _token_out: usize, amount_in: U256) -> Result<U256, &'static str> {
+        PairSnapshot::get_amount_out(self, token_in == 0, amount_in)
+    }
+}
+
+//------------------------------------- Univ2Pool
+
+/// Pair of a Uniswap V2 fork, made of its immutables and a snapshot of its reserves.
+#[derive(Debug, Clone)]
+pub struct Univ2Pool {
+    pub pair: Univ2Pair,
+    /// `None` until the pair is first fetched.
+    pub snapshot: Option<PairSnapshot>,
+    token_0_decimals: u8,
+    token_1_decimals: u8,
+    /// Signature of `Sync`, computed once since `apply_log` checks it for every log of the pair.
+    sync_topic: H256,
+    pair_abi: Arc<BaseContract>,
+}
+
+impl Univ2Pool {
+    pub fn new(pair: Univ2Pair, tokens: &[Token], pair_abi: Arc<BaseContract>) -> BotResult<Self> {
+        let sync_topic = pair_abi
+            .abi()
+            .event("Sync")
+            .map(|event| event.signature())
+            .map_err(|e| BotError::Abi(format!("`UniswapV2Pair.Sync` event not found in ABI: {}", e)))?;
+        Ok(Self {
+            token_0_decimals: tokens[pair.token_0_id].decimals,
+            token_1_decimals: tokens[pair.token_1_id].decimals,
+            pair,
+            snapshot: None,
+            sync_topic,
+            pair_abi,
+        })
+    }
+
+    /// Returns the pairs of `Univ2Pair::get_pairs`, yet to be fetched.
+    pub async fn load(
+        chain_id: u32,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        tokens: &[Token],
+        chunk_size: usize,
+    ) -> BotResult<Vec<Self>> {
+        let pair_abi = Arc::new(BaseContract::from(i_univ2_pair_abi()?));
+        Univ2Pair::get_pairs(chain_id, provider, tokens, chunk_size)
+            .await?
+            .into_iter()
+            .map(|pair| Univ2Pool::new(pair, tokens, pair_abi.clone()))
+            .collect()
+    }
+}
+
+impl Quote for Univ2Pool {
+    fn get_amount_out(&self, token_in: usize, _token_out: usize, amount_in: U256) -> Result<U256, &'static str> {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.get_amount_out(token_in == 0, amount_in),
+            None => Err("Pool not fetched"),
+        }
+    }
+}
+
+impl Pool for Univ2Pool {
+    fn address(&self) -> Address {
+        self.pair.address
+    }
+
+    fn tokens(&self) -> Vec<usize> {
+        vec![self.pair.token_0_id, self.pair.token_1_id]
+    }
+
+    fn is_stale(&self) -> bool {
+        self.snapshot.is_none()
+    }
+
+    fn invalidate(&mut self) {
+        self.snapshot = None;
+    }
+
+    fn refresh_calls(&self) -> BotResult<Vec<(Address, Bytes)>> {
+        let data = self.pair_abi
+            .encode("getReserves", ())
+            .map_err(|e| BotError::Abi(format!("`UniswapV2Pair.getReserves()` method not found in ABI: {}", e)))?;
+        Ok(vec![(self.pair.address, data)])
+    }
+
+    fn apply_refresh(&mut self, returns: &[Option<Bytes>]) -> BotResult<()> {
+        let data = returns
+            .first()
+            .cloned()
+            .flatten()
+            .ok_or_else(|| BotError::Execution(format!("`UniswapV2Pair.getReserves()` call reverted on pair {:?}", self.pair.address)))?;
+        let (reserve_0, reserve_1, _): (U256, U256, u32) = self.pair_abi
+            .decode_output("getReserves", data)
+            .map_err(|e| BotError::Decode(format!("Failed to decode `UniswapV2Pair.getReserves()` output: {}", e)))?;
+        self.snapshot = Some(PairSnapshot { reserve_0, reserve_1, fee: self.pair.fee });
+        Ok(())
+    }
+
+    /// `Sync` is emitted with the new reserves by every `swap()`, `mint()`, `burn()` and `sync()`.
+    fn event_topics(&self) -> BotResult<Vec<H256>> {
+        Ok(vec![self.sync_topic])
+    }
+
+    fn apply_log(&mut self, log: &Log) -> BotResult<bool> {
+        if log.topics.first() != Some(&self.sync_topic) {
+            return Ok(false);
+        }
+        let (reserve_0, reserve_1): (U256, U256) = self.pair_abi
+            .decode_event("Sync", log.topics.clone(), log.data.clone())
+            .map_err(|e| BotError::Decode(format!("Failed to decode `UniswapV2Pair.Sync` event: {}", e)))?;
+        let snapshot = match self.snapshot.as_mut() {
+            Some(snapshot) => snapshot,
+            None => return Ok(false),
+        };
+        let changed = snapshot.reserve_0 != reserve_0 || snapshot.reserve_1 != reserve_1;
+        snapshot.reserve_0 = reserve_0;
+        snapshot.reserve_1 = reserve_1;
+        Ok(changed)
+    }
+
+    fn log_price(&self, token_in: usize, _token_out: usize) -> Option<f64> {
+        let snapshot = self.snapshot.as_ref()?;
+        if snapshot.reserve_0.is_zero() || snapshot.reserve_1.is_zero() {
+            return None;
+        }
+        let (p0, p1) = reserves_to_log_price(snapshot.reserve_0, snapshot.reserve_1, self.token_0_decimals, self.token_1_decimals);
+        let fee_weight = fee_to_log_weight(self.pair.fee as f32);
+        Some(if token_in == 0 { p0 } else { p1 } + fee_weight)
+    }
+
+    /// `UniswapV2Pair.swap()` of `amount_out`. The caller must transfer `amount_in` to the pair beforehand.
+    fn swap_calldata(&self, token_in: usize, _token_out: usize, _amount_in: U256, amount_out: U256, recipient: Address) -> BotResult<Bytes> {
+        let (amount_0_out, amount_1_out) = if token_in == 0 { (U256::zero(), amount_out) } else { (amount_out, U256::zero()) };
+        self.pair_abi
+            .encode("swap", (amount_0_out, amount_1_out, recipient, Bytes::default()))
+            .map_err(|e| BotError::Abi(format!("`UniswapV2Pair.swap()` method not found in ABI: {}", e)))
+    }
+
+    fn swap_payment(&self) -> SwapPayment {
+        SwapPayment::Transfer
+    }
+
+    fn swap_gas(&self, _token_in: usize, _token_out: usize, _amount_in: U256) -> u64 {
+        V2_SWAP_GAS
+    }
+
+    fn box_clone(&self) -> Box<dyn Pool> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Returns the amount of output token received for `amount_in` of input token from a pair holding
+/// `reserve_in` and `reserve_out`, as [UniswapV2Library.getAmountOut](https://github.com/Uniswap/v2-periphery/blob/master/contracts/libraries/UniswapV2Library.sol) does.
+/// `fee` is expressed in hundredths of a bip, so `3000` gives the `997 / 1000` factor of Uniswap V2.
+pub fn get_amount_out(amount_in: U256, reserve_in: U256, reserve_out: U256, fee: u32) -> Result<U256, &'static str> {
+    if amount_in.is_zero() {
+        return Err("UniswapV2Library: INSUFFICIENT_INPUT_AMOUNT");
+    }
+    if reserve_in.is_zero() || reserve_out.is_zero() {
+        return Err("UniswapV2Library: INSUFFICIENT_LIQUIDITY");
+    }
+    let amount_in_with_fee = amount_in
+        .checked_mul(U256::from(FEE_DENOMINATOR.checked_sub(fee).ok_or("UniswapV2Library: INVALID_FEE")?))
+        .ok_or("ds-math-mul-overflow")?;
+    let numerator: U512 = amount_in_with_fee.full_mul(reserve_out);
+    let denominator = reserve_in
+        .checked_mul(U256::from(FEE_DENOMINATOR))
+        .and_then(|r| r.checked_add(amount_in_with_fee))
+        .ok_or("ds-math-add-overflow")?;
+    U256::try_from(numerator / U512::from(denominator)).map_err(|_| "ds-math-mul-overflow")
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ tokens, univ2_pool, };
+
+    #[test]
+    fn test_get_amount_out() {
+        let reserve_in = U256::exp10(21);
+        let reserve_out = U256::exp10(24);
+        // same as the 997 / 1000 formula of UniswapV2Library
+        for amount_in in [U256::one(), U256::exp10(9), U256::exp10(18), U256::exp10(22)] {
+            let expected = amount_in * 997 * reserve_out / (reserve_in * 1000 + amount_in * 997);
+            assert_eq!(get_amount_out(amount_in, reserve_in, reserve_out, 3000), Ok(expected));
+        }
+        // fork with a 0.25% fee
+        let amount_in = U256::exp10(18);
+        let expected = amount_in * 9975 * reserve_out / (reserve_in * 10000 + amount_in * 9975);
+        assert_eq!(get_amount_out(amount_in, reserve_in, reserve_out, 2500), Ok(expected));
+
+        assert_eq!(get_amount_out(U256::zero(), reserve_in, reserve_out, 3000), Err("UniswapV2Library: INSUFFICIENT_INPUT_AMOUNT"));
+        assert_eq!(get_amount_out(amount_in, U256::zero(), reserve_out, 3000), Err("UniswapV2Library: INSUFFICIENT_LIQUIDITY"));
+        assert_eq!(get_amount_out(U256::MAX, reserve_in, reserve_out, 3000), Err("ds-math-mul-overflow"));
+        assert_eq!(get_amount_out(amount_in, reserve_in, reserve_out, 1_000_001), Err("UniswapV2Library: INVALID_FEE"));
+
+        // fees of 100% or more are rejected when the pairs are loaded
+        assert!(Univ2Pair::new(Address::zero(), 0, 0, 1, 3000).validate().is_ok());
+        assert!(matches!(Univ2Pair::new(Address::zero(), 0, 0, 1, 1_000_000).validate(), Err(BotError::Config(_))));
+    }
+
+    #[test]
+    fn test_pair_snapshot_get_amount_out() {
+        let pair = PairSnapshot { reserve_0: U256::exp10(21), reserve_1: U256::exp10(24), fee: 3000 };
+        let amount_in = U256::exp10(18);
+        assert_eq!(pair.get_amount_out(true, amount_in), get_amount_out(amount_in, pair.reserve_0, pair.reserve_1, 3000));
+        assert_eq!(pair.get_amount_out(false, amount_in), get_amount_out(amount_in, pair.reserve_1, pair.reserve_0, 3000));
+        // output never drains the reserve
+        assert!(pair.get_amount_out(false, U256::exp10(40)).unwrap() < pair.reserve_0);
+    }
+
+    #[test]
+    fn test_univ2_pool_sync() {
+        let pair_abi = BaseContract::from(i_univ2_pair_abi().unwrap());
+        let mut pool = univ2_pool(Address::repeat_byte(1), 0, (0, 1), None, &tokens(&[18; 2]));
+        let reserves = |reserve_0: u64, reserve_1: u64| ethers::abi::encode(&[
+            ethers::abi::Token::Uint(U256::from(reserve_0)),
+            ethers::abi::Token::Uint(U256::from(reserve_1)),
+        ]);
+
+        // refreshed in a single round
+        assert!(pool.is_stale());
+        assert_eq!(pool.refresh_calls().unwrap().len(), 1);
+        let mut data = reserves(1000, 2000);
+        data.extend(ethers::abi::encode(&[ethers::abi::Token::Uint(U256::zero())]));
+        pool.apply_refresh(&[Some(Bytes::from(data))]).unwrap();
+        assert!(!pool.is_stale());
+        assert_eq!(pool.snapshot, Some(PairSnapshot { reserve_0: U256::from(1000), reserve_1: U256::from(2000), fee: 3000 }));
+        assert!(pool.log_price(0, 1).unwrap() < 0.);
+        assert!(pool.log_price(1, 0).unwrap() > 0.);
+
+        // Sync sets the reserves
+        let log = Log {
+            address: pool.address(),
+            topics: pool.event_topics().unwrap(),
+            data: Bytes::from(reserves(1500, 1400)),
+            ..Default::default()
+        };
+        assert!(pool.apply_log(&log).unwrap());
+        assert!(!pool.apply_log(&log).unwrap());
+        assert_eq!(pool.snapshot.unwrap().reserve_0, U256::from(1500));
+
+        // swap out token 1 for token 0
+        let calldata = pool.swap_calldata(0, 1, U256::from(10), U256::from(9), Address::repeat_byte(2)).unwrap();
+        let (amount_0_out, amount_1_out, to, _): (U256, U256, Address, Bytes) = pair_abi.decode("swap", calldata).unwrap();
+        assert_eq!((amount_0_out, amount_1_out, to), (U256::zero(), U256::from(9), Address::repeat_byte(2)));
+    }
+}
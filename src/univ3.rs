@@ -2,23 +2,31 @@ use ethers::{
     middleware::SignerMiddleware,
     providers::{Http, Provider},
     signers::LocalWallet,
-    types::{ Address, U256, },
-    contract::Contract,
-    abi::Abi,
+    types::{ Address, Bytes, U256, I256, H256, Log, },
+    contract::{ Contract, BaseContract, AbiError, },
+    abi::{ Abi, Tokenize, Detokenize, },
 };
 use std::{
+    any::Any,
     path::Path,
     fs::{ File, },
     io::{Write, ErrorKind},
     collections::HashMap,
+    convert::TryFrom,
+    sync::Arc,
 };
 use serde::{Deserialize, Serialize};
 
 use crate::utils::*;
+use crate::univ3_math::*;
+use crate::optimizer::Quote;
+use crate::pool::{ Pool, SwapPayment, };
+use crate::profitability::{ V3_SWAP_GAS, TICK_CROSS_GAS, };
+use crate::error::{ BotError, BotResult, };
 
 //------------------------------------- Token
 
-#[derive(Debug, Deserialize, Serialize, Default)]
+#[derive(Debug, Clone, Deserialize, Serialize, Default, PartialEq, Eq)]
 #[serde(rename_all = "camelCase", default)]
 pub struct Token {
     pub chain_id: u32,
@@ -30,53 +38,24 @@ pub struct Token {
 }
 
 impl Token {
-    pub fn get_tokens(chain_id: u32) -> Vec<Token> {
+    pub fn get_tokens(chain_id: u32) -> BotResult<Vec<Token>> {
         let file_storing_tokens = format!("config/{}/tokens.json", &chain_id);
 
-        // create the file if file is not found
-        let file = match File::open(&file_storing_tokens) {
-            Ok(f) => f,
-            Err(e) => match e.kind() {
-                ErrorKind::NotFound => {
-                    panic!("Failed to open file: {}", &file_storing_tokens);
-                    /*
-                    println!("{} not found, creating from all tokens", &file_storing_tokens );
-                    // reading all tokens from raw tokens file
-                    let path_string = "config/raw/allTokens.json".to_string();
-                    let path = Path::new(&path_string);
-                    let error = format!("Failed to open file {}", &path_string);
-                    let file = File::open(path).expect(&error);
-                    let all_tokens: Vec<Token> = serde_json::from_reader(file).expect("Failed to extract all tokens from raw tokens file");
-                    // storing tokens with correct chain id
-                    let mut tokens: Vec<Token> = Vec::new();
-                    let mut token_id: usize = 0;
-                    for mut token in all_tokens {
-                        if token.chain_id == chain_id {
-                            token.token_id = token_id;
-                            token_id += 1;
-                            tokens.push(token);
-                        }
-                    }
-                    // exporting tokens with correct chain id to file
-                    let serialized_tokens = serde_json::to_string(&tokens).expect("Failed to serialize tokens");
-                    let error = format!("Failed to create file {}", &file_storing_tokens );
-                    let mut tokens_file = File::create(&file_storing_tokens ).expect(&error);
-                    let error = format!("Failed to write tokens to file {}", &file_storing_tokens );
-                    tokens_file.write_all(&serialized_tokens.as_bytes()).expect(&error);
-                    return tokens;
-                    */
-                },
-                _ => panic!("Failed to open file: {}", &file_storing_tokens),
-            },
-        };
-        let tokens: Vec<Token> = serde_json::from_reader(file).expect("Failed to extract tokens from json");
-        tokens
+        let file = File::open(&file_storing_tokens).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => BotError::Config(format!("Failed to open file: {}, build it with `tokens build`", &file_storing_tokens)),
+            _ => BotError::Config(format!("Failed to open file {}: {}", &file_storing_tokens, e)),
+        })?;
+        if file.metadata().map(|metadata| metadata.len() == 0).unwrap_or(false) {
+            return Err(BotError::Config(format!("{} is empty, build it with `tokens build`", &file_storing_tokens)));
+        }
+        serde_json::from_reader(file)
+            .map_err(|e| BotError::Config(format!("Failed to extract tokens from json: {}", e)))
     }
 }
 
 //------------------------------------- PoolImmutables
 
-#[derive(Debug, Deserialize, Serialize, Default)]
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
 #[serde(rename_all = "camelCase", default)]
 pub struct PoolImmutables {
     pub address: Address,
@@ -109,10 +88,39 @@ impl PoolImmutables {
         }
     }
 
+    /// Reads `fee()`, `tickSpacing()` and `maxLiquidityPerTick()` from the pool contract and stores them.
+    pub async fn fetch_immutables(
+        &mut self,
+        pool: &Contract<&SignerMiddleware<Provider<Http>, LocalWallet>>,
+    ) -> BotResult<()> {
+        let fee: u32 = pool
+            .method::<(), u32>("fee", ())
+            .map_err(|e| BotError::Abi(format!("`Pool.fee()` method not found in ABI: {}", e)))?
+            .call()
+            .await
+            .map_err(|e| BotError::Rpc(format!("`Pool.fee()` asynchronous call failed: {}", e)))?;
+        let tick_spacing: i32 = pool
+            .method::<(), i32>("tickSpacing", ())
+            .map_err(|e| BotError::Abi(format!("`Pool.tickSpacing()` method not found in ABI: {}", e)))?
+            .call()
+            .await
+            .map_err(|e| BotError::Rpc(format!("`Pool.tickSpacing()` asynchronous call failed: {}", e)))?;
+        let max_liquidity_per_tick: u128 = pool
+            .method::<(), u128>("maxLiquidityPerTick", ())
+            .map_err(|e| BotError::Abi(format!("`Pool.maxLiquidityPerTick()` method not found in ABI: {}", e)))?
+            .call()
+            .await
+            .map_err(|e| BotError::Rpc(format!("`Pool.maxLiquidityPerTick()` asynchronous call failed: {}", e)))?;
+        self.fee = fee as f32;
+        self.tick_spacing = tick_spacing as f32;
+        self.max_liquidity_per_tick = max_liquidity_per_tick as f32;
+        Ok(())
+    }
+
     pub async fn get_pool_immutables(
         chain_id: u32,
         provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
-    ) -> Vec<PoolImmutables> {
+    ) -> BotResult<Vec<PoolImmutables>> {
         let file_storing_pools = format!("config/{}/pools.json", &chain_id);
 
         // create the file if file is not found
@@ -124,18 +132,18 @@ impl PoolImmutables {
 
                     // get tokens
                     let path = format!("config/{}/tokens.json", &chain_id);
-                    let error = format!("Failed to open file: {}", &path);
-                    let path = Path::new(&path);
-                    let tokens_file = File::open(path).expect(&error);
-                    let tokens: Vec<Token> = serde_json::from_reader(tokens_file).expect("Failed to read Tokens from json");
+                    let tokens_file = File::open(Path::new(&path))
+                        .map_err(|e| BotError::Config(format!("Failed to open file {}: {}", &path, e)))?;
+                    let tokens: Vec<Token> = serde_json::from_reader(tokens_file)
+                        .map_err(|e| BotError::Config(format!("Failed to read Tokens from json: {}", e)))?;
 
                     // get factory contract
-                    let factory_addr = univ3_factory_addr(chain_id);
-                    let factory_abi: Abi = i_univ3_factory_abi();
+                    let factory_addr = univ3_factory_addr(chain_id)?;
+                    let factory_abi: Abi = i_univ3_factory_abi()?;
                     let factory = Contract::new(factory_addr, factory_abi, provider);
 
                     // get pool abi
-                    let pool_abi: Abi = i_univ3_pool_abi();
+                    let pool_abi: Abi = i_univ3_pool_abi()?;
 
                     // fetch pool address
                     let mut pools: Vec<PoolImmutables> = Vec::new();
@@ -146,79 +154,114 @@ impl PoolImmutables {
                     for j in i..tokens.len()-1 {
                     for fee in &fees {
                         let pool_addr: Address = factory
-                            .method::<(Address, Address, u32), Address>("getPool", (tokens[i].address, tokens[j].address, fee.clone()))
-                            .expect("`UniswapV3Factory.getPool()` method not found in ABI")
+                            .method::<(Address, Address, u32), Address>("getPool", (tokens[i].address, tokens[j].address, *fee))
+                            .map_err(|e| BotError::Abi(format!("`UniswapV3Factory.getPool()` method not found in ABI: {}", e)))?
                             .call()
                             .await
-                            .expect("`UniswapV3Factory.getPool()` asynchronous call failed");
+                            .map_err(|e| BotError::Rpc(format!("`UniswapV3Factory.getPool()` asynchronous call failed: {}", e)))?;
                         // if pool exists and has not already been fetched
                         if pool_addr != Address::zero() && !is_pool_fetched.contains_key(&pool_addr) {
                             // add to hash map
-                            is_pool_fetched.insert(pool_addr.clone(), true);
+                            is_pool_fetched.insert(pool_addr, true);
                             // get pool contract
-                            let pool = Contract::new(pool_addr, pool_abi.clone(), &provider);
+                            let pool = Contract::new(pool_addr, pool_abi.clone(), provider);
                             // get pool immutables
                             // find which is token 0 and is which token 1
                             let token0_addr: Address = pool
                                 .method::<(), Address>("token0", ())
-                                .expect("`Pool.token0()` method not found in ABI")
+                                .map_err(|e| BotError::Abi(format!("`Pool.token0()` method not found in ABI: {}", e)))?
                                 .call()
                                 .await
-                                .expect("`Pool.token0()` asynchronous call failed");
-                            if token0_addr == tokens[i].address {
-                                pools.push(PoolImmutables::new(
+                                .map_err(|e| BotError::Rpc(format!("`Pool.token0()` asynchronous call failed: {}", e)))?;
+                            let mut immutables = if token0_addr == tokens[i].address {
+                                PoolImmutables::new(
                                     pool_addr,
                                     pool_id,
                                     tokens[i].token_id,
                                     tokens[j].token_id,
                                     0., 0., 0.
-                                ));
+                                )
                             } else {
-                                pools.push(PoolImmutables::new(
+                                PoolImmutables::new(
                                     pool_addr,
                                     pool_id,
                                     tokens[j].token_id,
                                     tokens[i].token_id,
                                     0., 0., 0.
-                                ));
-                            }
+                                )
+                            };
+                            immutables.fetch_immutables(&pool).await?;
+                            pools.push(immutables);
                             pool_id += 1;
                         }
                     }}}
-                    // save to file
-                    let serialized_pools = serde_json::to_string(&pools).expect("Failed to serialize pools");
-                    let error = format!("Failed to create file {}", &file_storing_pools);
-                    let mut pools_file = File::create(&file_storing_pools).expect(&error);
-                    let error = format!("Failed to write pools to file {}", &file_storing_pools);
-                    pools_file.write_all(&serialized_pools.as_bytes()).expect(&error);
-                    return pools;
+                    PoolImmutables::save_pool_immutables(&file_storing_pools, &pools)?;
+                    return Ok(pools);
                 },
-                _ => panic!("Failed to open file: {}", &file_storing_pools),
+                _ => return Err(BotError::Config(format!("Failed to open file {}: {}", &file_storing_pools, e))),
             },
         };
-        let pools: Vec<PoolImmutables> = serde_json::from_reader(file).expect("Failed to extract pool immutables from json");
-        pools
+        let mut pools: Vec<PoolImmutables> = serde_json::from_reader(file)
+            .map_err(|e| BotError::Config(format!("Failed to extract pool immutables from json: {}", e)))?;
+
+        // pools saved before fees were recorded have a zero fee, fetch it from the pool
+        if pools.iter().any(|pool| pool.fee == 0.) {
+            println!("{} is missing pool fees, fetching them from the pools", &file_storing_pools);
+            let pool_abi: Abi = i_univ3_pool_abi()?;
+            for immutables in pools.iter_mut().filter(|pool| pool.fee == 0.) {
+                let pool = Contract::new(immutables.address, pool_abi.clone(), provider);
+                immutables.fetch_immutables(&pool).await?;
+            }
+            PoolImmutables::save_pool_immutables(&file_storing_pools, &pools)?;
+        }
+        Ok(pools)
+    }
+
+    /// Reads the pool immutables saved in `config/{chain_id}/pools.json`, without discovering or fetching the missing ones.
+    pub fn read_pool_immutables(chain_id: u32) -> BotResult<Vec<PoolImmutables>> {
+        let file_storing_pools = format!("config/{}/pools.json", &chain_id);
+        let file = File::open(&file_storing_pools)
+            .map_err(|e| BotError::Config(format!("Failed to open file {}: {}", &file_storing_pools, e)))?;
+        let pools: Vec<PoolImmutables> = serde_json::from_reader(file)
+            .map_err(|e| BotError::Config(format!("Failed to extract pool immutables from json: {}", e)))?;
+        if pools.iter().any(|pool| pool.fee == 0.) {
+            return Err(BotError::Config(format!("{} is missing pool fees, run the bot once to fetch them", &file_storing_pools)));
+        }
+        Ok(pools)
+    }
+
+    /// Serializes `pools` to the json file at `path`.
+    fn save_pool_immutables(path: &str, pools: &[PoolImmutables]) -> BotResult<()> {
+        let serialized_pools = serde_json::to_string_pretty(pools)
+            .map_err(|e| BotError::Config(format!("Failed to serialize pools: {}", e)))?;
+        let mut pools_file = File::create(path)
+            .map_err(|e| BotError::Config(format!("Failed to create file {}: {}", path, e)))?;
+        pools_file
+            .write_all(serialized_pools.as_bytes())
+            .map_err(|e| BotError::Config(format!("Failed to write pools to file {}: {}", path, e)))
     }
 }
 
 //------------------------------------- PoolState
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PoolState {
-    sqrt_price_X96: U256,
-    tick: i32,
-    observation_index: u16,
-    observation_cardinality: u16,
-    observation_cardinality_next: u16,
-    fee_protocol: u8,
-    unlocked: bool,
-    token_0_decimals: u8,
-    token_1_decimals: u8,
+    pub sqrt_price_x96: U256,
+    pub tick: i32,
+    pub observation_index: u16,
+    pub observation_cardinality: u16,
+    pub observation_cardinality_next: u16,
+    pub fee_protocol: u8,
+    pub unlocked: bool,
+    pub liquidity: u128,
+    pub token_0_decimals: u8,
+    pub token_1_decimals: u8,
 }
 
 impl PoolState {
     pub fn new(
         (
-            sqrt_price_X96,
+            sqrt_price_x96,
             tick,
             observation_index,
             observation_cardinality,
@@ -226,19 +269,828 @@ impl PoolState {
             fee_protocol,
             unlocked,
         ): (U256, i32, u16, u16, u16, u8, bool),
+        liquidity: u128,
         token_0_decimals: u8,
         token_1_decimals: u8
     ) -> Self {
         PoolState {
-            sqrt_price_X96,
+            sqrt_price_x96,
             tick,
             observation_index,
             observation_cardinality,
             observation_cardinality_next,
             fee_protocol,
             unlocked,
+            liquidity,
             token_0_decimals,
             token_1_decimals,
         }
     }
-}
\ No newline at end of file
+}
+//------------------------------------- PoolSnapshot
+
+/// Number of tick bitmap words fetched on each side of the current tick by default.
+/// Each word covers `256 * tick_spacing` ticks.
+pub const TICK_BITMAP_WORDS: i16 = 2;
+
+/// Pool state along with every initialized tick in the tick bitmap words surrounding the current tick.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolSnapshot {
+    pub state: PoolState,
+    pub fee: u32,
+    pub tick_spacing: i32,
+    /// Initialized ticks sorted by index.
+    pub ticks: Vec<TickInfo>,
+    /// Lowest tick covered by the fetched tick bitmap words.
+    pub min_tick: i32,
+    /// Highest tick covered by the fetched tick bitmap words.
+    pub max_tick: i32,
+}
+
+impl PoolSnapshot {
+    /// Returns the amount of output token received for `amount_in` of input token.
+    /// The swap stops at the edge of the fetched ticks, beyond which the liquidity is unknown,
+    /// so the input may not be fully consumed for very large amounts.
+    pub fn get_amount_out(&self, zero_for_one: bool, amount_in: U256) -> Result<U256, &'static str> {
+        let result = self.swap(zero_for_one, amount_in)?;
+        let amount_out = if zero_for_one { result.amount_1 } else { result.amount_0 };
+        Ok(amount_out.into_sign_and_abs().1)
+    }
+
+    /// Returns the number of initialized ticks crossed by a swap of `amount_in`, each costing extra gas.
+    pub fn ticks_crossed(&self, zero_for_one: bool, amount_in: U256) -> Result<usize, &'static str> {
+        let end_tick = self.swap(zero_for_one, amount_in)?.tick;
+        let start_tick = self.state.tick;
+        Ok(self.ticks
+            .iter()
+            .filter(|t| if zero_for_one { end_tick < t.index && t.index <= start_tick } else { start_tick < t.index && t.index <= end_tick })
+            .count())
+    }
+
+    /// Swaps `amount_in` through the fetched ticks.
+    fn swap(&self, zero_for_one: bool, amount_in: U256) -> Result<SwapResult, &'static str> {
+        let limit_tick = if zero_for_one { self.min_tick } else { self.max_tick };
+        let sqrt_price_limit_x96 = get_sqrt_ratio_at_tick(limit_tick.clamp(MIN_TICK, MAX_TICK))?
+            .clamp(min_sqrt_ratio() + 1, max_sqrt_ratio() - 1);
+        let amount_specified = I256::try_from(amount_in).map_err(|_| "SC")?;
+        swap(
+            &self.state,
+            &self.ticks,
+            self.tick_spacing,
+            self.fee,
+            zero_for_one,
+            amount_specified,
+            sqrt_price_limit_x96,
+        )
+    }
+}
+
+impl Quote for PoolSnapshot {
+    fn get_amount_out(&self, token_in: usize, _token_out: usize, amount_in: U256) -> Result<U256, &'static str> {
+        PoolSnapshot::get_amount_out(self, token_in == 0, amount_in)
+    }
+}
+
+/// Returns the first and last tick bitmap words to fetch to cover `words` words on each side of `tick`.
+pub fn tick_bitmap_word_range(tick: i32, tick_spacing: i32, words: i16) -> (i16, i16) {
+    let word = (tick.div_euclid(tick_spacing) >> 8) as i16;
+    let min_word = ((MIN_TICK / tick_spacing) >> 8) as i16;
+    let max_word = ((MAX_TICK / tick_spacing) >> 8) as i16;
+    (
+        word.saturating_sub(words).max(min_word),
+        word.saturating_add(words).min(max_word),
+    )
+}
+
+/// Returns the ticks flagged as initialized in the tick bitmap word at position `word`.
+pub fn initialized_ticks_in_word(word: i16, bitmap: U256, tick_spacing: i32) -> Vec<i32> {
+    (0..256)
+        .filter(|&bit| bitmap.bit(bit))
+        .map(|bit| (((word as i32) << 8) + bit as i32) * tick_spacing)
+        .collect()
+}
+
+//------------------------------------- PoolEvent
+
+/// Change to a pool's state decoded from one of its logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolEvent {
+    Initialize { sqrt_price_x96: U256, tick: i32 },
+    Swap { sqrt_price_x96: U256, liquidity: u128, tick: i32 },
+    Mint { tick_lower: i32, tick_upper: i32, amount: u128 },
+    Burn { tick_lower: i32, tick_upper: i32, amount: u128 },
+}
+
+/// Names of the pool events changing its state.
+pub const POOL_EVENTS: [&str; 4] = ["Initialize", "Swap", "Mint", "Burn"];
+
+impl PoolEvent {
+    /// Signatures of `POOL_EVENTS` in the ABI of `contract`, in the same order.
+    pub fn topics(pool_abi: &BaseContract, contract: &str) -> BotResult<Vec<H256>> {
+        POOL_EVENTS
+            .iter()
+            .map(|name| {
+                pool_abi
+                    .abi()
+                    .event(name)
+                    .map(|event| event.signature())
+                    .map_err(|e| BotError::Abi(format!("`{}.{}` event not found in ABI: {}", contract, name, e)))
+            })
+            .collect()
+    }
+
+    /// Decodes `log` with the pool ABI, `None` if it is not one of `POOL_EVENTS`, whose signatures are `topics`.
+    pub fn decode(pool_abi: &BaseContract, topics: &[H256], log: &Log) -> BotResult<Option<Self>> {
+        let topic = match log.topics.first() {
+            Some(topic) => *topic,
+            None => return Ok(None),
+        };
+        let name = match topics.iter().position(|t| *t == topic).and_then(|i| POOL_EVENTS.get(i)) {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let decode_error = |e: AbiError| BotError::Decode(format!("Failed to decode `Pool.{}` event: {}", name, e));
+        let topics = log.topics.clone();
+        let data = log.data.clone();
+        let event = match *name {
+            "Initialize" => {
+                let (sqrt_price_x96, tick): (U256, i32) = pool_abi
+                    .decode_event(name, topics, data)
+                    .map_err(decode_error)?;
+                PoolEvent::Initialize { sqrt_price_x96, tick }
+            },
+            "Swap" => {
+                let (_, _, _, _, sqrt_price_x96, liquidity, tick): (Address, Address, I256, I256, U256, u128, i32) = pool_abi
+                    .decode_event(name, topics, data)
+                    .map_err(decode_error)?;
+                PoolEvent::Swap { sqrt_price_x96, liquidity, tick }
+            },
+            "Mint" => {
+                let (_, _, tick_lower, tick_upper, amount, _, _): (Address, Address, i32, i32, u128, U256, U256) = pool_abi
+                    .decode_event(name, topics, data)
+                    .map_err(decode_error)?;
+                PoolEvent::Mint { tick_lower, tick_upper, amount }
+            },
+            _ => {
+                let (_, tick_lower, tick_upper, amount, _, _): (Address, i32, i32, u128, U256, U256) = pool_abi
+                    .decode_event(name, topics, data)
+                    .map_err(decode_error)?;
+                PoolEvent::Burn { tick_lower, tick_upper, amount }
+            },
+        };
+        Ok(Some(event))
+    }
+}
+
+impl PoolSnapshot {
+    /// Applies `event` to the snapshot, returns whether the snapshot changed.
+    /// Only the ticks inside the fetched tick bitmap words are tracked, as the liquidity of the others is unknown.
+    /// Fails if a position update overflows or underflows the tracked liquidity, leaving the snapshot inconsistent.
+    pub fn apply(&mut self, event: &PoolEvent) -> BotResult<bool> {
+        match *event {
+            PoolEvent::Initialize { sqrt_price_x96, tick } | PoolEvent::Swap { sqrt_price_x96, tick, .. } => {
+                let liquidity = match *event {
+                    PoolEvent::Swap { liquidity, .. } => liquidity,
+                    _ => self.state.liquidity,
+                };
+                let changed = self.state.sqrt_price_x96 != sqrt_price_x96
+                    || self.state.tick != tick
+                    || self.state.liquidity != liquidity;
+                self.state.sqrt_price_x96 = sqrt_price_x96;
+                self.state.tick = tick;
+                self.state.liquidity = liquidity;
+                Ok(changed)
+            },
+            PoolEvent::Mint { tick_lower, tick_upper, amount } => {
+                self.update_position(tick_lower, tick_upper, amount as i128)
+            },
+            PoolEvent::Burn { tick_lower, tick_upper, amount } => {
+                self.update_position(tick_lower, tick_upper, -(amount as i128))
+            },
+        }
+    }
+
+    /// Adds `liquidity_delta` to the position between `tick_lower` and `tick_upper`, as `UniswapV3Pool._modifyPosition` does.
+    fn update_position(&mut self, tick_lower: i32, tick_upper: i32, liquidity_delta: i128) -> BotResult<bool> {
+        if liquidity_delta == 0 {
+            return Ok(false);
+        }
+        self.update_tick(tick_lower, liquidity_delta, false)?;
+        self.update_tick(tick_upper, liquidity_delta, true)?;
+        if tick_lower <= self.state.tick && self.state.tick < tick_upper {
+            self.state.liquidity = add_delta(self.state.liquidity, liquidity_delta)
+                .map_err(|e| BotError::Math(format!("Position update overflows pool liquidity ({})", e)))?;
+        }
+        Ok(true)
+    }
+
+    fn update_tick(&mut self, index: i32, liquidity_delta: i128, upper: bool) -> BotResult<()> {
+        if index < self.min_tick || index > self.max_tick {
+            return Ok(());
+        }
+        let net_delta = if upper { -liquidity_delta } else { liquidity_delta };
+        match self.ticks.binary_search_by_key(&index, |t| t.index) {
+            Ok(i) => {
+                let tick = &mut self.ticks[i];
+                tick.liquidity_gross = add_delta(tick.liquidity_gross, liquidity_delta)
+                    .map_err(|e| BotError::Math(format!("Position update overflows tick {} liquidity ({})", index, e)))?;
+                tick.liquidity_net += net_delta;
+                // flipped to uninitialized
+                if tick.liquidity_gross == 0 {
+                    self.ticks.remove(i);
+                }
+            },
+            Err(i) => {
+                if liquidity_delta > 0 {
+                    self.ticks.insert(i, TickInfo {
+                        index,
+                        liquidity_gross: liquidity_delta as u128,
+                        liquidity_net: net_delta,
+                    });
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Whether the price moved outside of the fetched tick bitmap words, in which case the snapshot must be refetched.
+    pub fn is_stale(&self) -> bool {
+        self.state.tick < self.min_tick || self.state.tick > self.max_tick
+    }
+}
+
+//------------------------------------- Univ3Pool
+
+/// Progress of the refresh of a `Univ3Pool`, which takes three rounds of calls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Univ3Fetch {
+    /// The snapshot is complete.
+    Done,
+    /// `slot0()` and `liquidity()` are to be fetched.
+    Slot0,
+    /// The tick bitmap words between `word_min` and `word_max` are to be fetched.
+    Bitmap { state: PoolState, word_min: i16, word_max: i16 },
+    /// `ticks()` of every initialized tick in `indices` is to be fetched.
+    Ticks { state: PoolState, word_min: i16, word_max: i16, indices: Vec<i32> },
+}
+
+/// Uniswap V3 pool, made of its immutables and a snapshot of its state.
+#[derive(Debug, Clone)]
+pub struct Univ3Pool {
+    pub immutables: PoolImmutables,
+    /// `None` until the pool is first fetched.
+    pub snapshot: Option<PoolSnapshot>,
+    token_0_decimals: u8,
+    token_1_decimals: u8,
+    /// Number of tick bitmap words fetched on each side of the current tick.
+    words: i16,
+    fetch: Univ3Fetch,
+    /// Signatures of `POOL_EVENTS`, computed once since `apply_log` checks them for every log of the pool.
+    topics: Vec<H256>,
+    pool_abi: Arc<BaseContract>,
+}
+
+impl Univ3Pool {
+    pub fn new(immutables: PoolImmutables, tokens: &[Token], words: i16, pool_abi: Arc<BaseContract>) -> BotResult<Self> {
+        Ok(Self {
+            token_0_decimals: tokens[immutables.token_0_id].decimals,
+            token_1_decimals: tokens[immutables.token_1_id].decimals,
+            immutables,
+            snapshot: None,
+            words,
+            fetch: Univ3Fetch::Slot0,
+            topics: PoolEvent::topics(&pool_abi, "Pool")?,
+            pool_abi,
+        })
+    }
+
+    /// Returns the pools of `PoolImmutables::get_pool_immutables`, yet to be fetched.
+    pub async fn load(
+        chain_id: u32,
+        provider: &SignerMiddleware<Provider<Http>, LocalWallet>,
+        tokens: &[Token],
+    ) -> BotResult<Vec<Self>> {
+        let pool_abi = Arc::new(BaseContract::from(i_univ3_pool_abi()?));
+        PoolImmutables::get_pool_immutables(chain_id, provider)
+            .await?
+            .into_iter()
+            .map(|immutables| Univ3Pool::new(immutables, tokens, TICK_BITMAP_WORDS, pool_abi.clone()))
+            .collect()
+    }
+
+    fn encode<T: Tokenize>(&self, name: &str, args: T) -> BotResult<(Address, Bytes)> {
+        let data = self.pool_abi
+            .encode(name, args)
+            .map_err(|e| BotError::Abi(format!("`Pool.{}()` method not found in ABI: {}", name, e)))?;
+        Ok((self.immutables.address, data))
+    }
+
+    fn decode<D: Detokenize>(&self, name: &str, data: Option<&Option<Bytes>>) -> BotResult<D> {
+        let data = data
+            .cloned()
+            .flatten()
+            .ok_or_else(|| BotError::Execution(format!("`Pool.{}()` call reverted on pool {:?}", name, self.immutables.address)))?;
+        self.pool_abi
+            .decode_output(name, data)
+            .map_err(|e| BotError::Decode(format!("Failed to decode `Pool.{}()` output: {}", name, e)))
+    }
+}
+
+impl Quote for Univ3Pool {
+    fn get_amount_out(&self, token_in: usize, _token_out: usize, amount_in: U256) -> Result<U256, &'static str> {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.get_amount_out(token_in == 0, amount_in),
+            None => Err("Pool not fetched"),
+        }
+    }
+}
+
+impl Pool for Univ3Pool {
+    fn address(&self) -> Address {
+        self.immutables.address
+    }
+
+    fn tokens(&self) -> Vec<usize> {
+        vec![self.immutables.token_0_id, self.immutables.token_1_id]
+    }
+
+    /// Also stale when the price moved outside of the fetched tick bitmap words.
+    fn is_stale(&self) -> bool {
+        self.fetch != Univ3Fetch::Done || self.snapshot.as_ref().map(PoolSnapshot::is_stale).unwrap_or(true)
+    }
+
+    fn invalidate(&mut self) {
+        self.fetch = Univ3Fetch::Slot0;
+    }
+
+    /// Fetches `slot0()` and `liquidity()`, then the `words` tick bitmap words on each side of the current tick,
+    /// then `ticks()` for every initialized tick found in them.
+    fn refresh_calls(&self) -> BotResult<Vec<(Address, Bytes)>> {
+        match &self.fetch {
+            Univ3Fetch::Done | Univ3Fetch::Slot0 => {
+                if self.immutables.tick_spacing <= 0. {
+                    return Err(BotError::Config(format!("Pool {:?} has no tick spacing, fetch its immutables first", self.immutables.address)));
+                }
+                Ok(vec![self.encode("slot0", ())?, self.encode("liquidity", ())?])
+            },
+            Univ3Fetch::Bitmap { word_min, word_max, .. } => (*word_min..=*word_max)
+                .map(|word| self.encode("tickBitmap", word))
+                .collect(),
+            Univ3Fetch::Ticks { indices, .. } => indices
+                .iter()
+                .map(|&index| self.encode("ticks", index))
+                .collect(),
+        }
+    }
+
+    fn apply_refresh(&mut self, returns: &[Option<Bytes>]) -> BotResult<()> {
+        let tick_spacing = self.immutables.tick_spacing as i32;
+        self.fetch = match std::mem::replace(&mut self.fetch, Univ3Fetch::Slot0) {
+            Univ3Fetch::Done | Univ3Fetch::Slot0 => {
+                let slot0: (U256, i32, u16, u16, u16, u8, bool) = self.decode("slot0", returns.first())?;
+                let liquidity: u128 = self.decode("liquidity", returns.get(1))?;
+                let state = PoolState::new(slot0, liquidity, self.token_0_decimals, self.token_1_decimals);
+                let (word_min, word_max) = tick_bitmap_word_range(state.tick, tick_spacing, self.words);
+                Univ3Fetch::Bitmap { state, word_min, word_max }
+            },
+            Univ3Fetch::Bitmap { state, word_min, word_max } => {
+                let mut indices: Vec<i32> = Vec::new();
+                for (i, word) in (word_min..=word_max).enumerate() {
+                    let bitmap: U256 = self.decode("tickBitmap", returns.get(i))?;
+                    indices.extend(initialized_ticks_in_word(word, bitmap, tick_spacing));
+                }
+                Univ3Fetch::Ticks { state, word_min, word_max, indices }
+            },
+            Univ3Fetch::Ticks { state, word_min, word_max, indices } => {
+                let mut ticks: Vec<TickInfo> = Vec::with_capacity(indices.len());
+                for (i, index) in indices.into_iter().enumerate() {
+                    let (liquidity_gross, liquidity_net, _, _, _, _, _, _):
+                        (u128, i128, U256, U256, i64, U256, u32, bool) = self.decode("ticks", returns.get(i))?;
+                    ticks.push(TickInfo { index, liquidity_gross, liquidity_net });
+                }
+                self.snapshot = Some(PoolSnapshot {
+                    state,
+                    fee: self.immutables.fee as u32,
+                    tick_spacing,
+                    ticks,
+                    min_tick: ((word_min as i32) << 8) * tick_spacing,
+                    max_tick: (((word_max as i32) << 8) + 255) * tick_spacing,
+                });
+                Univ3Fetch::Done
+            },
+        };
+        Ok(())
+    }
+
+    fn event_topics(&self) -> BotResult<Vec<H256>> {
+        Ok(self.topics.clone())
+    }
+
+    fn apply_log(&mut self, log: &Log) -> BotResult<bool> {
+        match (PoolEvent::decode(&self.pool_abi, &self.topics, log)?, self.snapshot.as_mut()) {
+            (Some(event), Some(snapshot)) => snapshot.apply(&event),
+            _ => Ok(false),
+        }
+    }
+
+    fn log_price(&self, token_in: usize, _token_out: usize) -> Option<f64> {
+        let snapshot = self.snapshot.as_ref()?;
+        // uninitialized pool
+        if snapshot.state.sqrt_price_x96.is_zero() || snapshot.state.liquidity == 0 {
+            return None;
+        }
+        let (p0, p1) = sqrtPriceX86_to_log_price(snapshot.state.sqrt_price_x96, self.token_0_decimals, self.token_1_decimals);
+        let fee_weight = fee_to_log_weight(self.immutables.fee);
+        Some(if token_in == 0 { p0 } else { p1 } + fee_weight)
+    }
+
+    /// `UniswapV3Pool.swap()` of exactly `amount_in`, down to the price limit of the pool.
+    /// The caller must pay `amount_in` in `uniswapV3SwapCallback`.
+    fn swap_calldata(&self, token_in: usize, _token_out: usize, amount_in: U256, _amount_out: U256, recipient: Address) -> BotResult<Bytes> {
+        let zero_for_one = token_in == 0;
+        let amount_specified = I256::try_from(amount_in)
+            .map_err(|_| BotError::Math(format!("Swap amount {} overflows int256", amount_in)))?;
+        let sqrt_price_limit_x96 = if zero_for_one { min_sqrt_ratio() + 1 } else { max_sqrt_ratio() - 1 };
+        let (_, data) = self.encode("swap", (recipient, zero_for_one, amount_specified, sqrt_price_limit_x96, Bytes::default()))?;
+        Ok(data)
+    }
+
+    fn swap_payment(&self) -> SwapPayment {
+        SwapPayment::Callback
+    }
+
+    fn swap_gas(&self, token_in: usize, _token_out: usize, amount_in: U256) -> u64 {
+        let ticks_crossed = self.snapshot
+            .as_ref()
+            .and_then(|snapshot| snapshot.ticks_crossed(token_in == 0, amount_in).ok())
+            .unwrap_or_default();
+        V3_SWAP_GAS + TICK_CROSS_GAS * ticks_crossed as u64
+    }
+
+    /// `UniswapV3Pool.flash()` charges the swap fee, rounded up.
+    fn flash_fee(&self, amount: U256) -> Option<U256> {
+        mul_div_rounding_up(amount, U256::from(self.immutables.fee as u32), U256::from(1_000_000))
+    }
+
+    fn box_clone(&self) -> Box<dyn Pool> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::{ abi::Token, types::U64, };
+    use std::collections::HashMap;
+    use crate::pool::{ PoolTracker, BlockHeader, };
+    use crate::testing::tokens;
+
+    #[test]
+    fn test_tick_bitmap_word_range() {
+        assert_eq!(tick_bitmap_word_range(0, 60, 2), (-2, 2));
+        assert_eq!(tick_bitmap_word_range(-1, 60, 2), (-3, 1));
+        assert_eq!(tick_bitmap_word_range(15360, 60, 1), (0, 2));
+        // clamped to the words that can hold valid ticks
+        assert_eq!(tick_bitmap_word_range(MIN_TICK, 1, 2), (-3466, -3464));
+        assert_eq!(tick_bitmap_word_range(MAX_TICK, 1, 2), (3463, 3465));
+    }
+
+    #[test]
+    fn test_initialized_ticks_in_word() {
+        let bitmap = U256::one() | (U256::one() << 5) | (U256::one() << 255);
+        assert_eq!(initialized_ticks_in_word(0, bitmap, 10), vec![0, 50, 2550]);
+        assert_eq!(initialized_ticks_in_word(-1, bitmap, 10), vec![-2560, -2510, -10]);
+        assert!(initialized_ticks_in_word(3, U256::zero(), 10).is_empty());
+    }
+
+    fn snapshot() -> PoolSnapshot {
+        let liquidity = 10u128.pow(18);
+        PoolSnapshot {
+            state: PoolState::new((get_sqrt_ratio_at_tick(0).unwrap(), 0, 0, 1, 1, 0, true), liquidity, 18, 18),
+            fee: 3000,
+            tick_spacing: 60,
+            ticks: vec![
+                TickInfo { index: -600, liquidity_gross: liquidity, liquidity_net: liquidity as i128 },
+                TickInfo { index: 600, liquidity_gross: liquidity, liquidity_net: -(liquidity as i128) },
+            ],
+            min_tick: -30720,
+            max_tick: 30660,
+        }
+    }
+
+    #[test]
+    fn test_apply_mint_and_burn() {
+        let mut snapshot = snapshot();
+        let amount_out = snapshot.get_amount_out(true, U256::exp10(16)).unwrap();
+        let added = 10u128.pow(17);
+
+        // in range position adds liquidity and initializes its ticks
+        assert!(snapshot.apply(&PoolEvent::Mint { tick_lower: -120, tick_upper: 600, amount: added }).unwrap());
+        assert_eq!(snapshot.state.liquidity, 10u128.pow(18) + added);
+        assert_eq!(snapshot.ticks.iter().map(|t| t.index).collect::<Vec<i32>>(), vec![-600, -120, 600]);
+        assert_eq!(snapshot.ticks[2].liquidity_gross, 10u128.pow(18) + added);
+        assert_eq!(snapshot.ticks[2].liquidity_net, -(10i128.pow(18) + added as i128));
+        assert!(snapshot.get_amount_out(true, U256::exp10(16)).unwrap() > amount_out);
+
+        // out of range position only touches its ticks
+        assert!(snapshot.apply(&PoolEvent::Mint { tick_lower: 60, tick_upper: 120, amount: 7 }).unwrap());
+        assert_eq!(snapshot.state.liquidity, 10u128.pow(18) + added);
+        assert_eq!(snapshot.ticks.len(), 5);
+
+        // burning everything uninitializes the ticks and restores the snapshot
+        assert!(snapshot.apply(&PoolEvent::Burn { tick_lower: 60, tick_upper: 120, amount: 7 }).unwrap());
+        assert!(snapshot.apply(&PoolEvent::Burn { tick_lower: -120, tick_upper: 600, amount: added }).unwrap());
+        assert_eq!(snapshot.ticks, self::snapshot().ticks);
+        assert_eq!(snapshot.state.liquidity, 10u128.pow(18));
+        assert_eq!(snapshot.get_amount_out(true, U256::exp10(16)).unwrap(), amount_out);
+
+        // poking a position to collect fees changes nothing
+        assert!(!snapshot.apply(&PoolEvent::Burn { tick_lower: -600, tick_upper: 600, amount: 0 }).unwrap());
+    }
+
+    #[test]
+    fn test_apply_swap() {
+        let mut snapshot = snapshot();
+        let sqrt_price_x96 = get_sqrt_ratio_at_tick(-700).unwrap();
+        assert!(snapshot.apply(&PoolEvent::Swap { sqrt_price_x96, liquidity: 0, tick: -700 }).unwrap());
+        assert!(!snapshot.apply(&PoolEvent::Swap { sqrt_price_x96, liquidity: 0, tick: -700 }).unwrap());
+        assert_eq!(snapshot.state.tick, -700);
+        assert_eq!(snapshot.state.liquidity, 0);
+        assert!(!snapshot.is_stale());
+        assert!(snapshot.apply(&PoolEvent::Initialize { sqrt_price_x96, tick: -40000 }).unwrap());
+        assert!(snapshot.is_stale());
+    }
+
+    #[test]
+    fn test_ticks_crossed() {
+        let snapshot = snapshot();
+        assert_eq!(snapshot.ticks_crossed(true, U256::exp10(16)).unwrap(), 0);
+        // the price moves below tick -600 after about 3e16
+        assert_eq!(snapshot.ticks_crossed(true, U256::exp10(17)).unwrap(), 1);
+        assert_eq!(snapshot.ticks_crossed(false, U256::exp10(17)).unwrap(), 1);
+    }
+
+    fn swap_log(address: Address, tick: i32, liquidity: u128) -> Log {
+        let pool_abi = BaseContract::from(i_univ3_pool_abi().unwrap());
+        Log {
+            address,
+            topics: vec![
+                pool_abi.abi().event("Swap").unwrap().signature(),
+                H256::from(Address::repeat_byte(2)),
+                H256::from(Address::repeat_byte(3)),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Int(I256::from(1000).into_raw()),
+                Token::Int(I256::from(-997).into_raw()),
+                Token::Uint(get_sqrt_ratio_at_tick(tick).unwrap()),
+                Token::Uint(U256::from(liquidity)),
+                Token::Int(I256::from(tick).into_raw()),
+            ])),
+            ..Default::default()
+        }
+    }
+
+    fn int_topic(value: i32) -> H256 {
+        let mut topic = [0u8; 32];
+        I256::from(value).into_raw().to_big_endian(&mut topic);
+        H256::from(topic)
+    }
+
+    fn mint_log(address: Address, tick_lower: i32, tick_upper: i32, amount: u128) -> Log {
+        let pool_abi = BaseContract::from(i_univ3_pool_abi().unwrap());
+        Log {
+            address,
+            topics: vec![
+                pool_abi.abi().event("Mint").unwrap().signature(),
+                H256::from(Address::repeat_byte(2)),
+                int_topic(tick_lower),
+                int_topic(tick_upper),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Address(Address::repeat_byte(2)),
+                Token::Uint(U256::from(amount)),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+            ])),
+            ..Default::default()
+        }
+    }
+
+    fn burn_log(address: Address, tick_lower: i32, tick_upper: i32, amount: u128) -> Log {
+        let pool_abi = BaseContract::from(i_univ3_pool_abi().unwrap());
+        Log {
+            address,
+            topics: vec![
+                pool_abi.abi().event("Burn").unwrap().signature(),
+                H256::from(Address::repeat_byte(2)),
+                int_topic(tick_lower),
+                int_topic(tick_upper),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[
+                Token::Uint(U256::from(amount)),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+            ])),
+            ..Default::default()
+        }
+    }
+
+    /// Fetched pool in the state of `snapshot()`.
+    fn tracked_pool(immutables: &PoolImmutables) -> Box<dyn Pool> {
+        let pool_abi = Arc::new(BaseContract::from(i_univ3_pool_abi().unwrap()));
+        let mut pool = Univ3Pool::new(immutables.clone(), &tokens(&[18; 3]), TICK_BITMAP_WORDS, pool_abi).unwrap();
+        pool.snapshot = Some(snapshot());
+        pool.fetch = Univ3Fetch::Done;
+        Box::new(pool)
+    }
+
+    fn tracked_snapshots(tracker: &PoolTracker) -> Vec<PoolSnapshot> {
+        tracker.pools
+            .iter()
+            .map(|pool| pool.as_any().downcast_ref::<Univ3Pool>().unwrap().snapshot.clone().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_univ3_pool_refresh() {
+        let immutables = PoolImmutables::new(Address::repeat_byte(1), 0, 0, 1, 3000., 60., 0.);
+        let mut pool = tracked_pool(&immutables).as_any().downcast_ref::<Univ3Pool>().unwrap().clone();
+        // the words -2 to 2 fetched below cover the ticks up to (2 * 256 + 255) * 60
+        let expected = PoolSnapshot { max_tick: 46020, ..snapshot() };
+        pool.invalidate();
+        assert!(pool.is_stale());
+
+        // slot0 and liquidity
+        assert_eq!(pool.refresh_calls().unwrap().len(), 2);
+        pool.apply_refresh(&[
+            Some(Bytes::from(ethers::abi::encode(&[
+                Token::Uint(expected.state.sqrt_price_x96),
+                Token::Int(I256::from(expected.state.tick).into_raw()),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::one()),
+                Token::Uint(U256::one()),
+                Token::Uint(U256::zero()),
+                Token::Bool(true),
+            ]))),
+            Some(Bytes::from(ethers::abi::encode(&[Token::Uint(U256::from(expected.state.liquidity))]))),
+        ]).unwrap();
+
+        // two words on each side of the current tick, ticks -600 and 600 in words -1 and 0
+        let calls = pool.refresh_calls().unwrap();
+        assert_eq!(calls.len(), 5);
+        assert!(calls.iter().all(|(target, _)| *target == immutables.address));
+        let word = |bitmap: U256| Some(Bytes::from(ethers::abi::encode(&[Token::Uint(bitmap)])));
+        pool.apply_refresh(&[
+            word(U256::zero()),
+            word(U256::one() << 246),
+            word(U256::one() << 10),
+            word(U256::zero()),
+            word(U256::zero()),
+        ]).unwrap();
+        assert!(pool.is_stale());
+
+        // liquidity of the initialized ticks
+        assert_eq!(pool.refresh_calls().unwrap().len(), 2);
+        let tick = |info: &TickInfo| Some(Bytes::from(ethers::abi::encode(&[
+            Token::Uint(U256::from(info.liquidity_gross)),
+            Token::Int(I256::from(info.liquidity_net).into_raw()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Int(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Bool(true),
+        ])));
+        pool.apply_refresh(&[tick(&expected.ticks[0]), tick(&expected.ticks[1])]).unwrap();
+        assert!(!pool.is_stale());
+        assert_eq!(pool.snapshot, Some(expected));
+
+        // a reverted call fails the refresh
+        pool.invalidate();
+        assert!(matches!(pool.apply_refresh(&[None, None]), Err(BotError::Execution(_))));
+    }
+
+    #[test]
+    fn test_tracker_apply_log() {
+        let pool_abi = BaseContract::from(i_univ3_pool_abi().unwrap());
+        let pool = PoolImmutables::new(Address::repeat_byte(1), 3, 0, 1, 3000., 60., 0.);
+        let mut tracker = PoolTracker::new();
+        assert_eq!(tracker.insert(tracked_pool(&pool)), 0);
+        assert_eq!(tracker.take_dirty(), vec![0]);
+
+        let topics = PoolEvent::topics(&pool_abi, "Pool").unwrap();
+        let log = swap_log(pool.address, -5, 42);
+        assert_eq!(
+            PoolEvent::decode(&pool_abi, &topics, &log).unwrap(),
+            Some(PoolEvent::Swap { sqrt_price_x96: get_sqrt_ratio_at_tick(-5).unwrap(), liquidity: 42, tick: -5 }),
+        );
+        assert_eq!(tracker.apply_log(&log).unwrap(), Some(0));
+        assert_eq!(tracked_snapshots(&tracker)[0].state.tick, -5);
+        assert_eq!(tracker.take_dirty(), vec![0]);
+        assert!(tracker.take_dirty().is_empty());
+
+        // logs of untracked pools are ignored
+        let other = Log { address: Address::repeat_byte(9), ..log };
+        assert_eq!(tracker.apply_log(&other).unwrap(), None);
+    }
+
+    #[test]
+    fn test_tracker_reorg() {
+        let pools = [
+            PoolImmutables::new(Address::repeat_byte(1), 0, 0, 1, 3000., 60., 0.),
+            PoolImmutables::new(Address::repeat_byte(2), 1, 1, 2, 3000., 60., 0.),
+        ];
+        let header = |number: u64, fork: u8| BlockHeader {
+            number: U64::from(number),
+            hash: H256::from_low_u64_be(number << 8 | fork as u64),
+            parent_hash: H256::from_low_u64_be((number - 1) << 8 | if number > 2 { fork } else { 0 } as u64),
+        };
+        let pool_abi = BaseContract::from(i_univ3_pool_abi().unwrap());
+        let topics = PoolEvent::topics(&pool_abi, "Pool").unwrap();
+        assert!(matches!(
+            PoolEvent::decode(&pool_abi, &topics, &mint_log(pools[1].address, -60, 60, 5)).unwrap(),
+            Some(PoolEvent::Mint { tick_lower: -60, tick_upper: 60, amount: 5 }),
+        ));
+        let new_tracker = || {
+            let mut tracker = PoolTracker::new();
+            for pool in &pools {
+                tracker.insert(tracked_pool(pool));
+            }
+            tracker.apply_block(&header(1, 0), &[]).unwrap();
+            tracker.apply_block(&header(2, 0), &[swap_log(pools[0].address, -5, 10u128.pow(18))]).unwrap();
+            tracker.take_dirty();
+            tracker
+        };
+
+        // fork a: block 3 changes both pools
+        let mut tracker = new_tracker();
+        tracker.apply_block(&header(3, 1), &[
+            mint_log(pools[0].address, -120, 120, 10u128.pow(17)),
+            swap_log(pools[0].address, -20, 10u128.pow(18) + 10u128.pow(17)),
+            swap_log(pools[1].address, 30, 10u128.pow(18)),
+        ]).unwrap();
+        assert_eq!(tracker.block(), Some(U64::from(3)));
+        assert_eq!(tracker.take_dirty(), vec![0, 1]);
+
+        // fork b replaces block 3 and adds block 4
+        let fork_b = [
+            (header(3, 2), vec![swap_log(pools[0].address, 7, 10u128.pow(18))]),
+            (header(4, 2), vec![mint_log(pools[1].address, -60, 60, 5)]),
+        ];
+        let canonical: HashMap<U64, H256> = [(3, 2), (2, 0), (1, 0)]
+            .iter()
+            .map(|&(number, fork)| (U64::from(number), header(number, fork).hash))
+            .collect();
+        assert_eq!(tracker.common_ancestor(&canonical), Some(U64::from(2)));
+        tracker.rollback(U64::from(2));
+        assert_eq!(tracker.block(), Some(U64::from(2)));
+        assert_eq!(tracker.take_dirty(), vec![0, 1]);
+        for (header, logs) in &fork_b {
+            tracker.apply_block(header, logs).unwrap();
+        }
+
+        // same snapshots as a tracker which only ever saw fork b
+        let mut expected = new_tracker();
+        for (header, logs) in &fork_b {
+            expected.apply_block(header, logs).unwrap();
+        }
+        assert_eq!(tracker.block(), Some(U64::from(4)));
+        assert_eq!(tracked_snapshots(&tracker), tracked_snapshots(&expected));
+        assert_eq!(tracked_snapshots(&tracker)[0].state.tick, 7);
+
+        // reorg deeper than the journal
+        let canonical: HashMap<U64, H256> = (1..=4)
+            .map(|number| (U64::from(number), H256::from_low_u64_be(number << 8 | 9)))
+            .collect();
+        assert_eq!(tracker.common_ancestor(&canonical), None);
+    }
+
+    #[test]
+    fn test_tracker_apply_block_error() {
+        let pool = PoolImmutables::new(Address::repeat_byte(1), 0, 0, 1, 3000., 60., 0.);
+        let header = |number: u64| BlockHeader {
+            number: U64::from(number),
+            hash: H256::from_low_u64_be(number),
+            parent_hash: H256::from_low_u64_be(number - 1),
+        };
+        let mut tracker = PoolTracker::new();
+        tracker.insert(tracked_pool(&pool));
+        tracker.apply_block(&header(1), &[]).unwrap();
+        let before = tracked_snapshots(&tracker);
+
+        // burning more than the position holds fails the whole block
+        let logs = [
+            swap_log(pool.address, 30, 10u128.pow(18)),
+            burn_log(pool.address, -600, 600, 2 * 10u128.pow(18)),
+        ];
+        assert!(matches!(tracker.apply_block(&header(2), &logs), Err(BotError::Math(_))));
+        assert_eq!(tracked_snapshots(&tracker), before);
+        assert_eq!(tracker.block(), Some(U64::from(1)));
+    }
+}
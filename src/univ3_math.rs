@@ -0,0 +1,857 @@
+use ethers::{
+    types::{ U256, I256, },
+    abi::ethereum_types::U512,
+};
+use std::convert::TryFrom;
+
+use crate::univ3::PoolState;
+
+//------------------------------------- FullMath
+
+/// Returns `floor(a * b / denominator)` with full precision, `None` if the result overflows or `denominator` is zero.
+/// Port of [FullMath.mulDiv](https://github.com/Uniswap/v3-core/blob/412d9b236a1e75a98568d49b1aeb21e3a1430544/contracts/libraries/FullMath.sol).
+pub fn mul_div(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+    U256::try_from(a.full_mul(b) / U512::from(denominator)).ok()
+}
+
+/// Returns `ceil(a * b / denominator)` with full precision, `None` if the result overflows or `denominator` is zero.
+pub fn mul_div_rounding_up(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    let result = mul_div(a, b, denominator)?;
+    if (a.full_mul(b) % U512::from(denominator)).is_zero() {
+        Some(result)
+    } else if result < U256::MAX {
+        Some(result + 1)
+    } else {
+        None
+    }
+}
+
+/// Returns `ceil(x / y)`, with `0` if `y` is zero as in [UnsafeMath.divRoundingUp](https://github.com/Uniswap/v3-core/blob/412d9b236a1e75a98568d49b1aeb21e3a1430544/contracts/libraries/UnsafeMath.sol).
+pub fn div_rounding_up(x: U256, y: U256) -> U256 {
+    if y.is_zero() {
+        return U256::zero();
+    }
+    let quotient = x / y;
+    if (x % y).is_zero() { quotient } else { quotient + 1 }
+}
+
+//------------------------------------- LiquidityMath
+
+/// Adds a signed liquidity delta to liquidity, reverting with `LS` or `LA` on underflow or overflow.
+pub fn add_delta(x: u128, y: i128) -> Result<u128, &'static str> {
+    if y < 0 {
+        x.checked_sub(y.unsigned_abs()).ok_or("LS")
+    } else {
+        x.checked_add(y as u128).ok_or("LA")
+    }
+}
+
+//------------------------------------- TickMath
+
+pub const MIN_TICK: i32 = -887272;
+pub const MAX_TICK: i32 = -MIN_TICK;
+
+/// The value returned by `get_sqrt_ratio_at_tick(MIN_TICK)`.
+pub fn min_sqrt_ratio() -> U256 {
+    U256::from(4295128739u64)
+}
+
+/// The value returned by `get_sqrt_ratio_at_tick(MAX_TICK)`.
+pub fn max_sqrt_ratio() -> U256 {
+    U256::from_dec_str("1461446703485210103287273052203988822378723970342").unwrap()
+}
+
+/// Returns `sqrt(1.0001^tick) * 2^96`, reverting with `T` if `|tick| > MAX_TICK`.
+/// Port of [TickMath.getSqrtRatioAtTick](https://github.com/Uniswap/v3-core/blob/412d9b236a1e75a98568d49b1aeb21e3a1430544/contracts/libraries/TickMath.sol).
+pub fn get_sqrt_ratio_at_tick(tick: i32) -> Result<U256, &'static str> {
+    let abs_tick = tick.unsigned_abs();
+    if abs_tick > MAX_TICK as u32 {
+        return Err("T");
+    }
+
+    let mut ratio = if abs_tick & 0x1 != 0 {
+        U256::from(0xfffcb933bd6fad37aa2d162d1a594001u128)
+    } else {
+        U256::one() << 128
+    };
+    let factors: [(u32, u128); 19] = [
+        (0x2, 0xfff97272373d413259a46990580e213a),
+        (0x4, 0xfff2e50f5f656932ef12357cf3c7fdcc),
+        (0x8, 0xffe5caca7e10e4e61c3624eaa0941cd0),
+        (0x10, 0xffcb9843d60f6159c9db58835c926644),
+        (0x20, 0xff973b41fa98c081472e6896dfb254c0),
+        (0x40, 0xff2ea16466c96a3843ec78b326b52861),
+        (0x80, 0xfe5dee046a99a2a811c461f1969c3053),
+        (0x100, 0xfcbe86c7900a88aedcffc83b479aa3a4),
+        (0x200, 0xf987a7253ac413176f2b074cf7815e54),
+        (0x400, 0xf3392b0822b70005940c7a398e4b70f3),
+        (0x800, 0xe7159475a2c29b7443b29c7fa6e889d9),
+        (0x1000, 0xd097f3bdfd2022b8845ad8f792aa5825),
+        (0x2000, 0xa9f746462d870fdf8a65dc1f90e061e5),
+        (0x4000, 0x70d869a156d2a1b890bb3df62baf32f7),
+        (0x8000, 0x31be135f97d08fd981231505542fcfa6),
+        (0x10000, 0x9aa508b5b7a84e1c677de54f3e99bc9),
+        (0x20000, 0x5d6af8dedb81196699c329225ee604),
+        (0x40000, 0x2216e584f5fa1ea926041bedfe98),
+        (0x80000, 0x48a170391f7dc42444e8fa2),
+    ];
+    for (mask, factor) in factors {
+        if abs_tick & mask != 0 {
+            ratio = (ratio * U256::from(factor)) >> 128;
+        }
+    }
+
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+
+    // divide by 1<<32 rounding up to go from a Q128.128 to a Q128.96
+    let round_up = if (ratio % (U256::one() << 32)).is_zero() { 0 } else { 1 };
+    Ok((ratio >> 32) + round_up)
+}
+
+/// Returns the greatest tick such that `get_sqrt_ratio_at_tick(tick) <= sqrt_price_x96`,
+/// reverting with `R` if the price is outside `[min_sqrt_ratio(), max_sqrt_ratio())`.
+/// Port of [TickMath.getTickAtSqrtRatio](https://github.com/Uniswap/v3-core/blob/412d9b236a1e75a98568d49b1aeb21e3a1430544/contracts/libraries/TickMath.sol).
+pub fn get_tick_at_sqrt_ratio(sqrt_price_x96: U256) -> Result<i32, &'static str> {
+    if sqrt_price_x96 < min_sqrt_ratio() || sqrt_price_x96 >= max_sqrt_ratio() {
+        return Err("R");
+    }
+    let ratio = sqrt_price_x96 << 32;
+
+    let msb = ratio.bits() - 1;
+    let mut r = if msb >= 128 { ratio >> (msb - 127) } else { ratio << (127 - msb) };
+
+    // Q64.64 binary logarithm, only the 14 most significant fractional bits are computed
+    let mut log_2 = I256::from(msb as i32 - 128) << 64u32;
+    for shift in (50..64u32).rev() {
+        r = (r * r) >> 127;
+        let f = r >> 128;
+        log_2 |= I256::from_raw(f) << shift;
+        r >>= f.as_u32();
+    }
+
+    // Q128.128 logarithm in base sqrt(1.0001)
+    let log_sqrt10001 = log_2.wrapping_mul(I256::from(255738958999603826347141u128));
+
+    let tick_low = (log_sqrt10001
+        .wrapping_sub(I256::from(3402992956809132418596140100660247210u128)))
+        .asr(128)
+        .low_i32();
+    let tick_high = (log_sqrt10001
+        .wrapping_add(I256::from(291339464771989622907027621153398088495u128)))
+        .asr(128)
+        .low_i32();
+
+    if tick_low == tick_high || get_sqrt_ratio_at_tick(tick_high)? > sqrt_price_x96 {
+        Ok(tick_low)
+    } else {
+        Ok(tick_high)
+    }
+}
+
+//------------------------------------- SqrtPriceMath
+
+const RESOLUTION: usize = 96;
+
+fn q96() -> U256 {
+    U256::one() << RESOLUTION
+}
+
+fn max_u160() -> U256 {
+    (U256::one() << 160) - 1
+}
+
+/// Returns the next sqrt price given a delta of token0, always rounding up.
+/// Port of [SqrtPriceMath](https://github.com/Uniswap/v3-core/blob/412d9b236a1e75a98568d49b1aeb21e3a1430544/contracts/libraries/SqrtPriceMath.sol).
+pub fn get_next_sqrt_price_from_amount_0_rounding_up(
+    sqrt_p_x96: U256,
+    liquidity: u128,
+    amount: U256,
+    add: bool,
+) -> Result<U256, &'static str> {
+    if amount.is_zero() {
+        return Ok(sqrt_p_x96);
+    }
+    let numerator_1 = U256::from(liquidity) << RESOLUTION;
+    let (product, product_overflow) = amount.overflowing_mul(sqrt_p_x96);
+
+    if add {
+        if !product_overflow {
+            let (denominator, denominator_overflow) = numerator_1.overflowing_add(product);
+            if !denominator_overflow {
+                return mul_div_rounding_up(numerator_1, sqrt_p_x96, denominator).ok_or("MD");
+            }
+        }
+        let denominator = (numerator_1 / sqrt_p_x96).checked_add(amount).ok_or("SC")?;
+        Ok(div_rounding_up(numerator_1, denominator))
+    } else {
+        if product_overflow || numerator_1 <= product {
+            return Err("SPM");
+        }
+        let denominator = numerator_1 - product;
+        let next = mul_div_rounding_up(numerator_1, sqrt_p_x96, denominator).ok_or("MD")?;
+        if next > max_u160() {
+            return Err("SC");
+        }
+        Ok(next)
+    }
+}
+
+/// Returns the next sqrt price given a delta of token1, always rounding down.
+pub fn get_next_sqrt_price_from_amount_1_rounding_down(
+    sqrt_p_x96: U256,
+    liquidity: u128,
+    amount: U256,
+    add: bool,
+) -> Result<U256, &'static str> {
+    let liquidity = U256::from(liquidity);
+    if add {
+        let quotient = if amount <= max_u160() {
+            (amount << RESOLUTION) / liquidity
+        } else {
+            mul_div(amount, q96(), liquidity).ok_or("MD")?
+        };
+        let next = sqrt_p_x96.checked_add(quotient).ok_or("SC")?;
+        if next > max_u160() {
+            return Err("SC");
+        }
+        Ok(next)
+    } else {
+        let quotient = if amount <= max_u160() {
+            div_rounding_up(amount << RESOLUTION, liquidity)
+        } else {
+            mul_div_rounding_up(amount, q96(), liquidity).ok_or("MD")?
+        };
+        if sqrt_p_x96 <= quotient {
+            return Err("SPM");
+        }
+        Ok(sqrt_p_x96 - quotient)
+    }
+}
+
+/// Returns the next sqrt price given an input amount of token0 or token1.
+pub fn get_next_sqrt_price_from_input(
+    sqrt_p_x96: U256,
+    liquidity: u128,
+    amount_in: U256,
+    zero_for_one: bool,
+) -> Result<U256, &'static str> {
+    if sqrt_p_x96.is_zero() || liquidity == 0 {
+        return Err("SPM");
+    }
+    if zero_for_one {
+        get_next_sqrt_price_from_amount_0_rounding_up(sqrt_p_x96, liquidity, amount_in, true)
+    } else {
+        get_next_sqrt_price_from_amount_1_rounding_down(sqrt_p_x96, liquidity, amount_in, true)
+    }
+}
+
+/// Returns the next sqrt price given an output amount of token0 or token1.
+pub fn get_next_sqrt_price_from_output(
+    sqrt_p_x96: U256,
+    liquidity: u128,
+    amount_out: U256,
+    zero_for_one: bool,
+) -> Result<U256, &'static str> {
+    if sqrt_p_x96.is_zero() || liquidity == 0 {
+        return Err("SPM");
+    }
+    if zero_for_one {
+        get_next_sqrt_price_from_amount_1_rounding_down(sqrt_p_x96, liquidity, amount_out, false)
+    } else {
+        get_next_sqrt_price_from_amount_0_rounding_up(sqrt_p_x96, liquidity, amount_out, false)
+    }
+}
+
+/// Returns the amount of token0 between two prices for a given liquidity.
+pub fn get_amount_0_delta(
+    sqrt_ratio_a_x96: U256,
+    sqrt_ratio_b_x96: U256,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<U256, &'static str> {
+    let (sqrt_ratio_a_x96, sqrt_ratio_b_x96) = if sqrt_ratio_a_x96 > sqrt_ratio_b_x96 {
+        (sqrt_ratio_b_x96, sqrt_ratio_a_x96)
+    } else {
+        (sqrt_ratio_a_x96, sqrt_ratio_b_x96)
+    };
+    if sqrt_ratio_a_x96.is_zero() {
+        return Err("SPM");
+    }
+    let numerator_1 = U256::from(liquidity) << RESOLUTION;
+    let numerator_2 = sqrt_ratio_b_x96 - sqrt_ratio_a_x96;
+
+    if round_up {
+        let amount = mul_div_rounding_up(numerator_1, numerator_2, sqrt_ratio_b_x96).ok_or("MD")?;
+        Ok(div_rounding_up(amount, sqrt_ratio_a_x96))
+    } else {
+        Ok(mul_div(numerator_1, numerator_2, sqrt_ratio_b_x96).ok_or("MD")? / sqrt_ratio_a_x96)
+    }
+}
+
+/// Returns the amount of token1 between two prices for a given liquidity.
+pub fn get_amount_1_delta(
+    sqrt_ratio_a_x96: U256,
+    sqrt_ratio_b_x96: U256,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<U256, &'static str> {
+    let (sqrt_ratio_a_x96, sqrt_ratio_b_x96) = if sqrt_ratio_a_x96 > sqrt_ratio_b_x96 {
+        (sqrt_ratio_b_x96, sqrt_ratio_a_x96)
+    } else {
+        (sqrt_ratio_a_x96, sqrt_ratio_b_x96)
+    };
+    let liquidity = U256::from(liquidity);
+    let delta = sqrt_ratio_b_x96 - sqrt_ratio_a_x96;
+
+    if round_up {
+        mul_div_rounding_up(liquidity, delta, q96()).ok_or("MD")
+    } else {
+        mul_div(liquidity, delta, q96()).ok_or("MD")
+    }
+}
+
+//------------------------------------- SwapMath
+
+/// Fees are expressed in hundredths of a bip.
+const FEE_PIPS_DENOMINATOR: u32 = 1_000_000;
+
+/// Result of swapping within a single tick range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapStep {
+    pub sqrt_ratio_next_x96: U256,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub fee_amount: U256,
+}
+
+/// Computes the result of swapping some amount in or out given the parameters of the swap.
+/// `amount_remaining` is positive for exact input and negative for exact output.
+/// Port of [SwapMath.computeSwapStep](https://github.com/Uniswap/v3-core/blob/412d9b236a1e75a98568d49b1aeb21e3a1430544/contracts/libraries/SwapMath.sol).
+pub fn compute_swap_step(
+    sqrt_ratio_current_x96: U256,
+    sqrt_ratio_target_x96: U256,
+    liquidity: u128,
+    amount_remaining: I256,
+    fee_pips: u32,
+) -> Result<SwapStep, &'static str> {
+    let zero_for_one = sqrt_ratio_current_x96 >= sqrt_ratio_target_x96;
+    let exact_in = !amount_remaining.is_negative();
+    let amount_remaining_abs = amount_remaining.into_sign_and_abs().1;
+
+    let mut amount_in = U256::zero();
+    let mut amount_out = U256::zero();
+    let sqrt_ratio_next_x96;
+
+    if exact_in {
+        let amount_remaining_less_fee = mul_div(
+            amount_remaining_abs,
+            U256::from(FEE_PIPS_DENOMINATOR - fee_pips),
+            U256::from(FEE_PIPS_DENOMINATOR),
+        ).ok_or("MD")?;
+        amount_in = if zero_for_one {
+            get_amount_0_delta(sqrt_ratio_target_x96, sqrt_ratio_current_x96, liquidity, true)?
+        } else {
+            get_amount_1_delta(sqrt_ratio_current_x96, sqrt_ratio_target_x96, liquidity, true)?
+        };
+        sqrt_ratio_next_x96 = if amount_remaining_less_fee >= amount_in {
+            sqrt_ratio_target_x96
+        } else {
+            get_next_sqrt_price_from_input(
+                sqrt_ratio_current_x96,
+                liquidity,
+                amount_remaining_less_fee,
+                zero_for_one,
+            )?
+        };
+    } else {
+        amount_out = if zero_for_one {
+            get_amount_1_delta(sqrt_ratio_target_x96, sqrt_ratio_current_x96, liquidity, false)?
+        } else {
+            get_amount_0_delta(sqrt_ratio_current_x96, sqrt_ratio_target_x96, liquidity, false)?
+        };
+        sqrt_ratio_next_x96 = if amount_remaining_abs >= amount_out {
+            sqrt_ratio_target_x96
+        } else {
+            get_next_sqrt_price_from_output(
+                sqrt_ratio_current_x96,
+                liquidity,
+                amount_remaining_abs,
+                zero_for_one,
+            )?
+        };
+    }
+
+    let max = sqrt_ratio_target_x96 == sqrt_ratio_next_x96;
+
+    // get the input/output amounts
+    if zero_for_one {
+        if !max || !exact_in {
+            amount_in = get_amount_0_delta(sqrt_ratio_next_x96, sqrt_ratio_current_x96, liquidity, true)?;
+        }
+        if !max || exact_in {
+            amount_out = get_amount_1_delta(sqrt_ratio_next_x96, sqrt_ratio_current_x96, liquidity, false)?;
+        }
+    } else {
+        if !max || !exact_in {
+            amount_in = get_amount_1_delta(sqrt_ratio_current_x96, sqrt_ratio_next_x96, liquidity, true)?;
+        }
+        if !max || exact_in {
+            amount_out = get_amount_0_delta(sqrt_ratio_current_x96, sqrt_ratio_next_x96, liquidity, false)?;
+        }
+    }
+
+    // cap the output amount to not exceed the remaining output amount
+    if !exact_in && amount_out > amount_remaining_abs {
+        amount_out = amount_remaining_abs;
+    }
+
+    let fee_amount = if exact_in && sqrt_ratio_next_x96 != sqrt_ratio_target_x96 {
+        // we didn't reach the target, so take the remainder of the maximum input as fee
+        amount_remaining_abs - amount_in
+    } else {
+        mul_div_rounding_up(
+            amount_in,
+            U256::from(fee_pips),
+            U256::from(FEE_PIPS_DENOMINATOR - fee_pips),
+        ).ok_or("MD")?
+    };
+
+    Ok(SwapStep {
+        sqrt_ratio_next_x96,
+        amount_in,
+        amount_out,
+        fee_amount,
+    })
+}
+
+//------------------------------------- Swap
+
+/// Liquidity data of an initialized tick, as returned by `UniswapV3Pool.ticks()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TickInfo {
+    pub index: i32,
+    pub liquidity_gross: u128,
+    pub liquidity_net: i128,
+}
+
+/// Pool state after a simulated swap, with the signed token deltas from the pool's perspective.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapResult {
+    pub amount_0: I256,
+    pub amount_1: I256,
+    pub sqrt_price_x96: U256,
+    pub tick: i32,
+    pub liquidity: u128,
+}
+
+/// Returns the next initialized tick in the same 256 tick word as `tick`, or the word boundary if there is none,
+/// along with whether the returned tick is initialized. `ticks` must be sorted by index.
+/// Mirrors [TickBitmap.nextInitializedTickWithinOneWord](https://github.com/Uniswap/v3-core/blob/412d9b236a1e75a98568d49b1aeb21e3a1430544/contracts/libraries/TickBitmap.sol)
+/// so that the swap loop takes exactly the same steps as the pool.
+pub fn next_initialized_tick_within_one_word(
+    ticks: &[TickInfo],
+    tick: i32,
+    tick_spacing: i32,
+    lte: bool,
+) -> (i32, bool) {
+    let compressed = tick.div_euclid(tick_spacing);
+
+    if lte {
+        let word_start = (compressed >> 8) << 8;
+        let lower = word_start * tick_spacing;
+        let upper = compressed * tick_spacing;
+        // last initialized tick at or below `upper`
+        let i = ticks.partition_point(|t| t.index <= upper);
+        match i.checked_sub(1).map(|i| ticks[i].index) {
+            Some(index) if index >= lower => (index, true),
+            _ => (lower, false),
+        }
+    } else {
+        let compressed = compressed + 1;
+        let word_end = ((compressed >> 8) << 8) + 255;
+        let lower = compressed * tick_spacing;
+        let upper = word_end * tick_spacing;
+        // first initialized tick at or above `lower`
+        let i = ticks.partition_point(|t| t.index < lower);
+        match ticks.get(i).map(|t| t.index) {
+            Some(index) if index <= upper => (index, true),
+            _ => (upper, false),
+        }
+    }
+}
+
+/// Simulates `UniswapV3Pool.swap` on `state` without modifying it.
+/// `ticks` holds every initialized tick the swap may cross, sorted by index.
+/// `amount_specified` is positive for exact input and negative for exact output, as on-chain.
+/// Returns the pool's revert reason if the swap would revert.
+pub fn swap(
+    state: &PoolState,
+    ticks: &[TickInfo],
+    tick_spacing: i32,
+    fee: u32,
+    zero_for_one: bool,
+    amount_specified: I256,
+    sqrt_price_limit_x96: U256,
+) -> Result<SwapResult, &'static str> {
+    if amount_specified.is_zero() {
+        return Err("AS");
+    }
+    if !state.unlocked {
+        return Err("LOK");
+    }
+    let limit_is_valid = if zero_for_one {
+        sqrt_price_limit_x96 < state.sqrt_price_x96 && sqrt_price_limit_x96 > min_sqrt_ratio()
+    } else {
+        sqrt_price_limit_x96 > state.sqrt_price_x96 && sqrt_price_limit_x96 < max_sqrt_ratio()
+    };
+    if !limit_is_valid {
+        return Err("SPL");
+    }
+
+    let exact_input = amount_specified.is_positive();
+    let mut amount_specified_remaining = amount_specified;
+    let mut amount_calculated = I256::zero();
+    let mut sqrt_price_x96 = state.sqrt_price_x96;
+    let mut tick = state.tick;
+    let mut liquidity = state.liquidity;
+
+    while !amount_specified_remaining.is_zero() && sqrt_price_x96 != sqrt_price_limit_x96 {
+        let sqrt_price_start_x96 = sqrt_price_x96;
+
+        let (tick_next, initialized) = next_initialized_tick_within_one_word(
+            ticks,
+            tick,
+            tick_spacing,
+            zero_for_one,
+        );
+        // the tick bitmap is not aware of the min/max tick bounds
+        let tick_next = tick_next.clamp(MIN_TICK, MAX_TICK);
+        let sqrt_price_next_x96 = get_sqrt_ratio_at_tick(tick_next)?;
+
+        let target_is_limit = if zero_for_one {
+            sqrt_price_next_x96 < sqrt_price_limit_x96
+        } else {
+            sqrt_price_next_x96 > sqrt_price_limit_x96
+        };
+        let step = compute_swap_step(
+            sqrt_price_x96,
+            if target_is_limit { sqrt_price_limit_x96 } else { sqrt_price_next_x96 },
+            liquidity,
+            amount_specified_remaining,
+            fee,
+        )?;
+        sqrt_price_x96 = step.sqrt_ratio_next_x96;
+
+        let amount_in = I256::try_from(step.amount_in + step.fee_amount).map_err(|_| "SC")?;
+        let amount_out = I256::try_from(step.amount_out).map_err(|_| "SC")?;
+        if exact_input {
+            amount_specified_remaining -= amount_in;
+            amount_calculated -= amount_out;
+        } else {
+            amount_specified_remaining += amount_out;
+            amount_calculated += amount_in;
+        }
+
+        if sqrt_price_x96 == sqrt_price_next_x96 {
+            // crossing an initialized tick moves its net liquidity in or out of range
+            if initialized {
+                let liquidity_net = ticks
+                    .binary_search_by_key(&tick_next, |t| t.index)
+                    .map(|i| ticks[i].liquidity_net)
+                    .unwrap_or(0);
+                let liquidity_net = if zero_for_one { -liquidity_net } else { liquidity_net };
+                liquidity = add_delta(liquidity, liquidity_net)?;
+            }
+            tick = if zero_for_one { tick_next - 1 } else { tick_next };
+        } else if sqrt_price_x96 != sqrt_price_start_x96 {
+            tick = get_tick_at_sqrt_ratio(sqrt_price_x96)?;
+        }
+    }
+
+    let (amount_0, amount_1) = if zero_for_one == exact_input {
+        (amount_specified - amount_specified_remaining, amount_calculated)
+    } else {
+        (amount_calculated, amount_specified - amount_specified_remaining)
+    };
+
+    Ok(SwapResult {
+        amount_0,
+        amount_1,
+        sqrt_price_x96,
+        tick,
+        liquidity,
+    })
+}
+
+/// Returns the amount of output token received for exactly `amount_in` of input token,
+/// pushing the price as far as the pool allows.
+pub fn get_amount_out(
+    state: &PoolState,
+    ticks: &[TickInfo],
+    tick_spacing: i32,
+    fee: u32,
+    zero_for_one: bool,
+    amount_in: U256,
+) -> Result<U256, &'static str> {
+    let sqrt_price_limit_x96 = if zero_for_one {
+        min_sqrt_ratio() + 1
+    } else {
+        max_sqrt_ratio() - 1
+    };
+    let amount_specified = I256::try_from(amount_in).map_err(|_| "SC")?;
+    let result = swap(state, ticks, tick_spacing, fee, zero_for_one, amount_specified, sqrt_price_limit_x96)?;
+    let amount_out = if zero_for_one { result.amount_1 } else { result.amount_0 };
+    Ok(amount_out.into_sign_and_abs().1)
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test vectors from https://github.com/Uniswap/v3-core/tree/412d9b236a1e75a98568d49b1aeb21e3a1430544/test
+
+    fn u(n: &str) -> U256 {
+        U256::from_dec_str(n).unwrap()
+    }
+
+    fn e18(n: u64) -> U256 {
+        U256::from(n) * U256::exp10(18)
+    }
+
+    /// `encodePriceSqrt(1, 1)`
+    fn price_1_1() -> U256 {
+        u("79228162514264337593543950336")
+    }
+
+    /// `encodePriceSqrt(121, 100)`
+    fn price_121_100() -> U256 {
+        u("87150978765690771352898345369")
+    }
+
+    #[test]
+    fn test_get_sqrt_ratio_at_tick() {
+        assert_eq!(get_sqrt_ratio_at_tick(MIN_TICK - 1), Err("T"));
+        assert_eq!(get_sqrt_ratio_at_tick(MAX_TICK + 1), Err("T"));
+        assert_eq!(get_sqrt_ratio_at_tick(MIN_TICK), Ok(u("4295128739")));
+        assert_eq!(get_sqrt_ratio_at_tick(MIN_TICK + 1), Ok(u("4295343490")));
+        assert_eq!(get_sqrt_ratio_at_tick(MAX_TICK - 1), Ok(u("1461373636630004318706518188784493106690254656249")));
+        assert_eq!(get_sqrt_ratio_at_tick(MAX_TICK), Ok(u("1461446703485210103287273052203988822378723970342")));
+        assert_eq!(get_sqrt_ratio_at_tick(0), Ok(price_1_1()));
+    }
+
+    #[test]
+    fn test_get_tick_at_sqrt_ratio() {
+        assert_eq!(get_tick_at_sqrt_ratio(min_sqrt_ratio() - 1), Err("R"));
+        assert_eq!(get_tick_at_sqrt_ratio(max_sqrt_ratio()), Err("R"));
+        assert_eq!(get_tick_at_sqrt_ratio(min_sqrt_ratio()), Ok(MIN_TICK));
+        assert_eq!(get_tick_at_sqrt_ratio(u("4295343490")), Ok(MIN_TICK + 1));
+        assert_eq!(get_tick_at_sqrt_ratio(u("1461373636630004318706518188784493106690254656249")), Ok(MAX_TICK - 1));
+        assert_eq!(get_tick_at_sqrt_ratio(max_sqrt_ratio() - 1), Ok(MAX_TICK - 1));
+        assert_eq!(get_tick_at_sqrt_ratio(price_1_1()), Ok(0));
+    }
+
+    #[test]
+    fn test_tick_math_round_trip() {
+        for tick in [-500000, -60000, -887, -1, 0, 1, 60, 887, 60000, 500000] {
+            let ratio = get_sqrt_ratio_at_tick(tick).unwrap();
+            assert_eq!(get_tick_at_sqrt_ratio(ratio), Ok(tick));
+            assert_eq!(get_tick_at_sqrt_ratio(ratio - 1), Ok(tick - 1));
+        }
+    }
+
+    #[test]
+    fn test_get_next_sqrt_price_from_input() {
+        assert_eq!(get_next_sqrt_price_from_input(U256::zero(), 0, e18(1) / 10, false), Err("SPM"));
+        assert_eq!(get_next_sqrt_price_from_input(U256::one(), 0, e18(1) / 10, true), Err("SPM"));
+        assert_eq!(get_next_sqrt_price_from_input(price_1_1(), 10u128.pow(18), U256::zero(), true), Ok(price_1_1()));
+        assert_eq!(
+            get_next_sqrt_price_from_input(price_1_1(), 10u128.pow(18), e18(1) / 10, false),
+            Ok(u("87150978765690771352898345369")),
+        );
+        assert_eq!(
+            get_next_sqrt_price_from_input(price_1_1(), 10u128.pow(18), e18(1) / 10, true),
+            Ok(u("72025602285694852357767227579")),
+        );
+        assert_eq!(
+            get_next_sqrt_price_from_input(price_1_1(), 10u128.pow(19), U256::one() << 100, true),
+            Ok(u("624999999995069620")),
+        );
+        assert_eq!(
+            get_next_sqrt_price_from_input(price_1_1(), 1, U256::MAX / 2, true),
+            Ok(U256::one()),
+        );
+    }
+
+    #[test]
+    fn test_get_next_sqrt_price_from_output() {
+        assert_eq!(get_next_sqrt_price_from_output(U256::zero(), 0, e18(1) / 10, false), Err("SPM"));
+        assert_eq!(
+            get_next_sqrt_price_from_output(price_1_1(), 10u128.pow(18), e18(1) / 10, false),
+            Ok(u("88031291682515930659493278152")),
+        );
+        assert_eq!(
+            get_next_sqrt_price_from_output(price_1_1(), 10u128.pow(18), e18(1) / 10, true),
+            Ok(u("71305346262837903834189555302")),
+        );
+        // output amount equal to the virtual reserves of token0
+        assert_eq!(
+            get_next_sqrt_price_from_output(u("20282409603651670423947251286016"), 1024, U256::from(4), false),
+            Err("SPM"),
+        );
+        // output amount equal to the virtual reserves of token1
+        assert_eq!(
+            get_next_sqrt_price_from_output(u("20282409603651670423947251286016"), 1024, U256::from(262144), true),
+            Err("SPM"),
+        );
+    }
+
+    #[test]
+    fn test_get_amount_deltas() {
+        assert_eq!(get_amount_0_delta(price_1_1(), price_1_1(), 0, true), Ok(U256::zero()));
+        assert_eq!(get_amount_0_delta(price_1_1(), price_121_100(), 10u128.pow(18), true), Ok(u("90909090909090910")));
+        assert_eq!(get_amount_0_delta(price_1_1(), price_121_100(), 10u128.pow(18), false), Ok(u("90909090909090909")));
+        assert_eq!(get_amount_1_delta(price_1_1(), price_121_100(), 10u128.pow(18), true), Ok(u("100000000000000000")));
+        assert_eq!(get_amount_1_delta(price_1_1(), price_121_100(), 10u128.pow(18), false), Ok(u("99999999999999999")));
+    }
+
+    #[test]
+    fn test_compute_swap_step() {
+        // exact amount in that gets capped at price target in one for zero
+        let price_target = u("79623317895830914510639640423");
+        let step = compute_swap_step(price_1_1(), price_target, 2 * 10u128.pow(18), I256::from(10i128.pow(18)), 600).unwrap();
+        assert_eq!(step.amount_in, u("9975124224178055"));
+        assert_eq!(step.fee_amount, u("5988667735148"));
+        assert_eq!(step.amount_out, u("9925619580021728"));
+        assert_eq!(step.sqrt_ratio_next_x96, price_target);
+
+        // entire input amount taken as fee
+        let step = compute_swap_step(
+            U256::from(2413),
+            u("79887613182836312"),
+            1985041575832132834610021537970,
+            I256::from(10),
+            1872,
+        ).unwrap();
+        assert_eq!(step.amount_in, U256::zero());
+        assert_eq!(step.fee_amount, U256::from(10));
+        assert_eq!(step.amount_out, U256::zero());
+        assert_eq!(step.sqrt_ratio_next_x96, U256::from(2413));
+
+        // handles intermediate insufficient liquidity in zero for one exact output case
+        let price = u("20282409603651670423947251286016");
+        let step = compute_swap_step(price, price * 11 / 10, 1024, I256::from(-4), 3000).unwrap();
+        assert_eq!(step.amount_out, U256::zero());
+        assert_eq!(step.sqrt_ratio_next_x96, price * 11 / 10);
+        assert_eq!(step.amount_in, U256::from(26215));
+        assert_eq!(step.fee_amount, U256::from(79));
+
+        // handles intermediate insufficient liquidity in one for zero exact output case
+        let step = compute_swap_step(price, price * 9 / 10, 1024, I256::from(-263000), 3000).unwrap();
+        assert_eq!(step.amount_out, U256::from(26214));
+        assert_eq!(step.sqrt_ratio_next_x96, price * 9 / 10);
+        assert_eq!(step.amount_in, U256::one());
+        assert_eq!(step.fee_amount, U256::one());
+    }
+
+    #[test]
+    fn test_next_initialized_tick_within_one_word() {
+        // initialized ticks from the TickBitmap spec, with a tick spacing of 1
+        let ticks: Vec<TickInfo> = [-200, -55, -4, 70, 78, 84, 139, 240, 535]
+            .iter()
+            .map(|&index| TickInfo { index, liquidity_gross: 1, liquidity_net: 1 })
+            .collect();
+
+        assert_eq!(next_initialized_tick_within_one_word(&ticks, 78, 1, false), (84, true));
+        assert_eq!(next_initialized_tick_within_one_word(&ticks, -55, 1, false), (-4, true));
+        assert_eq!(next_initialized_tick_within_one_word(&ticks, 77, 1, false), (78, true));
+        assert_eq!(next_initialized_tick_within_one_word(&ticks, 255, 1, false), (511, false));
+        assert_eq!(next_initialized_tick_within_one_word(&ticks, -257, 1, false), (-200, true));
+
+        assert_eq!(next_initialized_tick_within_one_word(&ticks, 78, 1, true), (78, true));
+        assert_eq!(next_initialized_tick_within_one_word(&ticks, 79, 1, true), (78, true));
+        assert_eq!(next_initialized_tick_within_one_word(&ticks, 258, 1, true), (256, false));
+        assert_eq!(next_initialized_tick_within_one_word(&ticks, 256, 1, true), (256, false));
+        assert_eq!(next_initialized_tick_within_one_word(&ticks, 72, 1, true), (70, true));
+        assert_eq!(next_initialized_tick_within_one_word(&ticks, -257, 1, true), (-512, false));
+    }
+
+    /// Pool at price 1 with a full range position of `10^18` and a narrower position of `10^18` over `[-600, 600]`.
+    fn test_pool() -> (PoolState, Vec<TickInfo>) {
+        let state = PoolState::new((price_1_1(), 0, 0, 1, 1, 0, true), 2 * 10u128.pow(18), 18, 18);
+        let min_tick = -887220;
+        let ticks = vec![
+            TickInfo { index: min_tick, liquidity_gross: 10u128.pow(18), liquidity_net: 10i128.pow(18) },
+            TickInfo { index: -600, liquidity_gross: 10u128.pow(18), liquidity_net: 10i128.pow(18) },
+            TickInfo { index: 600, liquidity_gross: 10u128.pow(18), liquidity_net: -(10i128.pow(18)) },
+            TickInfo { index: -min_tick, liquidity_gross: 10u128.pow(18), liquidity_net: -(10i128.pow(18)) },
+        ];
+        (state, ticks)
+    }
+
+    #[test]
+    fn test_swap_within_range() {
+        let (state, ticks) = test_pool();
+        let amount_in = e18(1) / 1000;
+        let result = swap(&state, &ticks, 60, 3000, true, I256::try_from(amount_in).unwrap(), min_sqrt_ratio() + 1).unwrap();
+
+        // a single step: the whole input is consumed and matches the swap step output
+        let step = compute_swap_step(
+            price_1_1(),
+            get_sqrt_ratio_at_tick(-600).unwrap(),
+            state.liquidity,
+            I256::try_from(amount_in).unwrap(),
+            3000,
+        ).unwrap();
+        assert_eq!(result.amount_0, I256::try_from(amount_in).unwrap());
+        assert_eq!(result.amount_1, -I256::try_from(step.amount_out).unwrap());
+        assert_eq!(result.sqrt_price_x96, step.sqrt_ratio_next_x96);
+        assert_eq!(result.tick, get_tick_at_sqrt_ratio(step.sqrt_ratio_next_x96).unwrap());
+        assert_eq!(result.liquidity, state.liquidity);
+    }
+
+    #[test]
+    fn test_swap_crosses_ticks() {
+        let (state, ticks) = test_pool();
+
+        // push the price through tick 600, leaving only the full range position in range
+        let result = swap(&state, &ticks, 60, 3000, false, I256::try_from(e18(1)).unwrap(), max_sqrt_ratio() - 1).unwrap();
+        assert_eq!(result.amount_1, I256::try_from(e18(1)).unwrap());
+        assert!(result.amount_0.is_negative());
+        assert!(result.tick >= 600);
+        assert_eq!(result.liquidity, 10u128.pow(18));
+
+        // stopping at the limit price only consumes part of the input
+        let limit = get_sqrt_ratio_at_tick(600).unwrap();
+        let result = swap(&state, &ticks, 60, 3000, false, I256::try_from(e18(1)).unwrap(), limit).unwrap();
+        assert_eq!(result.sqrt_price_x96, limit);
+        assert_eq!(result.tick, 600);
+        assert_eq!(result.liquidity, 10u128.pow(18));
+        assert!(result.amount_1 < I256::try_from(e18(1)).unwrap());
+    }
+
+    #[test]
+    fn test_swap_exact_output_matches_exact_input() {
+        let (state, ticks) = test_pool();
+        let exact_in = swap(&state, &ticks, 60, 3000, true, I256::try_from(e18(1)).unwrap(), min_sqrt_ratio() + 1).unwrap();
+
+        // asking for the output of an exact input swap never requires more input
+        let exact_out = swap(&state, &ticks, 60, 3000, true, exact_in.amount_1, min_sqrt_ratio() + 1).unwrap();
+        assert_eq!(exact_out.amount_1, exact_in.amount_1);
+        assert!(exact_out.amount_0 <= exact_in.amount_0);
+    }
+
+    #[test]
+    fn test_swap_reverts() {
+        let (state, ticks) = test_pool();
+        assert_eq!(swap(&state, &ticks, 60, 3000, true, I256::zero(), min_sqrt_ratio() + 1), Err("AS"));
+        assert_eq!(swap(&state, &ticks, 60, 3000, true, I256::from(1), price_1_1()), Err("SPL"));
+        assert_eq!(swap(&state, &ticks, 60, 3000, false, I256::from(1), max_sqrt_ratio()), Err("SPL"));
+    }
+}
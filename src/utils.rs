@@ -1,5 +1,6 @@
 use std::{
     fs::read_to_string,
+    time::Duration,
 };
 use ethers::{
     types::{Address, },
@@ -7,21 +8,90 @@ use ethers::{
     prelude::U256,
 };
 
+use crate::transactions::PriorityFeeStrategy;
+use crate::error::{ BotError, BotResult, };
+
 const TWO: f64 = 2.;
 const TEN: f64 = 10.;
+/// Denominator of `UniswapV3Pool.fee()`, which is expressed in hundredths of a bip.
+const FEE_DENOMINATOR: f64 = 1_000_000.;
 
 /// Reads CLI arguments and returns the parameters.
 /// CLI argument is the network name.
 /// Returns chain id and RPC provider url.
-pub fn read_args(args: Vec<String>) -> (u32, String) {
-    let network_name = &args[1];
+pub fn read_args(args: Vec<String>) -> BotResult<(u32, String)> {
+    let (chain_id, key) = read_network(&args)?;
+    let provider_url = dotenv::var(key)
+        .map_err(|_| BotError::Config(format!("{} not found in .env", key)))?;
+    Ok((chain_id, provider_url))
+}
+
+/// Reads the network name from the CLI arguments.
+/// Returns chain id and the .env key of the RPC provider url.
+pub fn read_network(args: &[String]) -> BotResult<(u32, &'static str)> {
+    let network_name = args
+        .get(1)
+        .ok_or_else(|| BotError::Config("Missing CLI argument. Must be one of 'mainnet', 'goerli', 'optimism', 'polygon', arbitrum'.".to_string()))?;
     match network_name.as_str() {
-        "mainnet" => (1, dotenv::var("MAINNET_RPC_URL").expect("Mainnet provider not found in .env")),
-        "goerli" => (5, dotenv::var("GOERLI_RPC_URL").expect("Goerli provider not found in .env")),
-        "optimism" => (10, dotenv::var("OPTIMISM_RPC_URL").expect("Optimism provider not found in .env")),
-        "polygon" => (137, dotenv::var("POLYGON_RPC_URL").expect("Polygon provider not found in .env")),
-        "arbitrum" => (42161, dotenv::var("ARBITRUM_RPC_URL").expect("Arbitrum provider not found in .env")),
-        n => panic!("Invalid CLI argument {}. Must be one of 'mainnet', 'goerli', 'optimism', 'polygon', arbitrum'.", n),
+        "mainnet" => Ok((1, "MAINNET_RPC_URL")),
+        "goerli" => Ok((5, "GOERLI_RPC_URL")),
+        "optimism" => Ok((10, "OPTIMISM_RPC_URL")),
+        "polygon" => Ok((137, "POLYGON_RPC_URL")),
+        "arbitrum" => Ok((42161, "ARBITRUM_RPC_URL")),
+        n => Err(BotError::Config(format!("Invalid CLI argument {}. Must be one of 'mainnet', 'goerli', 'optimism', 'polygon', arbitrum'.", n))),
+    }
+}
+
+/// Settings of the bot's run loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunConfig {
+    /// How often the node is polled for a new block.
+    pub poll_interval: Duration,
+    /// Maximum duration of a single iteration, after which it is abandoned and the bot waits for the next block.
+    pub iteration_timeout: Duration,
+    /// Maximum number of calls batched in a single Multicall `eth_call`.
+    pub multicall_chunk_size: usize,
+    /// Share of the expected profit of a cycle, in basis points, its simulation must reach for it to be sent.
+    pub min_profit_bps: u64,
+    /// Whether the simulations that revert are traced with `debug_traceCall` to find the failing hop.
+    pub trace_simulations: bool,
+    /// How the priority fee of the executor transactions is chosen.
+    pub priority_fee: PriorityFeeStrategy,
+}
+
+impl RunConfig {
+    /// Reads `POLL_INTERVAL_MS`, `ITERATION_TIMEOUT_MS`, `MULTICALL_CHUNK_SIZE`, `MIN_PROFIT_BPS` and `TRACE_SIMULATIONS` from .env,
+    /// defaulting to 1 second, 10 seconds, 200 calls, half of the expected profit and no tracing,
+    /// along with the priority fee strategy of `PriorityFeeStrategy::from_env`.
+    pub fn from_env() -> BotResult<Self> {
+        Self::parse(|key| dotenv::var(key).ok())
+    }
+
+    /// `from_env` with the variables looked up by `var`.
+    pub fn parse(var: impl Fn(&str) -> Option<String>) -> BotResult<Self> {
+        let read = |key: &str, default: u64| -> BotResult<u64> {
+            match var(key) {
+                Some(value) => value
+                    .parse::<u64>()
+                    .map_err(|_| BotError::Config(format!("{} must be a non-negative integer, got {}.", key, value))),
+                None => Ok(default),
+            }
+        };
+        // a zero interval panics in `tokio::time::interval`, a zero timeout skips every iteration
+        let read_positive = |key: &str, default: u64| -> BotResult<u64> {
+            match read(key, default)? {
+                0 => Err(BotError::Config(format!("{} must be a positive integer, got 0.", key))),
+                value => Ok(value),
+            }
+        };
+        Ok(Self {
+            poll_interval: Duration::from_millis(read_positive("POLL_INTERVAL_MS", 1_000)?),
+            iteration_timeout: Duration::from_millis(read_positive("ITERATION_TIMEOUT_MS", 10_000)?),
+            multicall_chunk_size: read("MULTICALL_CHUNK_SIZE", 200)?.max(1) as usize,
+            min_profit_bps: read("MIN_PROFIT_BPS", 5_000)?,
+            trace_simulations: read("TRACE_SIMULATIONS", 0)? != 0,
+            priority_fee: PriorityFeeStrategy::parse(&var)?,
+        })
     }
 }
 
@@ -34,65 +104,269 @@ pub fn sqrtPriceX86_to_log_price(
     decimals_0: u8,
     decimals_1: u8,
 ) -> (f64, f64) {
-    let mut p0p1: f64 = 0.;
-    if sqrt.bits() > 128 {
-        for b in 0..32 {
-            p0p1 += (sqrt.byte(b) as f64) * 255. * (b as f64);
-        }
-    } else {
-        p0p1 = sqrt.as_u128() as f64;
-    }
-    p0p1 = p0p1.powi(2) / TWO.powi(192) * TEN.powi(decimals_0 as i32 - decimals_1 as i32);
-    return (-p0p1.ln(), -(1./p0p1).ln());
+    // in log space, the square of a sqrt price of up to 160 bits is far from the range of f64 anyway
+    let log_p0p1 = 2. * u256_to_f64(sqrt).ln() - 192. * TWO.ln() + (decimals_0 as i32 - decimals_1 as i32) as f64 * TEN.ln();
+    (-log_p0p1, log_p0p1)
+}
+
+/// Returns the price of a constant product pair with reserves `reserve_0` and `reserve_1` to pass to a `Graph` instance,
+/// in the same form as `sqrtPriceX86_to_log_price`.
+pub fn reserves_to_log_price(
+    reserve_0: U256,
+    reserve_1: U256,
+    decimals_0: u8,
+    decimals_1: u8,
+) -> (f64, f64) {
+    // reserves are uint112 on chain
+    let p0p1 = reserve_1.as_u128() as f64 / reserve_0.as_u128() as f64 * TEN.powi(decimals_0 as i32 - decimals_1 as i32);
+    (-p0p1.ln(), -(1./p0p1).ln())
+}
+
+/// Returns the weight of an edge swapping `amount_in` for `amount_out`, in the same form as `sqrtPriceX86_to_log_price`.
+/// Used for the pools without a closed form spot price, by quoting a swap small enough to not move the price.
+pub fn amounts_to_log_price(
+    amount_in: U256,
+    amount_out: U256,
+    decimals_in: u8,
+    decimals_out: u8,
+) -> f64 {
+    let price = u256_to_f64(amount_out) / u256_to_f64(amount_in) * TEN.powi(decimals_in as i32 - decimals_out as i32);
+    -price.ln()
+}
+
+/// Converts `value` to the nearest f64, through its high and low 128 bits.
+fn u256_to_f64(value: U256) -> f64 {
+    (value >> 128).low_u128() as f64 * TWO.powi(128) + value.low_u128() as f64
+}
+
+/// Returns the weight to add to a log price to account for the pool fee.
+/// `fee` is expressed in hundredths of a bip, e.g. `3000.` for a 0.3% pool.
+/// The result is non-negative, so fees can only make a cycle less negative.
+pub fn fee_to_log_weight(fee: f32) -> f64 {
+    -(1. - fee as f64 / FEE_DENOMINATOR).ln()
 }
 
 //------------------------------------- ABIs
 
+/// Reads and parses the ABI stored at `path`.
+fn read_abi(path: &str) -> BotResult<Abi> {
+    let abi: String = read_to_string(path)
+        .map_err(|e| BotError::Abi(format!("Failed to read {}: {}", path, e)))?;
+    serde_json::from_str(&abi)
+        .map_err(|e| BotError::Abi(format!("Failed to parse the content of {} to ABI. Please check format: {}", path, e)))
+}
+
 /// Returns the ABI of [IUniswapV3Factory](https://github.com/Uniswap/v3-core/blob/412d9b236a1e75a98568d49b1aeb21e3a1430544/contracts/interfaces/IUniswapV3Factory.sol).
-pub fn i_univ3_factory_abi() -> Abi {
-    let path = "config/univ3/IUniswapV3FactoryABI.json".to_string();
-    let error = format!("File not found: {}", &path);
-    let abi: String = read_to_string(&path)
-        .expect(&error)
-        .parse()
-        .expect("Failed to parse ABI.");
-    let error: String = format!("Failed to parse the content of {} to ABI. Please check format.", &path);
-    serde_json::from_str(&abi).expect(&error)
+pub fn i_univ3_factory_abi() -> BotResult<Abi> {
+    read_abi("config/univ3/IUniswapV3FactoryABI.json")
 }
 
 /// Returns the ABI of [IUniswapV3Pool](https://github.com/Uniswap/v3-core/blob/412d9b236a1e75a98568d49b1aeb21e3a1430544/contracts/interfaces/IUniswapV3Pool.sol).
-pub fn i_univ3_pool_abi() -> Abi {
-    let path = "config/univ3/IUniswapV3PoolABI.json".to_string();
-    let error = format!("File not found: {}", &path);
-    let abi: String = read_to_string(&path)
-        .expect(&error)
-        .parse()
-        .expect("Failed to parse ABI.");
-    let error: String = format!("Failed to parse the content of {} to ABI. Please check format.", &path);
-    serde_json::from_str(&abi).expect(&error)
+pub fn i_univ3_pool_abi() -> BotResult<Abi> {
+    read_abi("config/univ3/IUniswapV3PoolABI.json")
+}
+
+/// Returns the ABI of [Multicall3](https://github.com/mds1/multicall/blob/main/src/Multicall3.sol).
+pub fn i_multicall3_abi() -> BotResult<Abi> {
+    read_abi("config/multicall/Multicall3ABI.json")
+}
+
+/// Returns the ABI of [IUniswapV2Factory](https://github.com/Uniswap/v2-core/blob/master/contracts/interfaces/IUniswapV2Factory.sol).
+pub fn i_univ2_factory_abi() -> BotResult<Abi> {
+    read_abi("config/univ2/IUniswapV2FactoryABI.json")
+}
+
+/// Returns the ABI of [IUniswapV2Pair](https://github.com/Uniswap/v2-core/blob/master/contracts/interfaces/IUniswapV2Pair.sol).
+pub fn i_univ2_pair_abi() -> BotResult<Abi> {
+    read_abi("config/univ2/IUniswapV2PairABI.json")
+}
+
+/// Returns the ABI of the [Curve Registry](https://curve.readthedocs.io/registry-registry.html).
+pub fn i_curve_registry_abi() -> BotResult<Abi> {
+    read_abi("config/curve/ICurveRegistryABI.json")
+}
+
+/// Returns the ABI of a [Curve StableSwap pool](https://curve.readthedocs.io/exchange-pools.html).
+pub fn i_curve_pool_abi() -> BotResult<Abi> {
+    read_abi("config/curve/ICurvePoolABI.json")
+}
+
+/// Returns the ABI of the [Balancer V2 Vault](https://docs.balancer.fi/reference/contracts/apis/vault.html).
+pub fn i_balancer_vault_abi() -> BotResult<Abi> {
+    read_abi("config/balancer/IVaultABI.json")
+}
+
+/// Returns the ABI of the getters shared by the Balancer V2 weighted and stable pools.
+pub fn i_balancer_pool_abi() -> BotResult<Abi> {
+    read_abi("config/balancer/IBasePoolABI.json")
+}
+
+/// Returns the ABI of the [Algebra Factory](https://github.com/cryptoalgebra/Algebra/blob/master/src/core/contracts/interfaces/IAlgebraFactory.sol).
+pub fn i_algebra_factory_abi() -> BotResult<Abi> {
+    read_abi("config/algebra/IAlgebraFactoryABI.json")
+}
+
+/// Returns the ABI of the [Algebra Pool](https://github.com/cryptoalgebra/Algebra/blob/master/src/core/contracts/interfaces/IAlgebraPool.sol).
+pub fn i_algebra_pool_abi() -> BotResult<Abi> {
+    read_abi("config/algebra/IAlgebraPoolABI.json")
+}
+
+/// Returns the ABI of [IERC20Metadata](https://github.com/OpenZeppelin/openzeppelin-contracts/blob/master/contracts/token/ERC20/extensions/IERC20Metadata.sol).
+pub fn i_erc20_metadata_abi() -> BotResult<Abi> {
+    read_abi("config/erc20/IERC20MetadataABI.json")
+}
+
+/// Returns the ABI of the `FlashArbExecutor` contract of `contracts/`.
+pub fn i_executor_abi() -> BotResult<Abi> {
+    read_abi("config/executor/FlashArbExecutorABI.json")
 }
 
 //------------------------------------- Contract addresses
 
+/// Parses the hard-coded address `addr`.
+fn parse_addr(addr: &str) -> BotResult<Address> {
+    addr.parse::<Address>()
+        .map_err(|e| BotError::Config(format!("Failed to parse address {}: {}", addr, e)))
+}
+
 /// Returns the address of the deployed instance of `[UniswapV3Factory](https://docs.uniswap.org/protocol/reference/core/UniswapV3Factory)` on network with chain id `chain_id`.
-/// Panics if `chain_id` is unknown.
-pub fn univ3_factory_addr(chain_id: u32) -> Address {
+pub fn univ3_factory_addr(chain_id: u32) -> BotResult<Address> {
+    match chain_id {
+        1 | 5 | 10 | 137 | 42161 => parse_addr("0x1F98431c8aD98523631AE4a59f267346ea31F984"),
+        n => Err(BotError::Config(format!("Unknown chain id {}.", n))),
+    }
+}
+
+/// Returns the factories of the Uniswap V2 forks deployed on network with chain id `chain_id`,
+/// along with the fee of their pairs in hundredths of a bip.
+pub fn univ2_factories(chain_id: u32) -> BotResult<Vec<(Address, u32)>> {
+    let factories: Vec<(&str, u32)> = match chain_id {
+        // Uniswap V2, SushiSwap
+        1 => vec![
+            ("0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f", 3000),
+            ("0xC0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac", 3000),
+        ],
+        // Uniswap V2
+        5 => vec![("0x5C69bEe701ef814a2B6a3EDD4B1652CB9cc5aA6f", 3000)],
+        10 => vec![],
+        // QuickSwap, SushiSwap
+        137 => vec![
+            ("0x5757371414417b8C6CAad45bAeF941aBc7d3Ab32", 3000),
+            ("0xc35DADB65012eC5796536bD9864eD8773aBc74C4", 3000),
+        ],
+        // SushiSwap
+        42161 => vec![("0xc35DADB65012eC5796536bD9864eD8773aBc74C4", 3000)],
+        n => return Err(BotError::Config(format!("Unknown chain id {}.", n))),
+    };
+    factories
+        .into_iter()
+        .map(|(addr, fee)| Ok((parse_addr(addr)?, fee)))
+        .collect()
+}
+
+/// Returns the address of the deployed instance of `[Multicall3](https://www.multicall3.com/deployments)` on network with chain id `chain_id`.
+/// Can be overridden with `MULTICALL_ADDRESS` in .env for chains where it is deployed elsewhere.
+pub fn multicall_addr(chain_id: u32) -> BotResult<Address> {
+    if let Ok(addr) = dotenv::var("MULTICALL_ADDRESS") {
+        return parse_addr(&addr);
+    }
+    match chain_id {
+        1 | 5 | 10 | 137 | 42161 => parse_addr("0xcA11bde05977b3631167028862bE2a179041CA11"),
+        n => Err(BotError::Config(format!("Unknown chain id {}.", n))),
+    }
+}
+
+/// Returns the address of the Algebra Factory deployed by QuickSwap V3 on network with chain id `chain_id`,
+/// `None` if there is none.
+pub fn algebra_factory_addr(chain_id: u32) -> BotResult<Option<Address>> {
+    match chain_id {
+        137 => parse_addr("0x411b0fAcC3489691f28ad58c47006AF5E3Ab3A28").map(Some),
+        1 | 5 | 10 | 42161 => Ok(None),
+        n => Err(BotError::Config(format!("Unknown chain id {}.", n))),
+    }
+}
+
+/// Returns the address of the wrapped native token on network with chain id `chain_id`, in which gas is priced.
+pub fn wrapped_native_addr(chain_id: u32) -> BotResult<Address> {
     match chain_id {
-        1 => "0x1F98431c8aD98523631AE4a59f267346ea31F984"
-            .parse::<Address>()
-            .expect("Failed to parse address."),
-        5 => "0x1F98431c8aD98523631AE4a59f267346ea31F984"
-            .parse::<Address>()
-            .expect("Failed to parse address."),
-        10 => "0x1F98431c8aD98523631AE4a59f267346ea31F984"
-            .parse::<Address>()
-            .expect("Failed to parse address."),
-        137 => "0x1F98431c8aD98523631AE4a59f267346ea31F984"
-            .parse::<Address>()
-            .expect("Failed to parse address."),
-        42161 => "0x1F98431c8aD98523631AE4a59f267346ea31F984"
-            .parse::<Address>()
-            .expect("Failed to parse address."),
-        n => panic!("Unknown chain id {}.", n),
+        1 => parse_addr("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"),
+        5 => parse_addr("0xB4FBF271143F4FBf7B91A5ded31805e42b2208d6"),
+        10 => parse_addr("0x4200000000000000000000000000000000000006"),
+        137 => parse_addr("0x0d500B1d8E8eF31E21C99d1Db9A6444d3ADf1270"),
+        42161 => parse_addr("0x82aF49447D8e07e3bd95BD0d56f35241523fBab1"),
+        n => Err(BotError::Config(format!("Unknown chain id {}.", n))),
+    }
+}
+
+/// Returns the address of the deployed instance of the Balancer V2 Vault on network with chain id `chain_id`.
+pub fn balancer_vault_addr(chain_id: u32) -> BotResult<Address> {
+    match chain_id {
+        1 | 5 | 10 | 137 | 42161 => parse_addr("0xBA12222222228d8Ba445958a75a0704d566BF2C8"),
+        n => Err(BotError::Config(format!("Unknown chain id {}.", n))),
+    }
+}
+
+/// Returns the address of the Curve Registry listing the StableSwap pools on network with chain id `chain_id`,
+/// `None` if Curve pools are not tracked on the network.
+/// Can be overridden with `CURVE_REGISTRY_ADDRESS` in .env.
+pub fn curve_registry_addr(chain_id: u32) -> BotResult<Option<Address>> {
+    if let Ok(addr) = dotenv::var("CURVE_REGISTRY_ADDRESS") {
+        return parse_addr(&addr).map(Some);
+    }
+    match chain_id {
+        1 => parse_addr("0x90E00ACe148ca3b23Ac1bC8C240C2a7Dd9c2d7f5").map(Some),
+        5 | 10 | 137 | 42161 => Ok(None),
+        n => Err(BotError::Config(format!("Unknown chain id {}.", n))),
+    }
+}
+
+//------------------------------------- tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_args_invalid() {
+        let args = |network: &str| vec!["bot".to_string(), network.to_string()];
+        assert!(matches!(read_args(vec!["bot".to_string()]), Err(BotError::Config(_))));
+        assert!(matches!(read_args(args("ropsten")), Err(BotError::Config(_))));
+        assert!(matches!(univ3_factory_addr(3), Err(BotError::Config(_))));
+    }
+
+    #[test]
+    fn test_sqrt_price_to_log_price() {
+        // from a sqrt price below 2^64 up to one of more than 128 bits, a price of 1.0001^tick
+        for tick in [-200_000, 0, 200_000, 500_000, 800_000] {
+            let sqrt = crate::univ3_math::get_sqrt_ratio_at_tick(tick).unwrap();
+            let (p0, p1) = sqrtPriceX86_to_log_price(sqrt, 18, 18);
+            assert!((p1 - tick as f64 * 1.0001f64.ln()).abs() < 1e-9);
+            assert_eq!(p0, -p1);
+        }
+        // a token 0 of 6 decimals worth 1e-12 of the 18 decimals token 1 per unit
+        let sqrt = crate::univ3_math::get_sqrt_ratio_at_tick(500_000).unwrap();
+        let (_, p1) = sqrtPriceX86_to_log_price(sqrt, 6, 18);
+        assert!((p1 - (500_000. * 1.0001f64.ln() - 12. * 10f64.ln())).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_run_config_parse() {
+        let parse = |vars: &[(&str, &str)]| {
+            RunConfig::parse(|key| vars.iter().find(|(k, _)| *k == key).map(|(_, value)| value.to_string()))
+        };
+        let config = parse(&[]).unwrap();
+        assert_eq!(config.poll_interval, Duration::from_millis(1_000));
+        assert_eq!(config.iteration_timeout, Duration::from_millis(10_000));
+        assert_eq!((config.multicall_chunk_size, config.min_profit_bps, config.trace_simulations), (200, 5_000, false));
+        assert_eq!(config.priority_fee, PriorityFeeStrategy::Fixed(1_000_000_000));
+
+        let config = parse(&[("MULTICALL_CHUNK_SIZE", "0"), ("PRIORITY_FEE_STRATEGY", "percentile"), ("PRIORITY_FEE_PERCENTILE", "90")]).unwrap();
+        assert_eq!(config.multicall_chunk_size, 1);
+        assert_eq!(config.priority_fee, PriorityFeeStrategy::Percentile(90));
+
+        assert!(matches!(parse(&[("POLL_INTERVAL_MS", "0")]), Err(BotError::Config(_))));
+        assert!(matches!(parse(&[("ITERATION_TIMEOUT_MS", "0")]), Err(BotError::Config(_))));
+        assert!(matches!(parse(&[("MIN_PROFIT_BPS", "-1")]), Err(BotError::Config(_))));
+        assert!(matches!(parse(&[("PRIORITY_FEE_STRATEGY", "tip")]), Err(BotError::Config(_))));
     }
 }